@@ -1,8 +1,34 @@
+use std::{path::PathBuf, time::Duration};
+
 use once_cell::sync::Lazy;
 
+use crate::feature::relay::types::{IdentityPolicy, PeerIdPolicy, SlowRecipientPolicy};
+
 pub struct Config {
     pub rust_env: String,
     pub port: u16,
+    pub peer_id_policy: PeerIdPolicy,
+    pub identity_policy: IdentityPolicy,
+    pub max_share_recipients: usize,
+    pub max_chunk_size: u32,
+    pub max_file_size: u64,
+    pub slow_recipient_policy: SlowRecipientPolicy,
+    pub slow_recipient_timeout: Duration,
+    pub resume_window: Duration,
+    pub flow_max_queued_bytes: u64,
+    pub spool_dir: Option<PathBuf>,
+    pub spool_share_quota: u64,
+    pub spool_ttl: Duration,
+    pub spool_janitor_interval: Duration,
+    pub pairing_code_ttl: Duration,
+    pub pairing_max_failed_attempts: u32,
+    pub pairing_throttle_window: Duration,
+    pub passphrase_max_failed_attempts: u32,
+    pub passphrase_lockout: Duration,
+    pub share_link_base_url: String,
+    pub upload_pair_timeout: Duration,
+    pub download_start_timeout: Duration,
+    pub admin_token: Option<String>,
 }
 
 fn get_rust_env() -> String {
@@ -21,5 +47,103 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(8080),
+        peer_id_policy: std::env::var("PEER_ID_POLICY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(PeerIdPolicy::Reject),
+        identity_policy: std::env::var("IDENTITY_POLICY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(IdentityPolicy::Strict),
+        max_share_recipients: std::env::var("MAX_SHARE_RECIPIENTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        max_chunk_size: std::env::var("MAX_CHUNK_SIZE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024 * 1024),
+        max_file_size: std::env::var("MAX_FILE_SIZE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10 * 1024 * 1024 * 1024),
+        slow_recipient_policy: std::env::var("SLOW_RECIPIENT_POLICY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(SlowRecipientPolicy::Wait),
+        slow_recipient_timeout: Duration::from_secs(
+            std::env::var("SLOW_RECIPIENT_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+        ),
+        resume_window: Duration::from_secs(
+            std::env::var("RESUME_WINDOW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+        ),
+        flow_max_queued_bytes: std::env::var("FLOW_MAX_QUEUED_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8 * 1024 * 1024),
+        spool_dir: std::env::var("SPOOL_DIR").ok().map(PathBuf::from),
+        spool_share_quota: std::env::var("SPOOL_SHARE_QUOTA_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024 * 1024 * 1024),
+        spool_ttl: Duration::from_secs(
+            std::env::var("SPOOL_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24 * 60 * 60),
+        ),
+        spool_janitor_interval: Duration::from_secs(
+            std::env::var("SPOOL_JANITOR_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+        ),
+        pairing_code_ttl: Duration::from_secs(
+            std::env::var("PAIRING_CODE_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+        ),
+        pairing_max_failed_attempts: std::env::var("PAIRING_MAX_FAILED_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        pairing_throttle_window: Duration::from_secs(
+            std::env::var("PAIRING_THROTTLE_WINDOW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+        ),
+        passphrase_max_failed_attempts: std::env::var("PASSPHRASE_MAX_FAILED_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        passphrase_lockout: Duration::from_secs(
+            std::env::var("PASSPHRASE_LOCKOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+        ),
+        share_link_base_url: std::env::var("SHARE_LINK_BASE_URL")
+            .unwrap_or("http://localhost:3000".to_owned()),
+        upload_pair_timeout: Duration::from_secs(
+            std::env::var("UPLOAD_PAIR_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+        ),
+        download_start_timeout: Duration::from_secs(
+            std::env::var("DOWNLOAD_START_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+        ),
+        admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|v| !v.is_empty()),
     }
 });
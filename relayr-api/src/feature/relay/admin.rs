@@ -0,0 +1,337 @@
+use std::fmt;
+
+use axum::{
+    extract::ws::{CloseFrame, Message},
+    http::{HeaderMap, header},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::feature::relay::{
+    registry::{PeerRole, PeerSnapshot, SessionRegistry, SessionSnapshot},
+    session::{ShareMode, TransferState},
+    state::RelayState,
+    types::DisconnectReason,
+    ws::{
+        dto::response::{
+            AsWsMessage, CancelSenderTransferResponseDto, PeerDisconnectedResponseDto,
+        },
+        peer_disconnect,
+    },
+};
+
+const DEFAULT_PAGE_LIMIT: usize = 50;
+const MAX_PAGE_LIMIT: usize = 500;
+pub const DEFAULT_KICK_REASON: &str = "disconnected by an administrator";
+// Close frame reasons must fit in a control frame next to the status code.
+const MAX_CLOSE_REASON_BYTES: usize = 123;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAuthError {
+    /// No admin token is configured, so the admin API is off.
+    Disabled,
+    /// The request has no `Authorization: Bearer` header.
+    MissingToken,
+    WrongToken,
+}
+
+impl fmt::Display for AdminAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(
+                f,
+                "the admin API is disabled, set `ADMIN_TOKEN` to enable it"
+            ),
+            Self::MissingToken => write!(f, "an admin bearer token is required"),
+            Self::WrongToken => write!(f, "wrong admin token"),
+        }
+    }
+}
+
+/// Checks the bearer token of an admin request against `expected`. Both
+/// sides are hashed before comparing, so the comparison takes the same time
+/// whatever the token and leaks nothing about its length.
+pub fn authorize(expected: Option<&str>, headers: &HeaderMap) -> Result<(), AdminAuthError> {
+    let expected = expected.ok_or(AdminAuthError::Disabled)?;
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AdminAuthError::MissingToken)?;
+
+    let expected = Sha256::digest(expected.as_bytes());
+    let given = Sha256::digest(given.trim().as_bytes());
+    let difference = expected
+        .iter()
+        .zip(given.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if difference == 0 {
+        Ok(())
+    } else {
+        Err(AdminAuthError::WrongToken)
+    }
+}
+
+/// One page of a listing, with the number of items that matched in total.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl<T> Page<T> {
+    /// Cuts `items` down to `limit` entries from `offset`. `limit` defaults
+    /// to 50 and is capped at 500.
+    pub fn new(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Self {
+        let offset = offset.unwrap_or_default();
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT);
+        let total = items.len();
+        Self {
+            items: items.into_iter().skip(offset).take(limit).collect(),
+            total,
+            offset,
+            limit,
+        }
+    }
+}
+
+/// Filters for `GET /admin/peers`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerQuery {
+    pub role: Option<PeerRole>,
+    /// Only peers whose ID starts with this.
+    pub peer_id: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Filters for `GET /admin/sessions`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionQuery {
+    pub state: Option<TransferState>,
+    pub mode: Option<ShareMode>,
+    /// Only sessions whose sender or one of whose recipients has an ID
+    /// starting with this.
+    pub peer_id: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Connected peers matching `query`, longest connected first.
+pub fn list_peers(registry: &SessionRegistry, query: &PeerQuery) -> Page<PeerSnapshot> {
+    let mut peers: Vec<PeerSnapshot> = registry
+        .peer_snapshots()
+        .into_iter()
+        .filter(|peer| query.role.is_none_or(|role| peer.role == role))
+        .filter(|peer| {
+            query
+                .peer_id
+                .as_deref()
+                .is_none_or(|prefix| peer.peer_id.starts_with(prefix))
+        })
+        .collect();
+    peers.sort_by(|a, b| {
+        b.connected_secs
+            .cmp(&a.connected_secs)
+            .then_with(|| a.peer_id.cmp(&b.peer_id))
+    });
+    Page::new(peers, query.offset, query.limit)
+}
+
+/// Transfer sessions matching `query`, by sender ID, including shares that
+/// only live on in the spool.
+pub fn list_sessions(state: &RelayState, query: &SessionQuery) -> Page<SessionSnapshot> {
+    let mut sessions = state.registry.session_snapshots();
+    if let Some(spool) = &state.spool {
+        for session in &mut sessions {
+            session.stored = spool.is_stored(&session.sender_id);
+        }
+        for (sender_id, files) in spool.stored_shares() {
+            if sessions
+                .iter()
+                .any(|session| session.sender_id == sender_id)
+            {
+                continue;
+            }
+            sessions.push(SessionSnapshot {
+                sender_id,
+                state: TransferState::Announced,
+                mode: ShareMode::default(),
+                e2ee: false,
+                total_size: files.total_size(),
+                files: Some(files),
+                bytes_relayed: 0,
+                recipients: Vec::new(),
+                stored: true,
+            });
+        }
+    }
+
+    let mut sessions: Vec<SessionSnapshot> = sessions
+        .into_iter()
+        .filter(|session| query.state.is_none_or(|state| session.state == state))
+        .filter(|session| query.mode.is_none_or(|mode| session.mode == mode))
+        .filter(|session| {
+            query.peer_id.as_deref().is_none_or(|prefix| {
+                session.sender_id.starts_with(prefix)
+                    || session
+                        .recipients
+                        .iter()
+                        .any(|recipient| recipient.recipient_id.starts_with(prefix))
+            })
+        })
+        .collect();
+    sessions.sort_by(|a, b| a.sender_id.cmp(&b.sender_id));
+    Page::new(sessions, query.offset, query.limit)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminActionError {
+    PeerNotConnected(String),
+    NotPaired(String),
+    ShareNotFound(String),
+}
+
+impl fmt::Display for AdminActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PeerNotConnected(peer_id) => write!(f, "peer `{peer_id}` is not connected"),
+            Self::NotPaired(peer_id) => write!(f, "no such pairing for sender `{peer_id}`"),
+            Self::ShareNotFound(sender_id) => {
+                write!(f, "sender `{sender_id}` has not announced anything")
+            }
+        }
+    }
+}
+
+/// Outcome of an admin action: the counterparts that were told about it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminAction {
+    pub notified: Vec<String>,
+}
+
+/// Query of `DELETE /admin/peers/{peer_id}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct KickQuery {
+    pub reason: Option<String>,
+}
+
+/// Query of `DELETE /admin/sessions/{sender_id}/pairings`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelPairingQuery {
+    /// Only unpair this recipient instead of all of them.
+    pub recipient_id: Option<String>,
+}
+
+/// Disconnects `peer_id` the way a dropped socket would, telling its
+/// counterparts, then closes its connection with `reason`. The relay lets
+/// go of the peer at once, without waiting for the close handshake.
+pub async fn kick_peer(
+    state: &RelayState,
+    peer_id: &str,
+    reason: &str,
+) -> Result<AdminAction, AdminActionError> {
+    let not_connected = || AdminActionError::PeerNotConnected(peer_id.to_owned());
+    let tx = state.registry.peer_tx(peer_id).ok_or_else(not_connected)?;
+    let notified = peer_disconnect::disconnect_peer(state, peer_id, &tx, DisconnectReason::Other)
+        .await
+        .ok_or_else(not_connected)?;
+
+    let close_msg = Message::Close(Some(CloseFrame {
+        code: 1008,
+        reason: truncate(reason, MAX_CLOSE_REASON_BYTES).into(),
+    }));
+    let _ = tx.send(close_msg.into()).await;
+    Ok(AdminAction { notified })
+}
+
+/// Unpairs `recipient_id` from `sender_id`, or every recipient if `None`,
+/// whatever state the transfer is in. Each recipient gets
+/// `cancelSenderTransfer` and the sender gets `peerDisconnected` for each
+/// recipient, so both sides drop the transfer as they already know how to.
+pub async fn cancel_pairing(
+    state: &RelayState,
+    sender_id: &str,
+    recipient_id: Option<&str>,
+) -> Result<AdminAction, AdminActionError> {
+    let not_paired = |_| AdminActionError::NotPaired(sender_id.to_owned());
+    let (recipient_ids, released_ack) = match recipient_id {
+        Some(recipient_id) => {
+            let released_ack = state
+                .registry
+                .evict_recipient(sender_id, recipient_id)
+                .map_err(not_paired)?;
+            (vec![recipient_id.to_owned()], released_ack)
+        }
+        None => (
+            state
+                .registry
+                .evict_recipients(sender_id)
+                .map_err(not_paired)?,
+            None,
+        ),
+    };
+
+    let mut notified = notify_recipients_cancelled(state, sender_id, &recipient_ids).await;
+    if let Some(sender_tx) = state.registry.peer_tx(sender_id) {
+        if let Some(ack_msg) = released_ack {
+            let _ = sender_tx.send(ack_msg).await;
+        }
+        let mut reached = true;
+        for recipient_id in &recipient_ids {
+            let msg = PeerDisconnectedResponseDto::new(recipient_id, "recipient").as_notification();
+            reached &= sender_tx.send(msg).await.is_ok();
+        }
+        if reached {
+            notified.push(sender_id.to_owned());
+        }
+    }
+    Ok(AdminAction { notified })
+}
+
+/// Forgets everything `sender_id` announced, live or stored in the spool,
+/// and cancels the transfer for its recipients. The sender stays connected
+/// but has to announce again before anyone can pair with it.
+pub async fn purge_share(
+    state: &RelayState,
+    sender_id: &str,
+) -> Result<AdminAction, AdminActionError> {
+    let recipient_ids = peer_disconnect::purge_share(state, sender_id)
+        .await
+        .ok_or_else(|| AdminActionError::ShareNotFound(sender_id.to_owned()))?;
+    let notified = notify_recipients_cancelled(state, sender_id, &recipient_ids).await;
+    Ok(AdminAction { notified })
+}
+
+async fn notify_recipients_cancelled(
+    state: &RelayState,
+    sender_id: &str,
+    recipient_ids: &[String],
+) -> Vec<String> {
+    let mut notified = Vec::new();
+    for recipient_id in recipient_ids {
+        if let Some(recipient_tx) = state.registry.peer_tx(recipient_id) {
+            let msg =
+                CancelSenderTransferResponseDto::new(sender_id, recipient_id).as_notification();
+            if recipient_tx.send(msg).await.is_ok() {
+                notified.push(recipient_id.clone());
+            }
+        }
+    }
+    notified
+}
+
+fn truncate(text: &str, max_bytes: usize) -> &str {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
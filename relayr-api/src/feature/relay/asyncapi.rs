@@ -0,0 +1,67 @@
+use schemars::{JsonSchema, generate::SchemaSettings};
+use serde_json::{Value, json};
+
+use crate::feature::relay::{
+    protocol::PROTOCOL_VERSION,
+    ws::dto::{request::RelayIncomingMessage, response::RelayOutgoingMessage},
+};
+
+/// AsyncAPI 3.0 description of the relay socket. The message payloads are
+/// JSON Schemas derived from the serde types, collected under
+/// `components.schemas`.
+pub fn asyncapi_document() -> Value {
+    let mut settings = SchemaSettings::draft07();
+    settings.definitions_path = "/components/schemas".into();
+    settings.meta_schema = None;
+    let mut generator = settings.into_generator();
+
+    let incoming = generator.subschema_for::<RelayIncomingMessage>();
+    let outgoing = generator.subschema_for::<RelayOutgoingMessage>();
+    let schemas = generator.take_definitions(true);
+
+    json!({
+        "asyncapi": "3.0.0",
+        "info": {
+            "title": "Relayr relay protocol",
+            "version": PROTOCOL_VERSION.to_string(),
+            "description": "Control messages exchanged on the relay WebSocket. File data travels as binary frames, either raw after a `fileChunk` message or as an enveloped frame.",
+        },
+        "channels": {
+            "relay": {
+                "address": "/api/v1/relay",
+                "messages": {
+                    "incoming": { "$ref": "#/components/messages/incoming" },
+                    "outgoing": { "$ref": "#/components/messages/outgoing" },
+                },
+            },
+        },
+        "operations": {
+            "receiveMessage": {
+                "action": "receive",
+                "channel": { "$ref": "#/channels/relay" },
+                "messages": [{ "$ref": "#/channels/relay/messages/incoming" }],
+            },
+            "sendMessage": {
+                "action": "send",
+                "channel": { "$ref": "#/channels/relay" },
+                "messages": [{ "$ref": "#/channels/relay/messages/outgoing" }],
+            },
+        },
+        "components": {
+            "messages": {
+                "incoming": message::<RelayIncomingMessage>("Sent by clients to the relay.", incoming),
+                "outgoing": message::<RelayOutgoingMessage>("Sent by the relay to clients.", outgoing),
+            },
+            "schemas": schemas,
+        },
+    })
+}
+
+fn message<T: JsonSchema>(summary: &str, payload: schemars::Schema) -> Value {
+    json!({
+        "name": T::schema_name(),
+        "summary": summary,
+        "contentType": "application/json",
+        "payload": payload,
+    })
+}
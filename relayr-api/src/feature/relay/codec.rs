@@ -0,0 +1,138 @@
+use std::fmt;
+
+use axum::extract::ws::Message;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::feature::relay::{
+    frame::{FRAME_VERSION, FrameHeader},
+    ws::dto::response::{RelayOutgoingMessage, RelayOutgoingPayload},
+};
+
+pub const JSON_SUBPROTOCOL: &str = "relayr.json.v1";
+pub const MSGPACK_SUBPROTOCOL: &str = "relayr.msgpack.v1";
+
+/// How control messages are encoded on a socket, picked with
+/// `Sec-WebSocket-Protocol` at upgrade time.
+///
+/// JSON control messages travel as text frames. MessagePack ones travel as
+/// binary frames, so on such a socket every file chunk is sent as an
+/// enveloped frame and any other binary frame is a control message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Subprotocols accepted from clients. A client offering several gets
+    /// the first of these it offered, so JSON stays the default.
+    pub const SUBPROTOCOLS: [&str; 2] = [JSON_SUBPROTOCOL, MSGPACK_SUBPROTOCOL];
+
+    pub fn from_subprotocol(subprotocol: &str) -> Option<Self> {
+        match subprotocol {
+            JSON_SUBPROTOCOL => Some(Self::Json),
+            MSGPACK_SUBPROTOCOL => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Encodes a control message in this format, as a text frame for JSON
+    /// and a binary one for MessagePack.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Message, CodecError> {
+        match self {
+            Self::Json => serde_json::to_string(value)
+                .map(Message::text)
+                .map_err(|e| CodecError(e.to_string())),
+            Self::MessagePack => rmp_serde::to_vec_named(value)
+                .map(Message::binary)
+                .map_err(|e| CodecError(e.to_string())),
+        }
+    }
+
+    /// Decodes a control message received in this format.
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Self::Json => serde_json::from_slice(data).map_err(|e| CodecError(e.to_string())),
+            Self::MessagePack => rmp_serde::from_slice(data).map_err(|e| CodecError(e.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CodecError(pub String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What the relay queues for a socket. Control messages stay typed until the
+/// socket's writer encodes them in its wire format; frames are sent as they
+/// are.
+#[derive(Debug, Clone)]
+pub enum Outgoing {
+    Control(Box<RelayOutgoingMessage>),
+    Frame(Message),
+}
+
+impl From<Message> for Outgoing {
+    fn from(msg: Message) -> Self {
+        Self::Frame(msg)
+    }
+}
+
+/// Encodes outgoing messages for one socket. On a MessagePack socket it also
+/// envelopes raw chunks, using the `fileChunk` message that precedes each of
+/// them.
+#[derive(Debug, Default)]
+pub struct OutgoingEncoder {
+    format: WireFormat,
+    // Header for the next raw chunk, from the last `fileChunk` message.
+    pending_chunk: Option<FrameHeader>,
+}
+
+impl OutgoingEncoder {
+    pub fn new(format: WireFormat) -> Self {
+        Self {
+            format,
+            pending_chunk: None,
+        }
+    }
+
+    pub fn encode(&mut self, outgoing: Outgoing) -> Message {
+        match outgoing {
+            Outgoing::Control(msg) => {
+                if self.format == WireFormat::MessagePack {
+                    self.pending_chunk = chunk_header(&msg.payload);
+                }
+                self.format.encode(&msg).unwrap_or_else(|err| {
+                    tracing::error!(error = %err, "failed to encode outgoing message");
+                    Message::text(msg.to_json())
+                })
+            }
+            Outgoing::Frame(Message::Binary(data)) if !FrameHeader::is_envelope(&data) => {
+                match self.pending_chunk.take() {
+                    Some(header) => Message::Binary(header.encode(&data).into()),
+                    None => Message::Binary(data),
+                }
+            }
+            Outgoing::Frame(msg) => msg,
+        }
+    }
+}
+
+fn chunk_header(payload: &RelayOutgoingPayload) -> Option<FrameHeader> {
+    let RelayOutgoingPayload::FileChunk(chunk) = payload else {
+        return None;
+    };
+    Some(FrameHeader {
+        version: FRAME_VERSION,
+        flags: 0,
+        session_id: chunk.sender_id.clone(),
+        file_index: chunk.file_index.unwrap_or_default(),
+        chunk_index: chunk.chunk_index,
+        length: chunk.chunk_data_size,
+    })
+}
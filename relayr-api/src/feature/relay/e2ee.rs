@@ -0,0 +1,19 @@
+use std::fmt;
+
+/// An ephemeral X25519 public key, sent hex-encoded in `keyExchange`.
+pub type PublicKey = [u8; 32];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidPublicKey(pub String);
+
+impl fmt::Display for InvalidPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a hex-encoded 32-byte public key", self.0)
+    }
+}
+
+pub fn parse_public_key(key: &str) -> Result<PublicKey, InvalidPublicKey> {
+    let mut public_key = [0; 32];
+    hex::decode_to_slice(key, &mut public_key).map_err(|_| InvalidPublicKey(key.to_owned()))?;
+    Ok(public_key)
+}
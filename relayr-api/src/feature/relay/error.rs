@@ -1,46 +1,147 @@
-use std::fmt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
-use axum::extract::ws::Message;
-use chrono::Utc;
-use serde::Serialize;
+use crate::feature::relay::ws::dto::request::RequestContext;
 
-#[derive(Serialize)]
+/// Errors reported on the relay socket. The numeric values are stable and
+/// sent alongside the name as `numericCode`; they are grouped by range:
+/// 1xxx malformed or unacceptable input, 2xxx pairing and transfer, 3xxx
+/// session state, 4xxx socket usage and 9xxx relay faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[repr(u16)]
 pub enum ErrorCode {
-    InvalidPayload,
+    InvalidPayload = 1000,
+    InvalidManifest = 1001,
+    InvalidFrame = 1002,
+    PeerIdTaken = 1003,
+    UnsupportedProtocolVersion = 1004,
+    LimitExceeded = 1005,
+    Forbidden = 1006,
 
-    SenderAlreadyConnected,
-    SenderDisconnected,
-    RecipientDisconnected,
+    SenderAlreadyConnected = 2000,
+    RecipientAlreadyConnected = 2001,
+    ShareFull = 2002,
+    RecipientDropped = 2003,
+    ShareNotReady = 2004,
+    SpoolUnavailable = 2005,
+    SpoolQuotaExceeded = 2006,
+    FlowControlBlocked = 2007,
+    IntegrityMismatch = 2008,
+    SenderDisconnected = 2009,
+    RecipientDisconnected = 2010,
+    InvalidPairingCode = 2011,
+    PairingThrottled = 2012,
+    PassphraseRequired = 2013,
+    WrongPassphrase = 2014,
+    PassphraseLockedOut = 2015,
+    EncryptionRequired = 2016,
+    KeyExchangeIncomplete = 2017,
 
-    ActiveConnectionNotFound,
-    RecipientMismatch,
+    ActiveConnectionNotFound = 3000,
+    RecipientMismatch = 3001,
+    InvalidStateTransition = 3002,
 
-    UnsupportedWsMessageType,
-    UnsupportedWsMessageTextType,
+    UnsupportedWsMessageType = 4000,
+    UnsupportedWsMessageTextType = 4001,
 
-    NotHandledYet,
-    Unknown,
+    Unknown = 9000,
 }
 
-#[derive(Serialize)]
+impl ErrorCode {
+    pub fn numeric(self) -> u16 {
+        self as u16
+    }
+
+    /// Whether sending the same message again later may succeed, once a
+    /// peer reconnects, a window reopens or the relay recovers. Anything
+    /// else needs a different message or a new share.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            Self::SenderAlreadyConnected
+                | Self::RecipientAlreadyConnected
+                | Self::ShareNotReady
+                | Self::SpoolUnavailable
+                | Self::FlowControlBlocked
+                | Self::IntegrityMismatch
+                | Self::SenderDisconnected
+                | Self::RecipientDisconnected
+                | Self::PairingThrottled
+                | Self::PassphraseLockedOut
+                | Self::KeyExchangeIncomplete
+                | Self::Unknown
+        )
+    }
+}
+
+/// Machine-readable facts about an error; which fields are set depends on
+/// the code.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub sender_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub recipient_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub expected_recipient_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub actual_recipient_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub file_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub chunk_index: Option<u32>,
+    /// The limit that was hit, in the unit of `actual`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional, type = "number")]
+    pub limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional, type = "number")]
+    pub actual: Option<u64>,
+    /// Seconds to wait before the request can succeed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional, type = "number")]
+    pub retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrorMessage {
-    pub success: bool,
     pub code: ErrorCode,
+    pub numeric_code: u16,
+    pub retryable: bool,
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
     pub details: Option<String>,
-    pub timestamp: i64,
+    /// `type` of the message whose handling failed, or `binary` for a
+    /// binary frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub related_message_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub context: Option<ErrorContext>,
 }
 
 impl ErrorMessage {
     pub fn new(code: ErrorCode, msg: &str) -> Self {
         Self {
-            success: false,
             code,
+            numeric_code: code.numeric(),
+            retryable: code.is_retryable(),
             message: msg.to_owned(),
             details: None,
-            timestamp: Utc::now().timestamp(),
+            related_message_type: RequestContext::current().message_type,
+            context: None,
         }
     }
 
@@ -48,25 +149,9 @@ impl ErrorMessage {
         self.details = Some(details.to_owned());
         self
     }
-}
 
-impl ErrorMessage {
-    pub fn as_ws_text_message(&self) -> Message {
-        Message::text(self.to_string())
-    }
-}
-
-impl fmt::Display for ErrorMessage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match serde_json::to_string(self) {
-            Ok(json) => write!(f, "{json}"),
-            Err(e) => {
-                tracing::error!(error = %e, "failed to serialize ErrorMessage");
-                write!(
-                    f,
-                    "{{\"success\": false, \"message\": \"internal serialization error\"}}"
-                )
-            }
-        }
+    pub fn with_context(mut self, context: ErrorContext) -> Self {
+        self.context = Some(context);
+        self
     }
 }
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+/// Send window of one recipient. Every binary frame relayed to it takes a
+/// credit and counts as queued until the recipient acknowledges it.
+///
+/// Recipients that never grant credits are only bounded by queued bytes, so
+/// clients that wait for each ack keep working unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct FlowWindow {
+    credits: Option<u32>,
+    // Sizes of relayed chunks not acknowledged yet, oldest first.
+    in_flight: VecDeque<u32>,
+    queued_bytes: u64,
+}
+
+impl FlowWindow {
+    pub fn credits(&self) -> Option<u32> {
+        self.credits
+    }
+
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued_bytes
+    }
+
+    pub fn grant(&mut self, credits: u32) {
+        self.credits = Some(self.credits.unwrap_or_default().saturating_add(credits));
+    }
+
+    /// Whether a chunk of `len` bytes may be relayed now. An empty queue
+    /// always takes one chunk, however large, so a small limit cannot stall
+    /// the transfer.
+    pub fn admits(&self, len: u32, max_queued_bytes: u64) -> bool {
+        self.credits != Some(0)
+            && (self.in_flight.is_empty() || self.queued_bytes + u64::from(len) <= max_queued_bytes)
+    }
+
+    /// Whether the sender may keep sending without being told to pause.
+    pub fn is_open(&self, max_queued_bytes: u64) -> bool {
+        self.credits != Some(0) && self.queued_bytes < max_queued_bytes
+    }
+
+    pub fn record_sent(&mut self, len: u32) {
+        if let Some(credits) = &mut self.credits {
+            *credits = credits.saturating_sub(1);
+        }
+        self.in_flight.push_back(len);
+        self.queued_bytes += u64::from(len);
+    }
+
+    pub fn record_acked(&mut self) {
+        if let Some(len) = self.in_flight.pop_front() {
+            self.queued_bytes -= u64::from(len);
+        }
+    }
+}
+
+/// The tightest window across every recipient of a share.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlowStatus {
+    /// Fewest credits left, if any recipient grants credits.
+    pub credits: Option<u32>,
+    /// Most bytes queued for a single recipient.
+    pub queued_bytes: u64,
+}
+
+/// Outcome of asking to relay a binary frame.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Relay the frame. `pause` is set if a window closed because of it.
+    Forward { pause: Option<FlowStatus> },
+    /// At least one recipient has no room; the frame must not be relayed.
+    Blocked(FlowStatus),
+}
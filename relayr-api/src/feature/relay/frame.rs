@@ -0,0 +1,128 @@
+use std::fmt;
+
+/// Marks a binary message as an enveloped chunk rather than raw file bytes.
+pub const FRAME_MAGIC: [u8; 4] = *b"RLYF";
+pub const FRAME_VERSION: u8 = 1;
+
+/// Set on the last chunk of a file.
+pub const FLAG_LAST_CHUNK: u8 = 0b0000_0001;
+/// Set when the payload is end-to-end encrypted; required on `e2ee` shares.
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0010;
+const KNOWN_FLAGS: u8 = FLAG_LAST_CHUNK | FLAG_ENCRYPTED;
+
+// magic + version + flags + session id length
+const PREFIX_LEN: usize = FRAME_MAGIC.len() + 3;
+// file index + chunk index + payload length
+const INDEX_LEN: usize = 12;
+
+/// Header of an enveloped chunk. All integers are big-endian:
+///
+/// ```text
+/// magic "RLYF" | version u8 | flags u8 | session id len u8 | session id
+/// | file index u32 | chunk index u32 | payload len u32 | payload
+/// ```
+///
+/// The session id is the sender's peer ID. Single-file shares use file
+/// index 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub flags: u8,
+    pub session_id: String,
+    pub file_index: u32,
+    pub chunk_index: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownFlags(u8),
+    InvalidSessionId,
+    LengthMismatch { declared: u32, actual: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "frame is shorter than its header"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported frame version {version}")
+            }
+            Self::UnknownFlags(flags) => write!(f, "unknown frame flags {flags:#010b}"),
+            Self::InvalidSessionId => write!(f, "session id is not valid UTF-8"),
+            Self::LengthMismatch { declared, actual } => write!(
+                f,
+                "header declares a {declared}-byte payload but the frame carries {actual} bytes"
+            ),
+        }
+    }
+}
+
+impl FrameHeader {
+    /// Whether `frame` claims to be enveloped. Anything else is treated as
+    /// raw chunk bytes announced by a preceding `fileChunk` message.
+    pub fn is_envelope(frame: &[u8]) -> bool {
+        frame.starts_with(&FRAME_MAGIC)
+    }
+
+    /// Splits an enveloped frame into its header and payload.
+    pub fn parse(frame: &[u8]) -> Result<(Self, &[u8]), FrameError> {
+        let prefix = frame.get(..PREFIX_LEN).ok_or(FrameError::Truncated)?;
+        let (version, flags, session_id_len) = (prefix[4], prefix[5], prefix[6]);
+        if version != FRAME_VERSION {
+            return Err(FrameError::UnsupportedVersion(version));
+        }
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(FrameError::UnknownFlags(flags));
+        }
+
+        let indices_at = PREFIX_LEN + usize::from(session_id_len);
+        let payload_at = indices_at + INDEX_LEN;
+        if frame.len() < payload_at {
+            return Err(FrameError::Truncated);
+        }
+        let session_id = std::str::from_utf8(&frame[PREFIX_LEN..indices_at])
+            .map_err(|_| FrameError::InvalidSessionId)?;
+        let read_u32 =
+            |at: usize| u32::from_be_bytes(frame[at..at + 4].try_into().expect("slice is 4 bytes"));
+
+        let header = Self {
+            version,
+            flags,
+            session_id: session_id.to_owned(),
+            file_index: read_u32(indices_at),
+            chunk_index: read_u32(indices_at + 4),
+            length: read_u32(indices_at + 8),
+        };
+        let payload = &frame[payload_at..];
+        if payload.len() != header.length as usize {
+            return Err(FrameError::LengthMismatch {
+                declared: header.length,
+                actual: payload.len(),
+            });
+        }
+        Ok((header, payload))
+    }
+
+    /// Builds an enveloped frame around `payload`. `length` is taken from
+    /// the payload, and session ids longer than 255 bytes are cut short.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let session_id = &self.session_id.as_bytes()[..self.session_id.len().min(255)];
+        let mut frame =
+            Vec::with_capacity(PREFIX_LEN + session_id.len() + INDEX_LEN + payload.len());
+        frame.extend_from_slice(&FRAME_MAGIC);
+        frame.extend_from_slice(&[self.version, self.flags, session_id.len() as u8]);
+        frame.extend_from_slice(session_id);
+        frame.extend_from_slice(&self.file_index.to_be_bytes());
+        frame.extend_from_slice(&self.chunk_index.to_be_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    pub fn is_last_chunk(&self) -> bool {
+        self.flags & FLAG_LAST_CHUNK != 0
+    }
+}
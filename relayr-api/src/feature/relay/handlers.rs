@@ -1,13 +1,34 @@
+use std::{convert::Infallible, net::SocketAddr};
+
 use axum::{
-    extract::{Path, Query, State, WebSocketUpgrade},
-    http::StatusCode,
-    response::IntoResponse,
+    Json,
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State, WebSocketUpgrade},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use tokio::sync::mpsc;
 
 use crate::{
     common::response::{ApiResponse, AppError, AppResult},
+    config::CONFIG,
     feature::relay::{
-        types::{FileMetadata, RelayQueryParams},
+        admin::{
+            self, AdminAction, AdminActionError, AdminAuthError, CancelPairingQuery,
+            DEFAULT_KICK_REASON, KickQuery, Page, PeerQuery, SessionQuery, list_peers,
+            list_sessions,
+        },
+        asyncapi::asyncapi_document,
+        codec::WireFormat,
+        error::ErrorCode,
+        http::{
+            download::{DownloadError, HttpRecipient, content_disposition},
+            upload::{HttpSender, HttpUpload, HttpUploadError},
+        },
+        pairing::PairingError,
+        passphrase::{PASSPHRASE_HEADER, PassphraseError},
+        registry::{PeerSnapshot, SessionSnapshot},
+        types::{RedeemedPairingCode, RelayQueryParams, SharedFiles},
         ws::socket::handle_socket,
     },
 };
@@ -18,27 +39,299 @@ pub async fn handle_relay_ws_upgrade(
     ws: WebSocketUpgrade,
     State(state): State<RelayState>,
     Query(params): Query<RelayQueryParams>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state, params.id))
+    ws.protocols(WireFormat::SUBPROTOCOLS)
+        .on_upgrade(move |socket| handle_socket(socket, state, params.id, addr.ip()))
+}
+
+/// Trades a pairing code for its sender ID. The code is used up either way,
+/// so a recipient may instead send it straight in `recipientReady`. On a
+/// protected share the passphrase goes in `X-Share-Passphrase`, and the code
+/// is only used up once it checks out.
+pub async fn handle_redeem_pairing_code(
+    Path(code): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<RelayState>,
+    headers: HeaderMap,
+) -> AppResult<RedeemedPairingCode> {
+    let sender_id = state
+        .pairing
+        .peek(&code, addr.ip())
+        .map_err(pairing_error)?;
+
+    let passphrase = headers
+        .get(PASSPHRASE_HEADER)
+        .and_then(|value| value.to_str().ok());
+    state
+        .share_locks
+        .verify(&sender_id, passphrase, addr.ip())
+        .await
+        .map_err(passphrase_error)?;
+
+    // Another recipient may have used the code up meanwhile.
+    match state.pairing.redeem(&code, addr.ip()) {
+        Ok(redeemed) if redeemed == sender_id => {
+            Ok(ApiResponse::default().with_data(RedeemedPairingCode { sender_id }))
+        }
+        Ok(_) => Err(pairing_error(PairingError::InvalidCode)),
+        Err(err) => Err(pairing_error(err)),
+    }
+}
+
+fn pairing_error(err: PairingError) -> AppError {
+    let status = match err {
+        PairingError::InvalidCode => StatusCode::NOT_FOUND,
+        PairingError::Throttled { .. } => StatusCode::TOO_MANY_REQUESTS,
+    };
+    AppError::default()
+        .with_code(status)
+        .with_message(&err.to_string())
 }
 
+fn passphrase_error(err: PassphraseError) -> AppError {
+    let status = match err {
+        PassphraseError::Required => StatusCode::UNAUTHORIZED,
+        PassphraseError::Wrong => StatusCode::FORBIDDEN,
+        PassphraseError::LockedOut { .. } => StatusCode::TOO_MANY_REQUESTS,
+        PassphraseError::Hash(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    AppError::default()
+        .with_code(status)
+        .with_message(&err.to_string())
+}
+
+/// Returns the files a sender has announced, live or stored. An unknown
+/// sender gets `404` before any passphrase is asked for: a passphrase
+/// protects the files, not the sender ID, which `recipientReady` reveals
+/// the same way.
 pub async fn handle_get_file_metadata(
     Path(sender_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<RelayState>,
-) -> AppResult<FileMetadata> {
-    if let Some(file_meta) = state.get_file_metadata(&sender_id).await {
-        Ok(ApiResponse::default().with_data(file_meta))
-    } else {
-        Err(AppError::default()
+    headers: HeaderMap,
+) -> AppResult<SharedFiles> {
+    let shared_files = state.registry.shared_files(&sender_id).or_else(|| {
+        state
+            .spool
+            .as_ref()
+            .and_then(|spool| spool.shared_files(&sender_id))
+    });
+    let Some(shared_files) = shared_files else {
+        return Err(AppError::default()
             .with_code(StatusCode::NOT_FOUND)
-            .with_message("File metadata not found"))
-    }
+            .with_message("File metadata not found"));
+    };
+
+    let passphrase = headers
+        .get(PASSPHRASE_HEADER)
+        .and_then(|value| value.to_str().ok());
+    state
+        .share_locks
+        .verify(&sender_id, passphrase, addr.ip())
+        .await
+        .map_err(passphrase_error)?;
+
+    Ok(ApiResponse::default().with_data(shared_files))
+}
+
+/// Shares the request body with a WebSocket recipient, for clients that
+/// cannot speak the WebSocket protocol, e.g. `curl -T file`. The reply is
+/// plain text streamed as the upload goes: the share link first, then how
+/// the transfer went. The request stays open until a recipient has the whole
+/// file or none turns up in time.
+pub async fn handle_upload(
+    Path(name): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<RelayState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    let upload = HttpUpload::from_request(name, &headers).map_err(|err| {
+        let status = match err {
+            HttpUploadError::InvalidName(_) | HttpUploadError::Empty => StatusCode::BAD_REQUEST,
+            HttpUploadError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            HttpUploadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        };
+        AppError::default()
+            .with_code(status)
+            .with_message(&err.to_string())
+    })?;
+    let sender = HttpSender::announce(state, upload, addr.ip())
+        .await
+        .map_err(|err| {
+            AppError::default()
+                .with_code(StatusCode::BAD_REQUEST)
+                .with_message(&err.message)
+        })?;
+
+    let (progress_tx, progress_rx) = mpsc::channel(16);
+    let _ = progress_tx
+        .send(format!("Share link: {}\n", sender.share_link()))
+        .await;
+    tokio::spawn(sender.run(body, progress_tx));
+
+    let lines = futures::stream::unfold(progress_rx, |mut progress_rx| async move {
+        let line = progress_rx.recv().await?;
+        Some((Ok::<_, Infallible>(line), progress_rx))
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+/// Receives a share on behalf of a client that cannot speak the WebSocket
+/// protocol, e.g. `curl` or `wget`. The relay pairs with the sender as a
+/// recipient, acknowledges chunks as the client reads them and streams the
+/// file as the response body, sized and named from its metadata.
+pub async fn handle_download(
+    Path(sender_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<RelayState>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let passphrase = headers
+        .get(PASSPHRASE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let recipient = HttpRecipient::pair(
+        state,
+        sender_id,
+        passphrase,
+        addr.ip(),
+        CONFIG.download_start_timeout,
+    )
+    .await
+    .map_err(|err| {
+        let status = match &err {
+            DownloadError::NotFound => StatusCode::NOT_FOUND,
+            DownloadError::MultipleFiles | DownloadError::Encrypted => StatusCode::BAD_REQUEST,
+            DownloadError::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            DownloadError::Refused(err) => match err.code {
+                ErrorCode::PassphraseRequired => StatusCode::UNAUTHORIZED,
+                ErrorCode::WrongPassphrase => StatusCode::FORBIDDEN,
+                ErrorCode::PassphraseLockedOut => StatusCode::TOO_MANY_REQUESTS,
+                ErrorCode::SenderDisconnected => StatusCode::NOT_FOUND,
+                ErrorCode::SenderAlreadyConnected
+                | ErrorCode::ShareFull
+                | ErrorCode::ShareNotReady
+                | ErrorCode::InvalidStateTransition => StatusCode::CONFLICT,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+        };
+        AppError::default()
+            .with_code(status)
+            .with_message(&err.to_string())
+    })?;
+
+    let file = recipient.file().clone();
+    let content_type = HeaderValue::from_str(&file.mime_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let disposition = HeaderValue::from_str(&content_disposition(&file.name))
+        .unwrap_or(HeaderValue::from_static("attachment"));
+
+    let (body_tx, body_rx) = mpsc::channel(4);
+    tokio::spawn(recipient.run(body_tx));
+    let chunks = futures::stream::unfold(body_rx, |mut body_rx| async move {
+        let chunk = body_rx.recv().await?;
+        Some((chunk, body_rx))
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, HeaderValue::from(file.size)),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
 }
 
 pub async fn handle_ping() -> AppResult<String> {
     Ok(ApiResponse::default().with_data("pong".to_string()))
 }
 
-pub async fn handle_debug_state(State(state): State<RelayState>) -> AppResult<String> {
-    Ok(ApiResponse::default().with_data(format!("{:#?}", state)))
+/// Served as a bare document rather than wrapped in `ApiResponse`, so AsyncAPI
+/// tooling can load it straight from the URL.
+pub async fn handle_get_asyncapi() -> Json<serde_json::Value> {
+    Json(asyncapi_document())
+}
+
+/// Connected peers with their pairings and connection age.
+pub async fn handle_admin_peers(
+    State(state): State<RelayState>,
+    Query(query): Query<PeerQuery>,
+    headers: HeaderMap,
+) -> AppResult<Page<PeerSnapshot>> {
+    authorize_admin(&headers)?;
+    Ok(ApiResponse::default().with_data(list_peers(&state.registry, &query)))
+}
+
+/// Transfer sessions with their announced files, per-recipient progress
+/// and bytes relayed.
+pub async fn handle_admin_sessions(
+    State(state): State<RelayState>,
+    Query(query): Query<SessionQuery>,
+    headers: HeaderMap,
+) -> AppResult<Page<SessionSnapshot>> {
+    authorize_admin(&headers)?;
+    Ok(ApiResponse::default().with_data(list_sessions(&state, &query)))
+}
+
+/// Disconnects a peer and closes its socket with `reason`.
+pub async fn handle_admin_kick_peer(
+    State(state): State<RelayState>,
+    Path(peer_id): Path<String>,
+    Query(query): Query<KickQuery>,
+    headers: HeaderMap,
+) -> AppResult<AdminAction> {
+    authorize_admin(&headers)?;
+    let reason = query.reason.as_deref().unwrap_or(DEFAULT_KICK_REASON);
+    admin_action(admin::kick_peer(&state, &peer_id, reason).await)
+}
+
+/// Unpairs one recipient of a sender, or all of them.
+pub async fn handle_admin_cancel_pairing(
+    State(state): State<RelayState>,
+    Path(sender_id): Path<String>,
+    Query(query): Query<CancelPairingQuery>,
+    headers: HeaderMap,
+) -> AppResult<AdminAction> {
+    authorize_admin(&headers)?;
+    admin_action(admin::cancel_pairing(&state, &sender_id, query.recipient_id.as_deref()).await)
+}
+
+/// Forgets a sender's announced files and stored share.
+pub async fn handle_admin_purge_share(
+    State(state): State<RelayState>,
+    Path(sender_id): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<AdminAction> {
+    authorize_admin(&headers)?;
+    admin_action(admin::purge_share(&state, &sender_id).await)
+}
+
+fn admin_action(result: Result<AdminAction, AdminActionError>) -> AppResult<AdminAction> {
+    // Every failure means the target is gone or was never there.
+    result
+        .map(|action| ApiResponse::default().with_data(action))
+        .map_err(|err| {
+            AppError::default()
+                .with_code(StatusCode::NOT_FOUND)
+                .with_message(&err.to_string())
+        })
+}
+
+fn authorize_admin(headers: &HeaderMap) -> Result<(), AppError> {
+    admin::authorize(CONFIG.admin_token.as_deref(), headers).map_err(|err| {
+        let status = match err {
+            AdminAuthError::Disabled => StatusCode::FORBIDDEN,
+            AdminAuthError::MissingToken | AdminAuthError::WrongToken => StatusCode::UNAUTHORIZED,
+        };
+        AppError::default()
+            .with_code(status)
+            .with_message(&err.to_string())
+    })
 }
@@ -0,0 +1,366 @@
+use std::{
+    fmt, io,
+    net::IpAddr,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use axum::{body::Bytes, extract::ws::Message};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::timeout,
+};
+
+use crate::{
+    config::CONFIG,
+    feature::relay::{
+        codec::Outgoing,
+        error::{ErrorCode, ErrorMessage},
+        frame::FrameHeader,
+        integrity::StreamVerifier,
+        state::RelayState,
+        types::{DisconnectReason, FileMetadata, SharedFiles},
+        ws::{
+            dto::{
+                request::{FileTransferAckPayload, RecipientReadyPayload, RelayIncomingPayload},
+                response::{FileChunkResponseDto, RelayOutgoingPayload},
+            },
+            peer_disconnect,
+            read_handlers::handle_text_message_payload,
+        },
+    },
+};
+
+#[derive(Debug, Clone)]
+pub enum DownloadError {
+    /// No share is announced or stored for the sender.
+    NotFound,
+    /// Manifest shares have no single body to download.
+    MultipleFiles,
+    /// End-to-end encrypted shares can only be read by a peer holding a key.
+    Encrypted,
+    /// The relay refused to pair, e.g. for a wrong passphrase.
+    Refused(Box<ErrorMessage>),
+    /// The sender did not accept the download in time.
+    TimedOut,
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "file not found"),
+            Self::MultipleFiles => write!(f, "shares of several files cannot be downloaded"),
+            Self::Encrypted => write!(f, "end-to-end encrypted shares cannot be downloaded"),
+            Self::Refused(err) => write!(f, "{}", err.message),
+            Self::TimedOut => write!(f, "the sender did not accept the download in time"),
+        }
+    }
+}
+
+/// `Content-Disposition` for downloading `file_name` as an attachment: a
+/// plain `filename` with anything outside printable ASCII, quotes and path
+/// separators replaced, and the exact name as a percent-encoded `filename*`.
+pub fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if !matches!(c, '"' | '\\' | '/' | '%') => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = file_name
+        .replace(['/', '\\'], "_")
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => (byte as char).to_string(),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// A recipient without a socket. It pairs with a sender on behalf of an
+/// HTTP client and acknowledges every chunk through the same handlers as a
+/// WebSocket recipient, so the sender cannot tell the difference. Replies
+/// the relay would write to a socket land in `rx` instead.
+pub struct HttpRecipient {
+    state: RelayState,
+    peer_id: String,
+    client_ip: IpAddr,
+    sender_id: String,
+    file: FileMetadata,
+    tx: Sender<Outgoing>,
+    rx: Receiver<Outgoing>,
+    stop_flag: Arc<AtomicBool>,
+    verifier: StreamVerifier,
+}
+
+impl HttpRecipient {
+    /// Pairs with `sender_id` and waits up to `start_timeout` for it to
+    /// accept, which a live sender does right away and a stored share does
+    /// as its replay starts.
+    pub async fn pair(
+        state: RelayState,
+        sender_id: String,
+        passphrase: Option<String>,
+        client_ip: IpAddr,
+        start_timeout: Duration,
+    ) -> Result<Self, DownloadError> {
+        let shared_files = state.registry.shared_files(&sender_id).or_else(|| {
+            state
+                .spool
+                .as_ref()
+                .and_then(|spool| spool.shared_files(&sender_id))
+        });
+        let file = match shared_files {
+            Some(SharedFiles::Single(file)) => file,
+            Some(SharedFiles::Manifest(_)) => return Err(DownloadError::MultipleFiles),
+            None => return Err(DownloadError::NotFound),
+        };
+        if state.registry.is_e2ee(&sender_id) {
+            return Err(DownloadError::Encrypted);
+        }
+
+        let (tx, rx) = mpsc::channel(100);
+        let registration = state
+            .registry
+            .register_peer(None, tx.clone(), CONFIG.peer_id_policy)
+            .map_err(|_| {
+                DownloadError::Refused(Box::new(ErrorMessage::new(
+                    ErrorCode::PeerIdTaken,
+                    "failed to register the download",
+                )))
+            })?;
+        let mut recipient = Self {
+            state,
+            peer_id: registration.peer_id,
+            client_ip,
+            sender_id,
+            file,
+            tx,
+            rx,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+            verifier: StreamVerifier::default(),
+        };
+
+        recipient
+            .send(RelayIncomingPayload::RecipientReady(
+                RecipientReadyPayload {
+                    sender_id: Some(recipient.sender_id.clone()),
+                    code: None,
+                    recipient_id: None,
+                    passphrase,
+                },
+            ))
+            .await;
+        let accepted = timeout(start_timeout, async {
+            loop {
+                match recipient.next_reply().await {
+                    Some(RelayOutgoingPayload::SenderAck(ack))
+                        if ack.request_type == "recipientReady" =>
+                    {
+                        break Ok(());
+                    }
+                    Some(RelayOutgoingPayload::Error(err)) => break Err(err),
+                    Some(RelayOutgoingPayload::PeerDisconnected(_)) | None => {
+                        break Err(ErrorMessage::new(
+                            ErrorCode::SenderDisconnected,
+                            "the sender disconnected",
+                        ));
+                    }
+                    Some(_) => {}
+                }
+            }
+        })
+        .await;
+
+        match accepted {
+            Ok(Ok(())) => Ok(recipient),
+            Ok(Err(err)) => {
+                recipient.close(DisconnectReason::Other).await;
+                Err(DownloadError::Refused(Box::new(err)))
+            }
+            Err(_) => {
+                recipient.close(DisconnectReason::Other).await;
+                Err(DownloadError::TimedOut)
+            }
+        }
+    }
+
+    pub fn recipient_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub fn file(&self) -> &FileMetadata {
+        &self.file
+    }
+
+    /// Writes the file to `body` as its chunks arrive and leaves the relay.
+    /// A chunk is acknowledged only once `body` has room for it, so the
+    /// sender goes no faster than the HTTP client reads. Failures end the
+    /// body with an error, which cuts the response short.
+    pub async fn run(mut self, body: Sender<io::Result<Bytes>>) {
+        let reason = match self.receive(&body).await {
+            Ok(()) => DisconnectReason::TransferCompleted,
+            Err(err) => {
+                tracing::info!(recipient_id = %self.peer_id, error = %err, "http download failed");
+                let _ = body.send(Err(io::Error::other(err))).await;
+                DisconnectReason::Other
+            }
+        };
+        self.close(reason).await;
+    }
+
+    async fn receive(&mut self, body: &Sender<io::Result<Bytes>>) -> Result<(), String> {
+        let mut announced: Option<FileChunkResponseDto> = None;
+        let mut received_size = 0;
+        loop {
+            let msg = tokio::select! {
+                msg = self.rx.recv() => msg,
+                _ = body.closed() => return Err("the downloading client went away".to_owned()),
+            };
+            let payload = match msg {
+                Some(Outgoing::Frame(Message::Binary(frame))) => {
+                    // Enveloped frames carry their own indices; plain ones
+                    // rely on the `fileChunk` sent before them.
+                    let (chunk_index, data) = if FrameHeader::is_envelope(&frame) {
+                        let (header, _) = FrameHeader::parse(&frame)
+                            .map_err(|err| format!("invalid binary frame: {err}"))?;
+                        let payload = frame.slice(frame.len() - header.length as usize..);
+                        (header.chunk_index, payload)
+                    } else {
+                        let chunk_index = announced
+                            .as_ref()
+                            .map(|chunk| chunk.chunk_index)
+                            .ok_or("binary frame was not preceded by a `fileChunk`")?;
+                        (chunk_index, frame)
+                    };
+
+                    received_size += data.len() as u64;
+                    if received_size > self.file.size {
+                        return Err("the sender sent more than the announced size".to_owned());
+                    }
+                    let chunk_data_size = data.len() as u32;
+                    body.send(Ok(data))
+                        .await
+                        .map_err(|_| "the downloading client went away".to_owned())?;
+
+                    let total_chunks = announced.take().map_or(0, |chunk| chunk.total_chunks);
+                    self.acknowledge(
+                        "acknowledged",
+                        total_chunks,
+                        received_size,
+                        chunk_index,
+                        chunk_data_size,
+                    )
+                    .await;
+                    continue;
+                }
+                Some(Outgoing::Control(msg)) => msg.payload,
+                Some(Outgoing::Frame(Message::Close(_))) | None => {
+                    return Err("the relay closed the download".to_owned());
+                }
+                Some(_) => continue,
+            };
+
+            match payload {
+                RelayOutgoingPayload::FileChunk(chunk) => announced = Some(chunk),
+                RelayOutgoingPayload::FileEnd(end) => {
+                    if received_size != self.file.size {
+                        return Err(format!(
+                            "the transfer ended after {} of {} bytes",
+                            received_size, self.file.size
+                        ));
+                    }
+                    self.acknowledge(
+                        "completed",
+                        end.total_chunks,
+                        received_size,
+                        end.last_chunk_index,
+                        0,
+                    )
+                    .await;
+                    return Ok(());
+                }
+                RelayOutgoingPayload::Error(err) => return Err(err.message),
+                RelayOutgoingPayload::PeerDisconnected(_) => {
+                    return Err("the sender disconnected".to_owned());
+                }
+                RelayOutgoingPayload::CancelSenderReady(_)
+                | RelayOutgoingPayload::CancelSenderTransfer(_) => {
+                    return Err("the sender cancelled the transfer".to_owned());
+                }
+                RelayOutgoingPayload::RestartTransfer(_) => {
+                    return Err("the sender restarted the transfer".to_owned());
+                }
+                RelayOutgoingPayload::SenderAck(ack) if ack.request_type == "uploadOutOfSync" => {
+                    return Err("the sender lost track of the transfer".to_owned());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn acknowledge(
+        &mut self,
+        status: &str,
+        total_chunks: u16,
+        received_size: u64,
+        chunk_index: u32,
+        chunk_data_size: u32,
+    ) {
+        let payload = RelayIncomingPayload::FileTransferAck(FileTransferAckPayload {
+            recipient_id: None,
+            sender_id: self.sender_id.clone(),
+            status: status.to_owned(),
+            file_index: None,
+            file_name: self.file.name.clone(),
+            total_chunks,
+            uploaded_size: received_size,
+            chunk_index,
+            chunk_data_size,
+            recipient_transfer_progress: (received_size * 100 / self.file.size.max(1)) as u8,
+        });
+        self.send(payload).await;
+    }
+
+    async fn send(&mut self, payload: RelayIncomingPayload) {
+        handle_text_message_payload(
+            payload,
+            &self.tx,
+            &self.state,
+            &self.peer_id,
+            self.client_ip,
+            self.stop_flag.clone(),
+            &mut self.verifier,
+        )
+        .await;
+    }
+
+    async fn next_reply(&mut self) -> Option<RelayOutgoingPayload> {
+        loop {
+            match self.rx.recv().await? {
+                Outgoing::Control(msg) => return Some(msg.payload),
+                Outgoing::Frame(Message::Close(_)) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    async fn close(self, reason: DisconnectReason) {
+        if let Some(removed) =
+            peer_disconnect::cleanup_peer_state(&self.state, &self.peer_id, &self.tx)
+        {
+            peer_disconnect::notify_peers_on_disconnect(
+                &self.state,
+                &self.peer_id,
+                &removed,
+                reason,
+            )
+            .await;
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    extract::ws::Message,
+    http::{HeaderMap, header},
+};
+use futures::StreamExt;
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::timeout,
+};
+
+use crate::{
+    config::CONFIG,
+    feature::relay::{
+        codec::Outgoing,
+        error::{ErrorCode, ErrorMessage},
+        integrity::StreamVerifier,
+        passphrase::PASSPHRASE_HEADER,
+        state::RelayState,
+        types::DisconnectReason,
+        ws::{
+            dto::{
+                request::{
+                    FileChunkPayload, FileEndPayload, FileMetadataPayload, RelayIncomingPayload,
+                    SenderAckPayload,
+                },
+                response::RelayOutgoingPayload,
+            },
+            peer_disconnect,
+            read_handlers::{handle_binary_message, handle_text_message_payload},
+        },
+    },
+};
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// A file sent over plain HTTP, e.g. with `curl -T`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUpload {
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpUploadError {
+    InvalidName(String),
+    /// The request has no `Content-Length`, e.g. a chunked upload from stdin.
+    LengthRequired,
+    Empty,
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+}
+
+impl fmt::Display for HttpUploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "`{name}` is not a valid file name"),
+            Self::LengthRequired => write!(f, "uploads need a `Content-Length` header"),
+            Self::Empty => write!(f, "the file is empty"),
+            Self::TooLarge { size, limit } => {
+                write!(f, "file of {size} bytes exceeds the limit of {limit} bytes")
+            }
+        }
+    }
+}
+
+impl HttpUpload {
+    /// Describes the file from the request: its name from the path, its size
+    /// from `Content-Length`, its type from `Content-Type` and an optional
+    /// passphrase from the passphrase header.
+    pub fn from_request(name: String, headers: &HeaderMap) -> Result<Self, HttpUploadError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(HttpUploadError::InvalidName(name));
+        }
+
+        let size = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or(HttpUploadError::LengthRequired)?;
+        if size == 0 {
+            return Err(HttpUploadError::Empty);
+        }
+        // `totalChunks` is a u16, which caps how many chunks a file can have.
+        let limit = CONFIG
+            .max_file_size
+            .min(u64::from(CONFIG.max_chunk_size) * u64::from(u16::MAX));
+        if size > limit {
+            return Err(HttpUploadError::TooLarge { size, limit });
+        }
+
+        let header_str = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        Ok(Self {
+            name,
+            size,
+            mime_type: header_str(header::CONTENT_TYPE.as_str())
+                .unwrap_or(DEFAULT_MIME_TYPE)
+                .to_owned(),
+            passphrase: header_str(PASSPHRASE_HEADER).map(str::to_owned),
+        })
+    }
+
+    pub fn total_chunks(&self) -> u16 {
+        self.size.div_ceil(u64::from(CONFIG.max_chunk_size)) as u16
+    }
+}
+
+/// A sender without a socket. It announces an [`HttpUpload`] and feeds the
+/// request body through the same handlers as a WebSocket sender, so the
+/// recipient sees the usual `senderAck`, `fileChunk` and `fileEnd` messages.
+/// Replies the relay would write to a socket land in `rx` instead.
+pub struct HttpSender {
+    state: RelayState,
+    peer_id: String,
+    client_ip: IpAddr,
+    upload: HttpUpload,
+    pair_timeout: Duration,
+    tx: Sender<Outgoing>,
+    rx: Receiver<Outgoing>,
+    stop_flag: Arc<AtomicBool>,
+    verifier: StreamVerifier,
+}
+
+impl HttpSender {
+    /// Registers a peer for the upload and announces its file. The error is
+    /// whatever the relay replied to the announcement.
+    pub async fn announce(
+        state: RelayState,
+        upload: HttpUpload,
+        client_ip: IpAddr,
+    ) -> Result<Self, ErrorMessage> {
+        let (tx, rx) = mpsc::channel(100);
+        let registration = state
+            .registry
+            .register_peer(None, tx.clone(), CONFIG.peer_id_policy)
+            .map_err(|_| {
+                ErrorMessage::new(ErrorCode::PeerIdTaken, "failed to register the upload")
+            })?;
+
+        let mut sender = Self {
+            state,
+            peer_id: registration.peer_id,
+            client_ip,
+            upload,
+            pair_timeout: CONFIG.upload_pair_timeout,
+            tx,
+            rx,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+            verifier: StreamVerifier::default(),
+        };
+
+        let upload = sender.upload.clone();
+        sender
+            .send(RelayIncomingPayload::FileMetadata(FileMetadataPayload {
+                sender_id: None,
+                name: upload.name,
+                size: upload.size,
+                mime_type: upload.mime_type,
+                sha256: None,
+                share_mode: None,
+                store_and_forward: None,
+                passphrase: upload.passphrase,
+                e2ee: None,
+            }))
+            .await;
+        // The announcement is only answered when it fails.
+        while let Ok(Outgoing::Control(msg)) = sender.rx.try_recv() {
+            if let RelayOutgoingPayload::Error(err) = msg.payload {
+                sender.close(DisconnectReason::Other).await;
+                return Err(err);
+            }
+        }
+        Ok(sender)
+    }
+
+    /// How long to wait for a recipient before giving up.
+    pub fn with_pair_timeout(mut self, pair_timeout: Duration) -> Self {
+        self.pair_timeout = pair_timeout;
+        self
+    }
+
+    pub fn sender_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Where recipients open the share in the UI.
+    pub fn share_link(&self) -> String {
+        format!(
+            "{}/transfer/receive?id={}",
+            CONFIG.share_link_base_url.trim_end_matches('/'),
+            self.peer_id
+        )
+    }
+
+    /// Waits for a recipient, sends it `body` and leaves the relay. Progress
+    /// is reported to the uploading client as lines on `progress`; the
+    /// upload is abandoned as soon as that client goes away.
+    pub async fn run(mut self, body: Body, progress: Sender<String>) {
+        let reason = match self.transfer(body, &progress).await {
+            Ok(()) => {
+                let done = format!("Sent {} bytes.\n", self.upload.size);
+                let _ = progress.send(done).await;
+                DisconnectReason::TransferCompleted
+            }
+            Err(err) => {
+                tracing::info!(sender_id = %self.peer_id, error = %err, "http upload failed");
+                let _ = progress.send(format!("Upload failed: {err}\n")).await;
+                DisconnectReason::Other
+            }
+        };
+        self.close(reason).await;
+    }
+
+    async fn transfer(&mut self, body: Body, progress: &Sender<String>) -> Result<(), String> {
+        let waiting = format!(
+            "Waiting up to {} seconds for a recipient...\n",
+            self.pair_timeout.as_secs()
+        );
+        let _ = progress.send(waiting).await;
+
+        let recipient_id = timeout(self.pair_timeout, async {
+            loop {
+                match self.next_reply(progress).await? {
+                    RelayOutgoingPayload::RecipientReady(ready) => break Ok(ready.recipient_id),
+                    RelayOutgoingPayload::Error(err) => break Err(err.message),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            format!(
+                "no recipient connected within {} seconds",
+                self.pair_timeout.as_secs()
+            )
+        })??;
+
+        let _ = progress
+            .send(format!("Sending to recipient `{recipient_id}`...\n"))
+            .await;
+        self.send(RelayIncomingPayload::SenderAck(SenderAckPayload {
+            request_type: "recipientReady".to_owned(),
+            sender_id: None,
+            recipient_id,
+            message: None,
+        }))
+        .await;
+
+        let chunk_size = CONFIG.max_chunk_size as usize;
+        let total_chunks = self.upload.total_chunks();
+        let mut frames = body.into_data_stream();
+        let mut buffer = Vec::with_capacity(chunk_size);
+        let mut uploaded_size = 0;
+        let mut chunk_index = 0;
+        while let Some(chunk) = read_chunk(&mut frames, &mut buffer, chunk_size).await? {
+            uploaded_size += chunk.len() as u64;
+            if uploaded_size > self.upload.size {
+                return Err("the body is longer than its `Content-Length`".to_owned());
+            }
+
+            self.send(RelayIncomingPayload::FileChunk(FileChunkPayload {
+                sender_id: None,
+                file_index: None,
+                file_name: self.upload.name.clone(),
+                total_size: self.upload.size,
+                total_chunks,
+                uploaded_size,
+                chunk_index,
+                chunk_data_size: chunk.len() as u32,
+                chunk_sha256: None,
+                sender_transfer_progress: (uploaded_size * 100 / self.upload.size) as u8,
+            }))
+            .await;
+            handle_binary_message(
+                chunk,
+                &self.tx,
+                &self.state,
+                &self.peer_id,
+                self.stop_flag.clone(),
+                &mut self.verifier,
+            )
+            .await;
+            self.wait_for_ack("acknowledged", progress).await?;
+            chunk_index += 1;
+        }
+        if uploaded_size != self.upload.size {
+            return Err("the body is shorter than its `Content-Length`".to_owned());
+        }
+
+        self.send(RelayIncomingPayload::FileEnd(FileEndPayload {
+            sender_id: None,
+            file_index: None,
+            file_name: self.upload.name.clone(),
+            total_size: self.upload.size,
+            total_chunks,
+            uploaded_size,
+            last_chunk_index: chunk_index,
+            sha256: None,
+        }))
+        .await;
+        self.wait_for_ack("completed", progress).await
+    }
+
+    async fn send(&mut self, payload: RelayIncomingPayload) {
+        handle_text_message_payload(
+            payload,
+            &self.tx,
+            &self.state,
+            &self.peer_id,
+            self.client_ip,
+            self.stop_flag.clone(),
+            &mut self.verifier,
+        )
+        .await;
+    }
+
+    /// The next message the relay sent this sender.
+    async fn next_reply(
+        &mut self,
+        progress: &Sender<String>,
+    ) -> Result<RelayOutgoingPayload, String> {
+        loop {
+            let msg = tokio::select! {
+                msg = self.rx.recv() => msg,
+                _ = progress.closed() => return Err("the uploading client went away".to_owned()),
+            };
+            match msg {
+                Some(Outgoing::Control(msg)) => return Ok(msg.payload),
+                Some(Outgoing::Frame(Message::Close(_))) | None => {
+                    return Err("the relay closed the upload".to_owned());
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Waits for the recipient to acknowledge with `status`, failing if it
+    /// reports an error, cancels or leaves.
+    async fn wait_for_ack(
+        &mut self,
+        status: &str,
+        progress: &Sender<String>,
+    ) -> Result<(), String> {
+        loop {
+            match self.next_reply(progress).await? {
+                RelayOutgoingPayload::FileTransferAck(ack) if ack.status == status => {
+                    return Ok(());
+                }
+                RelayOutgoingPayload::FileTransferAck(ack) if ack.status == "error" => {
+                    return Err(format!(
+                        "recipient `{}` reported an error",
+                        ack.recipient_id
+                    ));
+                }
+                RelayOutgoingPayload::Error(err) => return Err(err.message),
+                RelayOutgoingPayload::PeerDisconnected(_)
+                | RelayOutgoingPayload::RecipientDropped(_) => {
+                    return Err("the recipient disconnected".to_owned());
+                }
+                RelayOutgoingPayload::CancelRecipientReady(_)
+                | RelayOutgoingPayload::CancelRecipientTransfer(_) => {
+                    return Err("the recipient cancelled the transfer".to_owned());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn close(self, reason: DisconnectReason) {
+        if let Some(removed) =
+            peer_disconnect::cleanup_peer_state(&self.state, &self.peer_id, &self.tx)
+        {
+            peer_disconnect::notify_peers_on_disconnect(
+                &self.state,
+                &self.peer_id,
+                &removed,
+                reason,
+            )
+            .await;
+        }
+    }
+}
+
+/// Regroups the request body into chunks of `chunk_size` bytes, whatever
+/// sizes its frames arrive in. Only the last chunk may be shorter.
+async fn read_chunk(
+    frames: &mut BodyDataStream,
+    buffer: &mut Vec<u8>,
+    chunk_size: usize,
+) -> Result<Option<Bytes>, String> {
+    while buffer.len() < chunk_size {
+        match frames.next().await {
+            Some(Ok(frame)) => buffer.extend_from_slice(&frame),
+            Some(Err(err)) => return Err(format!("failed to read the upload: {err}")),
+            None => break,
+        }
+    }
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+
+    let rest = buffer.split_off(buffer.len().min(chunk_size));
+    Ok(Some(Bytes::from(std::mem::replace(buffer, rest))))
+}
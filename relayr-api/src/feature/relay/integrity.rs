@@ -0,0 +1,176 @@
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+pub type Sha256Digest = [u8; 32];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IntegrityError {
+    InvalidHash(String),
+    ChunkMismatch {
+        file_index: Option<u32>,
+        chunk_index: u32,
+    },
+    FileMismatch {
+        file_index: Option<u32>,
+    },
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHash(hash) => {
+                write!(f, "`{hash}` is not a hex-encoded SHA-256 digest")
+            }
+            Self::ChunkMismatch {
+                file_index,
+                chunk_index,
+            } => write!(
+                f,
+                "chunk {chunk_index} of file {} does not match its SHA-256",
+                file_index.unwrap_or_default()
+            ),
+            Self::FileMismatch { file_index } => write!(
+                f,
+                "file {} does not match its SHA-256",
+                file_index.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+pub fn parse_sha256(hash: &str) -> Result<Sha256Digest, IntegrityError> {
+    let mut digest = [0; 32];
+    hex::decode_to_slice(hash, &mut digest)
+        .map_err(|_| IntegrityError::InvalidHash(hash.to_owned()))?;
+    Ok(digest)
+}
+
+/// Checks the bytes a sender streams through the relay against the hashes it
+/// declared: per chunk in `fileChunk`, and per file in `fileMeta` or
+/// `fileManifest` and again in `fileEnd`. Lives with the sender's socket,
+/// which sees every frame in order.
+///
+/// A file is only hashed as a whole when its chunks arrive from chunk 0 in
+/// order; a resumed file can still be checked chunk by chunk.
+///
+/// Checking never changes what the verifier expects, so a frame or
+/// `fileEnd` the relay turns away for another reason can be sent again. Only
+/// once it has been accepted is it committed.
+#[derive(Debug, Clone, Default)]
+pub struct StreamVerifier {
+    // Declared whole-file hashes, by file index.
+    file_hashes: Vec<Option<Sha256Digest>>,
+    file_index: Option<u32>,
+    hasher: Sha256,
+    next_chunk: u32,
+    contiguous: bool,
+    pending_chunk: Option<(u32, Sha256Digest)>,
+    // Set between a `fileChunk` message and the binary frame it announced.
+    awaiting_frame: bool,
+}
+
+impl StreamVerifier {
+    /// Starts over for a newly announced share.
+    pub fn reset(&mut self, file_hashes: Vec<Option<Sha256Digest>>) {
+        *self = Self {
+            file_hashes,
+            ..Self::default()
+        };
+    }
+
+    /// Records a `fileChunk` message; the next binary frame is its data.
+    pub fn expect_chunk(
+        &mut self,
+        file_index: Option<u32>,
+        chunk_index: u32,
+        chunk_hash: Option<Sha256Digest>,
+    ) {
+        let same_file = file_index.unwrap_or_default() == self.file_index.unwrap_or_default();
+        // The relay turned away the frame of the chunk announced last, so
+        // the sender is announcing it again.
+        let resent = self.awaiting_frame && same_file && chunk_index + 1 == self.next_chunk;
+        if chunk_index == 0 || !same_file {
+            self.hasher.reset();
+            self.contiguous = chunk_index == 0;
+        } else if chunk_index != self.next_chunk && !resent {
+            self.contiguous = false;
+        }
+        self.file_index = file_index;
+        self.next_chunk = chunk_index + 1;
+        self.pending_chunk = chunk_hash.map(|hash| (chunk_index, hash));
+        self.awaiting_frame = true;
+    }
+
+    /// File and chunk index announced by a `fileChunk` message whose binary
+    /// frame has not arrived yet.
+    pub fn pending_frame(&self) -> Option<(u32, u32)> {
+        self.awaiting_frame.then(|| {
+            (
+                self.file_index.unwrap_or_default(),
+                self.next_chunk.saturating_sub(1),
+            )
+        })
+    }
+
+    /// Checks a binary frame against the hash of the chunk it belongs to.
+    pub fn check_chunk(&self, data: &[u8]) -> Result<(), IntegrityError> {
+        match self.pending_chunk {
+            Some((chunk_index, expected)) if Sha256::digest(data)[..] != expected => {
+                Err(IntegrityError::ChunkMismatch {
+                    file_index: self.file_index,
+                    chunk_index,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Feeds an accepted binary frame through the file hasher.
+    pub fn commit_chunk(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.pending_chunk = None;
+        self.awaiting_frame = false;
+    }
+
+    /// Checks the file a `fileEnd` closes against the hash declared up front
+    /// and the one confirmed in `fileEnd`, whichever are present.
+    pub fn check_file(
+        &self,
+        file_index: Option<u32>,
+        confirmed_hash: Option<Sha256Digest>,
+    ) -> Result<(), IntegrityError> {
+        let digest: Sha256Digest = self.hasher.clone().finalize().into();
+        let verifiable = self.contiguous
+            && file_index.unwrap_or_default() == self.file_index.unwrap_or_default();
+
+        let declared_hash = self
+            .file_hashes
+            .get(file_index.unwrap_or_default() as usize)
+            .copied()
+            .flatten();
+        let consistent = match (declared_hash, confirmed_hash) {
+            (Some(declared), Some(confirmed)) => declared == confirmed,
+            _ => true,
+        };
+        let matches = !verifiable
+            || declared_hash
+                .into_iter()
+                .chain(confirmed_hash)
+                .all(|expected| expected == digest);
+
+        if consistent && matches {
+            Ok(())
+        } else {
+            Err(IntegrityError::FileMismatch { file_index })
+        }
+    }
+
+    /// Records an accepted `fileEnd`; the next chunk starts a new file.
+    pub fn finish_file(&mut self) {
+        self.hasher.reset();
+        self.contiguous = false;
+        self.pending_chunk = None;
+        self.awaiting_frame = false;
+    }
+}
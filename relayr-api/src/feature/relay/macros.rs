@@ -7,26 +7,3 @@ macro_rules! send_or_stop {
         }
     };
 }
-
-#[macro_export]
-macro_rules! impl_ws_text_response {
-    ($type:ty) => {
-        impl fmt::Display for $type {
-            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-                match serde_json::to_string(self) {
-                    Ok(res) => write!(f, "{res}"),
-                    Err(e) => {
-                        tracing::error!(?self, error = %e, "failed to serialize dto response");
-                            write!(f, "{{\"success\": \"false\", \"message\": \"internal serialization error\"}}")
-                    }
-                }
-            }
-        }
-
-        impl AsWsTextMessage for $type {
-            fn as_ws_text_message(&self) -> Message {
-                Message::text(self.to_string())
-            }
-        }
-    };
-}
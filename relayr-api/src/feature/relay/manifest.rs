@@ -0,0 +1,109 @@
+use std::{collections::HashSet, fmt};
+
+use serde::Serialize;
+
+use crate::feature::relay::integrity::parse_sha256;
+
+/// One file of a multi-file share.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    /// Relative to the folder the recipient saves into, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Every file a sender announced with `fileManifest`. Chunks and `fileEnd`
+/// refer to a file by its index in `files`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileManifest {
+    pub files: Vec<ManifestEntry>,
+    pub total_size: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ManifestError {
+    Empty,
+    InvalidPath { path: String, reason: &'static str },
+    DuplicatePath(String),
+    InvalidHash { path: String, hash: String },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "manifest must list at least one file"),
+            Self::InvalidPath { path, reason } => write!(f, "invalid path `{path}`: {reason}"),
+            Self::DuplicatePath(path) => write!(f, "path `{path}` is listed more than once"),
+            Self::InvalidHash { path, hash } => {
+                write!(f, "`{hash}` is not a valid SHA-256 for `{path}`")
+            }
+        }
+    }
+}
+
+impl FileManifest {
+    /// Builds a manifest after checking that every path is a safe relative
+    /// path, that no path is listed twice and that declared hashes parse.
+    pub fn new(files: Vec<ManifestEntry>) -> Result<Self, ManifestError> {
+        if files.is_empty() {
+            return Err(ManifestError::Empty);
+        }
+
+        let mut seen = HashSet::new();
+        for entry in &files {
+            validate_relative_path(&entry.path).map_err(|reason| ManifestError::InvalidPath {
+                path: entry.path.clone(),
+                reason,
+            })?;
+            if !seen.insert(entry.path.as_str()) {
+                return Err(ManifestError::DuplicatePath(entry.path.clone()));
+            }
+            if let Some(hash) = &entry.sha256
+                && parse_sha256(hash).is_err()
+            {
+                return Err(ManifestError::InvalidHash {
+                    path: entry.path.clone(),
+                    hash: hash.clone(),
+                });
+            }
+        }
+
+        let total_size = files.iter().map(|entry| entry.size).sum();
+        Ok(Self { files, total_size })
+    }
+}
+
+/// Rejects paths that could land outside the recipient's destination folder
+/// once joined onto it: absolute paths, drive prefixes, `..` and `.`
+/// segments, and empty segments. Backslashes count as separators so a
+/// Windows recipient sees the same path as everyone else.
+pub fn validate_relative_path(path: &str) -> Result<(), &'static str> {
+    if path.is_empty() {
+        return Err("path is empty");
+    }
+    if path.contains('\0') {
+        return Err("path contains a NUL byte");
+    }
+    if path.starts_with(['/', '\\']) {
+        return Err("path must be relative");
+    }
+    if let [drive, b':', ..] = path.as_bytes()
+        && drive.is_ascii_alphabetic()
+    {
+        return Err("path must not start with a drive prefix");
+    }
+
+    for segment in path.split(['/', '\\']) {
+        match segment {
+            "" => return Err("path contains an empty segment"),
+            "." | ".." => return Err("path must not contain `.` or `..` segments"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
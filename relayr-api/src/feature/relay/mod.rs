@@ -1,7 +1,24 @@
+pub mod admin;
+pub mod asyncapi;
+pub mod codec;
+pub mod e2ee;
 pub mod error;
+pub mod flow;
+pub mod frame;
 pub mod handlers;
+pub mod http;
+pub mod integrity;
 pub mod macros;
+pub mod manifest;
+pub mod pairing;
+pub mod passphrase;
+pub mod protocol;
+pub mod registry;
 pub mod routes;
+pub mod session;
+pub mod spool;
 pub mod state;
+pub mod throttle;
 pub mod types;
+pub mod typescript;
 pub mod ws;
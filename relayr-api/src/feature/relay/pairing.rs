@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, seq::IndexedRandom};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::feature::relay::throttle::AttemptLimiter;
+
+/// How a pairing code is spelled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PairingCodeStyle {
+    /// Six digits, e.g. `042917`.
+    #[default]
+    Digits,
+    /// Three words joined by dashes, e.g. `amber-fox-river`.
+    Words,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingError {
+    /// The code is unknown, expired or already used. These are deliberately
+    /// indistinguishable so a guesser learns nothing from the reply.
+    InvalidCode,
+    /// The client made too many failed attempts and must wait `retry_after`.
+    Throttled { retry_after: Duration },
+}
+
+impl fmt::Display for PairingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCode => write!(f, "pairing code is invalid or has expired"),
+            Self::Throttled { retry_after } => write!(
+                f,
+                "too many failed pairing attempts, retry in {} seconds",
+                retry_after.as_secs().max(1)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingCode {
+    sender_id: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    codes: HashMap<String, PendingCode>,
+    attempts: AttemptLimiter<IpAddr>,
+}
+
+/// Short, single-use codes standing in for a sender ID, so a recipient can
+/// type `042917` instead of copying a long peer ID.
+///
+/// The code space is small, so every client is allowed only a few failed
+/// lookups per throttle window; once over the limit, even a correct code is
+/// refused until the window ends.
+#[derive(Debug)]
+pub struct PairingCodes {
+    inner: Mutex<Inner>,
+    ttl: Duration,
+}
+
+impl PairingCodes {
+    pub fn new(ttl: Duration, max_failed_attempts: u32, throttle_window: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                codes: HashMap::new(),
+                attempts: AttemptLimiter::new(max_failed_attempts, throttle_window),
+            }),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Mints a fresh code for `sender_id`, revoking any code it held before.
+    pub fn mint(&self, sender_id: &str, style: PairingCodeStyle) -> String {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        inner
+            .codes
+            .retain(|_, pending| pending.expires_at > now && pending.sender_id != sender_id);
+
+        let mut rng = rand::rng();
+        let code = loop {
+            let code = match style {
+                PairingCodeStyle::Digits => format!("{:06}", rng.random_range(0..1_000_000)),
+                PairingCodeStyle::Words => (0..3)
+                    .map(|_| *WORDS.choose(&mut rng).expect("word list is not empty"))
+                    .collect::<Vec<_>>()
+                    .join("-"),
+            };
+            if !inner.codes.contains_key(&code) {
+                break code;
+            }
+        };
+
+        inner.codes.insert(
+            code.clone(),
+            PendingCode {
+                sender_id: sender_id.to_owned(),
+                expires_at: now + self.ttl,
+            },
+        );
+        code
+    }
+
+    /// Revokes the code `sender_id` holds, if any, e.g. once it has nothing
+    /// left to share.
+    pub fn revoke(&self, sender_id: &str) {
+        self.lock()
+            .codes
+            .retain(|_, pending| pending.sender_id != sender_id);
+    }
+
+    /// Consumes `code` and returns the sender it stands for. Failed attempts
+    /// are counted against `client`.
+    pub fn redeem(&self, code: &str, client: IpAddr) -> Result<String, PairingError> {
+        self.look_up(code, client, true)
+    }
+
+    /// Returns the sender `code` stands for, leaving the code in place, e.g.
+    /// until the recipient has also given the right passphrase. Failed
+    /// attempts are counted as for [`Self::redeem`].
+    pub fn peek(&self, code: &str, client: IpAddr) -> Result<String, PairingError> {
+        self.look_up(code, client, false)
+    }
+
+    fn look_up(&self, code: &str, client: IpAddr, consume: bool) -> Result<String, PairingError> {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        inner
+            .attempts
+            .check(&client, now)
+            .map_err(|retry_after| PairingError::Throttled { retry_after })?;
+
+        let code = normalize(code);
+        let pending = if consume {
+            inner.codes.remove(&code)
+        } else {
+            inner.codes.get(&code).cloned()
+        };
+        match pending {
+            Some(pending) if pending.expires_at > now => Ok(pending.sender_id),
+            _ => {
+                inner.attempts.record_failure(client, now);
+                Err(PairingError::InvalidCode)
+            }
+        }
+    }
+}
+
+/// Accepts codes the way people type them: any case, and digits or words
+/// split by spaces or dashes.
+fn normalize(code: &str) -> String {
+    let parts: Vec<String> = code
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if parts
+        .iter()
+        .all(|part| part.bytes().all(|b| b.is_ascii_digit()))
+    {
+        parts.concat()
+    } else {
+        parts.join("-")
+    }
+}
+
+// 256 short, distinct words, so a three-word code carries 24 bits.
+const WORDS: &[&str] = &[
+    "acid", "acorn", "actor", "agent", "alarm", "album", "alpha", "amber", "angle", "apple",
+    "apron", "arena", "arrow", "aspen", "atlas", "attic", "badge", "bagel", "baker", "bamboo",
+    "banjo", "barn", "basil", "beach", "beard", "bell", "berry", "bison", "blade", "blaze",
+    "bloom", "board", "boat", "bolt", "bonus", "boot", "brave", "bread", "brick", "brook", "brush",
+    "bunny", "cabin", "cable", "cactus", "camel", "candy", "canoe", "cargo", "carol", "cedar",
+    "chalk", "charm", "chess", "chief", "cider", "cinema", "civic", "claim", "clay", "cliff",
+    "cloud", "clover", "coast", "cobra", "comet", "coral", "cotton", "crane", "crisp", "crown",
+    "cube", "curry", "daisy", "dance", "delta", "denim", "depot", "desk", "diver", "dock",
+    "dolphin", "donut", "dragon", "dream", "drift", "drum", "dune", "eagle", "earth", "echo",
+    "elbow", "elder", "ember", "engine", "epic", "fable", "falcon", "fancy", "feast", "fern",
+    "ferry", "fiber", "field", "flame", "flint", "flute", "focus", "forest", "fossil", "fox",
+    "frost", "fudge", "gala", "galaxy", "garden", "gecko", "ghost", "giant", "ginger", "glade",
+    "globe", "glove", "goat", "grain", "grape", "gravel", "gull", "harbor", "hazel", "heron",
+    "hill", "honey", "hotel", "husky", "igloo", "index", "iris", "ivory", "jacket", "jade", "jazz",
+    "jelly", "jewel", "jolly", "judge", "juice", "jungle", "kayak", "kettle", "kiwi", "koala",
+    "ladder", "lagoon", "lamp", "laser", "lemon", "lilac", "lily", "linen", "lion", "llama",
+    "lobby", "lotus", "lunar", "magic", "mango", "maple", "marble", "meadow", "melon", "metro",
+    "mint", "mocha", "moose", "mosaic", "motor", "mural", "nectar", "noble", "north", "novel",
+    "nutmeg", "oasis", "ocean", "olive", "omega", "onion", "opal", "orbit", "otter", "oxide",
+    "paddle", "panda", "paper", "patio", "peach", "pearl", "pepper", "piano", "pilot", "pine",
+    "pixel", "plaza", "plum", "polar", "pony", "prism", "pulse", "quartz", "quest", "quill",
+    "radar", "raven", "reef", "ridge", "river", "robin", "rocket", "rose", "ruby", "saddle",
+    "salsa", "sand", "satin", "scout", "shell", "silk", "sketch", "slate", "solar", "spark",
+    "spice", "squid", "stone", "storm", "sugar", "swan", "tango", "thorn", "tiger", "toast",
+    "topaz", "torch", "tulip", "tundra", "turtle", "umber", "vapor", "velvet", "violet", "walrus",
+    "willow", "yacht", "zebra", "zinc",
+];
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use tokio::task::spawn_blocking;
+
+use crate::feature::relay::throttle::AttemptLimiter;
+
+/// Request header carrying the passphrase for `/file-meta`.
+pub const PASSPHRASE_HEADER: &str = "x-share-passphrase";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassphraseError {
+    /// The share is protected and no passphrase was given.
+    Required,
+    /// The passphrase does not match.
+    Wrong,
+    /// The client got the passphrase wrong too often and must wait
+    /// `retry_after`.
+    LockedOut { retry_after: Duration },
+    /// The passphrase could not be hashed.
+    Hash(String),
+}
+
+impl fmt::Display for PassphraseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Required => write!(f, "this share is protected by a passphrase"),
+            Self::Wrong => write!(f, "wrong passphrase"),
+            Self::LockedOut { retry_after } => write!(
+                f,
+                "too many wrong passphrases, retry in {} seconds",
+                retry_after.as_secs().max(1)
+            ),
+            Self::Hash(err) => write!(f, "failed to hash passphrase: {err}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ShareLock {
+    // PHC string, so the salt and Argon2 parameters travel with the hash.
+    hash: String,
+    // Set once the sender is gone but its share lives on in the spool.
+    expires_at: Option<Instant>,
+}
+
+/// The passphrase a share had before [`ShareLocks::protect`] replaced it,
+/// to put back if the new announcement is rejected.
+#[derive(Debug)]
+pub struct ReplacedLock(Option<ShareLock>);
+
+#[derive(Debug)]
+struct Inner {
+    locks: HashMap<String, ShareLock>,
+    attempts: AttemptLimiter<(String, IpAddr)>,
+}
+
+/// Argon2 hashes of the passphrases senders attached to their shares, keyed
+/// by sender ID. The passphrase itself is never kept.
+///
+/// Hashing is deliberately slow, so it runs on the blocking pool and never
+/// under the lock. Wrong guesses are counted per share and client, and a
+/// client that keeps guessing is locked out of that share for a while.
+#[derive(Debug)]
+pub struct ShareLocks {
+    inner: Mutex<Inner>,
+}
+
+impl ShareLocks {
+    pub fn new(max_failed_attempts: u32, lockout: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                locks: HashMap::new(),
+                attempts: AttemptLimiter::new(max_failed_attempts, lockout),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        inner
+            .locks
+            .retain(|_, lock| lock.expires_at.is_none_or(|expires_at| expires_at > now));
+        inner
+    }
+
+    /// Protects `sender_id`'s share with `passphrase`, or lifts the
+    /// protection when there is none. Either way any earlier passphrase is
+    /// replaced, and handed back for [`Self::restore`].
+    pub async fn protect(
+        &self,
+        sender_id: &str,
+        passphrase: Option<String>,
+    ) -> Result<ReplacedLock, PassphraseError> {
+        let Some(passphrase) = passphrase else {
+            return Ok(ReplacedLock(self.lock().locks.remove(sender_id)));
+        };
+
+        let hash = spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(passphrase.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| PassphraseError::Hash(err.to_string()))
+        })
+        .await
+        .map_err(|err| PassphraseError::Hash(err.to_string()))??;
+
+        let replaced = self.lock().locks.insert(
+            sender_id.to_owned(),
+            ShareLock {
+                hash,
+                expires_at: None,
+            },
+        );
+        Ok(ReplacedLock(replaced))
+    }
+
+    /// Puts back the passphrase `protect` replaced, when the announcement
+    /// it was meant for failed.
+    pub fn restore(&self, sender_id: &str, replaced: ReplacedLock) {
+        let mut inner = self.lock();
+        match replaced.0 {
+            Some(lock) => inner.locks.insert(sender_id.to_owned(), lock),
+            None => inner.locks.remove(sender_id),
+        };
+    }
+
+    pub fn is_protected(&self, sender_id: &str) -> bool {
+        self.lock().locks.contains_key(sender_id)
+    }
+
+    /// Checks `passphrase` against `sender_id`'s share. Unprotected shares
+    /// accept anything; wrong guesses are counted against `client`.
+    pub async fn verify(
+        &self,
+        sender_id: &str,
+        passphrase: Option<&str>,
+        client: IpAddr,
+    ) -> Result<(), PassphraseError> {
+        let key = (sender_id.to_owned(), client);
+        let hash = {
+            let mut inner = self.lock();
+            let Some(lock) = inner.locks.get(sender_id) else {
+                return Ok(());
+            };
+            let hash = lock.hash.clone();
+            inner
+                .attempts
+                .check(&key, Instant::now())
+                .map_err(|retry_after| PassphraseError::LockedOut { retry_after })?;
+            hash
+        };
+        let Some(passphrase) = passphrase else {
+            return Err(PassphraseError::Required);
+        };
+
+        let passphrase = passphrase.to_owned();
+        let matches = spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(passphrase.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or(false);
+
+        let mut inner = self.lock();
+        if matches {
+            inner.attempts.reset(&key);
+            Ok(())
+        } else {
+            inner.attempts.record_failure(key, Instant::now());
+            Err(PassphraseError::Wrong)
+        }
+    }
+
+    /// Drops the passphrase once the share is gone.
+    pub fn release(&self, sender_id: &str) {
+        self.lock().locks.remove(sender_id);
+    }
+
+    /// Keeps the passphrase for `ttl` more, for a share that outlives its
+    /// sender in the spool.
+    pub fn release_after(&self, sender_id: &str, ttl: Duration) {
+        if let Some(lock) = self.lock().locks.get_mut(sender_id) {
+            lock.expires_at = Some(Instant::now() + ttl);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Version spoken by this relay. Version 1 is the protocol from before
+/// `hello`; clients that never send `hello` are assumed to speak it.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest version a client may ask for in `hello`.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional parts of the protocol. Clients list the ones they can use in
+/// `hello`; the relay answers with those it has enabled as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    Broadcast,
+    Manifest,
+    Resume,
+    StoreAndForward,
+    FlowControl,
+    Integrity,
+    FrameEnvelope,
+    E2ee,
+    /// Anything this relay does not know about; never sent back.
+    #[serde(other)]
+    #[ts(skip)]
+    #[schemars(skip)]
+    Unknown,
+}
+
+impl Capability {
+    /// Every capability this relay supports, given whether a spool is
+    /// configured.
+    pub fn enabled(spool_enabled: bool) -> Vec<Self> {
+        let mut capabilities = vec![
+            Self::Broadcast,
+            Self::Manifest,
+            Self::Resume,
+            Self::FlowControl,
+            Self::Integrity,
+            Self::FrameEnvelope,
+            Self::E2ee,
+        ];
+        if spool_enabled {
+            capabilities.push(Self::StoreAndForward);
+        }
+        capabilities
+    }
+}
+
+/// Limits the relay enforces, announced so clients can size their chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    pub max_chunk_size: u32,
+    #[ts(type = "number")]
+    pub max_file_size: u64,
+    pub max_share_recipients: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub features: Vec<Capability>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnsupportedVersion {
+    pub requested: u32,
+}
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "protocol version {} is not supported, this relay speaks {}..={}",
+            self.requested, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        )
+    }
+}
+
+/// Settles on the highest version both sides speak and the features both
+/// sides listed.
+pub fn negotiate(
+    requested_version: u32,
+    client_capabilities: &[Capability],
+    enabled: &[Capability],
+) -> Result<Negotiated, UnsupportedVersion> {
+    if requested_version < MIN_PROTOCOL_VERSION {
+        return Err(UnsupportedVersion {
+            requested: requested_version,
+        });
+    }
+
+    let features = enabled
+        .iter()
+        .copied()
+        .filter(|capability| client_capabilities.contains(capability))
+        .collect();
+    Ok(Negotiated {
+        version: requested_version.min(PROTOCOL_VERSION),
+        features,
+    })
+}
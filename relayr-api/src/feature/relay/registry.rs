@@ -0,0 +1,996 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+
+use crate::feature::relay::{
+    codec::Outgoing,
+    flow::{Admission, FlowStatus},
+    session::{
+        AckOutcome, InvalidTransition, ResumePoint, ShareMode, TransferEvent, TransferSession,
+        TransferState,
+    },
+    types::{PeerIdPolicy, SharedFiles},
+};
+
+const SHARD_COUNT: usize = 16;
+const DEFAULT_RESUME_WINDOW: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_QUEUED_BYTES: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Default)]
+struct Shard {
+    peers: HashMap<String, Peer>,
+    shared_files: HashMap<String, SharedFiles>,
+    // Keyed by sender.
+    sessions: HashMap<String, TransferSession>,
+    // sender -> recipients in pairing order, stored in the sender's shard.
+    recipients_of: HashMap<String, Vec<String>>,
+    // recipient -> sender, stored in the recipient's shard.
+    sender_of: HashMap<String, String>,
+    // Transfers cut off by a disconnect, keyed by sender. They outlive the
+    // sender's own state so a reconnecting sender can pick them up.
+    parked: HashMap<String, Vec<ParkedTransfer>>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    tx: Sender<Outgoing>,
+    connected_at: Instant,
+}
+
+impl Peer {
+    fn new(tx: Sender<Outgoing>) -> Self {
+        Self {
+            tx,
+            connected_at: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParkedTransfer {
+    recipient_id: String,
+    shared_files: SharedFiles,
+    resume_from: ResumePoint,
+    parked_at: Instant,
+}
+
+impl Shard {
+    // Remembers how far `recipient_id` got if the transfer was under way.
+    fn park(&mut self, sender_id: &str, recipient_id: &str) {
+        let (Some(session), Some(shared_files)) = (
+            self.sessions.get(sender_id),
+            self.shared_files.get(sender_id),
+        ) else {
+            return;
+        };
+        if session.state() != TransferState::Transferring {
+            return;
+        }
+        let Some(resume_from) = session.resume_point(recipient_id) else {
+            return;
+        };
+
+        let parked = ParkedTransfer {
+            recipient_id: recipient_id.to_owned(),
+            shared_files: shared_files.clone(),
+            resume_from,
+            parked_at: Instant::now(),
+        };
+        let transfers = self.parked.entry(sender_id.to_owned()).or_default();
+        transfers.retain(|transfer| transfer.recipient_id != recipient_id);
+        transfers.push(parked);
+    }
+
+    // Takes the parked transfer of the pair if it is recent enough and the
+    // sender announced the same files again.
+    fn unpark(
+        &mut self,
+        sender_id: &str,
+        recipient_id: &str,
+        window: Duration,
+    ) -> Option<ResumePoint> {
+        let transfers = self.parked.get_mut(sender_id)?;
+        transfers.retain(|transfer| transfer.parked_at.elapsed() < window);
+        let position = transfers
+            .iter()
+            .position(|transfer| transfer.recipient_id == recipient_id);
+        let parked = position.map(|position| transfers.swap_remove(position));
+        if transfers.is_empty() {
+            self.parked.remove(sender_id);
+        }
+
+        let parked = parked?;
+        (self.shared_files.get(sender_id) == Some(&parked.shared_files))
+            .then_some(parked.resume_from)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    PeerIdTaken,
+}
+
+#[derive(Debug)]
+pub struct Registration {
+    pub peer_id: String,
+    /// Channel of the connection that previously owned `peer_id`, if it was
+    /// taken over.
+    pub replaced: Option<Sender<Outgoing>>,
+}
+
+#[derive(Debug)]
+pub struct Pairing {
+    pub sender_tx: Sender<Outgoing>,
+    /// Set when the pair was cut off mid-transfer within the resume window.
+    pub resume_from: Option<ResumePoint>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PairError {
+    SenderNotConnected,
+    RecipientNotConnected,
+    SenderBusy { current_recipient: String },
+    RecipientBusy { current_sender: String },
+    ShareFull { limit: usize },
+    InvalidState(InvalidTransition),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UnpairError {
+    NotPaired,
+    RecipientMismatch { current_recipient: String },
+    InvalidState(InvalidTransition),
+}
+
+/// Partners a peer was paired with at the moment it was removed.
+#[derive(Debug, Default)]
+pub struct RemovedPeer {
+    pub recipient_ids: Vec<String>,
+    pub sender_id: Option<String>,
+    /// Ack the sender was waiting on that no longer depends on the removed
+    /// recipient.
+    pub released_ack: Option<Outgoing>,
+}
+
+/// Recipients dropped from a broadcast share for falling behind.
+#[derive(Debug, Default)]
+pub struct DroppedRecipients {
+    pub recipient_ids: Vec<String>,
+    pub released_ack: Option<Outgoing>,
+}
+
+/// Whether a connected peer is sharing, receiving, or neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PeerRole {
+    /// Has announced a share.
+    Sender,
+    /// Is paired with a sender.
+    Recipient,
+    Idle,
+}
+
+/// A connected peer, as listed by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerSnapshot {
+    pub peer_id: String,
+    pub role: PeerRole,
+    pub connected_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recipient_ids: Vec<String>,
+}
+
+/// A sender's transfer session, as listed by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSnapshot {
+    pub sender_id: String,
+    pub state: TransferState,
+    pub mode: ShareMode,
+    pub e2ee: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<SharedFiles>,
+    pub total_size: u64,
+    pub bytes_relayed: u64,
+    pub recipients: Vec<RecipientProgress>,
+    /// Whether the share is stored in the spool. Stored shares outlive
+    /// their sender, and recipients of a replay are not listed.
+    pub stored: bool,
+}
+
+/// How far one recipient of a session has got.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecipientProgress {
+    pub recipient_id: String,
+    /// Chunks acknowledged without a gap, and the bytes they hold.
+    pub chunks_acknowledged: u32,
+    pub bytes_acknowledged: u64,
+    pub completed: bool,
+}
+
+// What happens to the sender's session when its last recipient leaves.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OnLastRecipient {
+    // Validate `Unpair` and detach.
+    Unpair,
+    // Validate `Cancel` and keep the pairing so the transfer can restart.
+    Cancel,
+    // Force the session back to `Announced` and detach, parking the
+    // recipient's progress so the pair can resume later.
+    Reset,
+    // Like `Reset`, but without parking: the pair must start over.
+    Evict,
+}
+
+/// Every piece of per-peer relay state lives here: the outgoing channel, the
+/// announced files, the sender's transfer session, and the sender <->
+/// recipient pairings in both directions.
+///
+/// Peers are spread across a fixed set of shards. Operations that touch
+/// several peers lock their shards in index order, so pairing and unpairing
+/// are atomic and cannot deadlock.
+#[derive(Debug)]
+pub struct SessionRegistry {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    resume_window: Duration,
+    max_queued_bytes: u64,
+}
+
+struct LockedShards<'a> {
+    registry: &'a SessionRegistry,
+    guards: Vec<(usize, MutexGuard<'a, Shard>)>,
+}
+
+impl LockedShards<'_> {
+    // Panics if `peer_id` was not part of the `lock_many` call.
+    fn of(&mut self, peer_id: &str) -> &mut Shard {
+        let index = self.registry.shard_index(peer_id);
+        self.guards
+            .iter_mut()
+            .find(|(i, _)| *i == index)
+            .map(|(_, guard)| &mut **guard)
+            .expect("shard was not locked")
+    }
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            hasher: RandomState::new(),
+            resume_window: DEFAULT_RESUME_WINDOW,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+        }
+    }
+
+    /// How long a transfer cut off by a disconnect can be resumed.
+    pub fn with_resume_window(mut self, resume_window: Duration) -> Self {
+        self.resume_window = resume_window;
+        self
+    }
+
+    /// How many relayed but unacknowledged bytes a recipient may have queued
+    /// before the sender is paused.
+    pub fn with_max_queued_bytes(mut self, max_queued_bytes: u64) -> Self {
+        self.max_queued_bytes = max_queued_bytes;
+        self
+    }
+
+    fn shard_index(&self, peer_id: &str) -> usize {
+        self.hasher.hash_one(peer_id) as usize % self.shards.len()
+    }
+
+    fn lock(&self, peer_id: &str) -> MutexGuard<'_, Shard> {
+        self.lock_index(self.shard_index(peer_id))
+    }
+
+    fn lock_index(&self, index: usize) -> MutexGuard<'_, Shard> {
+        // A panic while holding a shard never leaves it half-updated, so a
+        // poisoned lock is still safe to use.
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_many(&self, peer_ids: &[&str]) -> LockedShards<'_> {
+        let mut indices: Vec<usize> = peer_ids.iter().map(|id| self.shard_index(id)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        LockedShards {
+            registry: self,
+            guards: indices
+                .into_iter()
+                .map(|index| (index, self.lock_index(index)))
+                .collect(),
+        }
+    }
+
+    /// Registers a new connection. Without a requested ID the server mints
+    /// one; otherwise `policy` decides what happens if the ID is in use.
+    pub fn register_peer(
+        &self,
+        requested_id: Option<&str>,
+        tx: Sender<Outgoing>,
+        policy: PeerIdPolicy,
+    ) -> Result<Registration, RegisterError> {
+        let Some(requested_id) = requested_id else {
+            return Ok(Registration {
+                peer_id: self.insert_unique(|| nanoid!(), tx),
+                replaced: None,
+            });
+        };
+
+        let mut shard = self.lock(requested_id);
+        if !shard.peers.contains_key(requested_id) {
+            shard.peers.insert(requested_id.to_owned(), Peer::new(tx));
+            return Ok(Registration {
+                peer_id: requested_id.to_owned(),
+                replaced: None,
+            });
+        }
+
+        match policy {
+            PeerIdPolicy::Reject => Err(RegisterError::PeerIdTaken),
+            PeerIdPolicy::TakeOver => Ok(Registration {
+                peer_id: requested_id.to_owned(),
+                replaced: shard
+                    .peers
+                    .insert(requested_id.to_owned(), Peer::new(tx))
+                    .map(|peer| peer.tx),
+            }),
+            PeerIdPolicy::Suffix => {
+                drop(shard);
+                Ok(Registration {
+                    peer_id: self.insert_unique(|| format!("{requested_id}-{}", nanoid!(4)), tx),
+                    replaced: None,
+                })
+            }
+        }
+    }
+
+    fn insert_unique(&self, mut generate: impl FnMut() -> String, tx: Sender<Outgoing>) -> String {
+        loop {
+            let peer_id = generate();
+            let mut shard = self.lock(&peer_id);
+            if !shard.peers.contains_key(&peer_id) {
+                shard.peers.insert(peer_id.clone(), Peer::new(tx));
+                return peer_id;
+            }
+        }
+    }
+
+    /// Whether `tx` still owns `peer_id`, i.e. the connection has been
+    /// neither kicked nor taken over.
+    pub fn is_registered(&self, peer_id: &str, tx: &Sender<Outgoing>) -> bool {
+        self.lock(peer_id)
+            .peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.tx.same_channel(tx))
+    }
+
+    pub fn peer_tx(&self, peer_id: &str) -> Option<Sender<Outgoing>> {
+        self.lock(peer_id)
+            .peers
+            .get(peer_id)
+            .map(|peer| peer.tx.clone())
+    }
+
+    pub fn store_shared_files(&self, sender_id: &str, shared_files: SharedFiles) {
+        self.lock(sender_id)
+            .shared_files
+            .insert(sender_id.to_owned(), shared_files);
+    }
+
+    pub fn shared_files(&self, sender_id: &str) -> Option<SharedFiles> {
+        self.lock(sender_id).shared_files.get(sender_id).cloned()
+    }
+
+    /// Number of files `sender_id` announced, without cloning the manifest.
+    pub fn file_count(&self, sender_id: &str) -> Option<usize> {
+        self.lock(sender_id)
+            .shared_files
+            .get(sender_id)
+            .map(SharedFiles::file_count)
+    }
+
+    pub fn clear_shared_files(&self, sender_id: &str) {
+        self.lock(sender_id).shared_files.remove(sender_id);
+    }
+
+    pub fn recipients_of(&self, sender_id: &str) -> Vec<String> {
+        self.lock(sender_id)
+            .recipients_of
+            .get(sender_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn sender_of(&self, recipient_id: &str) -> Option<String> {
+        self.lock(recipient_id).sender_of.get(recipient_id).cloned()
+    }
+
+    pub fn session_state(&self, sender_id: &str) -> Option<TransferState> {
+        self.lock(sender_id)
+            .sessions
+            .get(sender_id)
+            .map(TransferSession::state)
+    }
+
+    pub fn is_e2ee(&self, sender_id: &str) -> bool {
+        self.lock(sender_id)
+            .sessions
+            .get(sender_id)
+            .is_some_and(TransferSession::is_e2ee)
+    }
+
+    /// Records a public key relayed between `sender_id` and `recipient_id`.
+    pub fn record_public_key(&self, sender_id: &str, recipient_id: &str, from_sender: bool) {
+        if let Some(session) = self.lock(sender_id).sessions.get_mut(sender_id) {
+            session.record_public_key(recipient_id, from_sender);
+        }
+    }
+
+    /// Recipients of `sender_id` that have not finished the key exchange.
+    pub fn awaiting_keys(&self, sender_id: &str) -> Vec<String> {
+        let shard = self.lock(sender_id);
+        match (
+            shard.sessions.get(sender_id),
+            shard.recipients_of.get(sender_id),
+        ) {
+            (Some(session), Some(recipients)) => session.awaiting_keys(recipients),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Applies `event` to the transfer session of `sender_id`. Announcing a
+    /// file is the only event that may create a session.
+    pub fn transition(
+        &self,
+        sender_id: &str,
+        event: TransferEvent,
+    ) -> Result<TransferState, InvalidTransition> {
+        let mut shard = self.lock(sender_id);
+
+        match shard.sessions.get_mut(sender_id) {
+            Some(session) => session.apply(event),
+            None if event == TransferEvent::Announce => {
+                shard
+                    .sessions
+                    .insert(sender_id.to_owned(), TransferSession::default());
+                Ok(TransferState::Announced)
+            }
+            None => Err(InvalidTransition { state: None, event }),
+        }
+    }
+
+    /// Announces a file or manifest for `sender_id`, starting a fresh session
+    /// in `mode`, end-to-end encrypted if `e2ee` is set.
+    pub fn announce(
+        &self,
+        sender_id: &str,
+        shared_files: SharedFiles,
+        mode: ShareMode,
+        e2ee: bool,
+    ) -> Result<(), InvalidTransition> {
+        let mut shard = self.lock(sender_id);
+
+        if let Some(session) = shard.sessions.get_mut(sender_id) {
+            session.apply(TransferEvent::Announce)?;
+        }
+        shard.sessions.insert(
+            sender_id.to_owned(),
+            TransferSession::new(mode).with_e2ee(e2ee),
+        );
+        shard
+            .shared_files
+            .insert(sender_id.to_owned(), shared_files);
+        Ok(())
+    }
+
+    /// Pairs `recipient_id` with `sender_id` if both are connected, the
+    /// recipient is free, and the sender's session is waiting for recipients.
+    /// A single-mode share takes one recipient; a broadcast share takes up to
+    /// `max_recipients`. If the same pair was cut off mid-transfer recently,
+    /// the recipient's progress is restored and returned as a resume point.
+    pub fn pair(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+        max_recipients: usize,
+    ) -> Result<Pairing, PairError> {
+        let mut shards = self.lock_many(&[sender_id, recipient_id]);
+
+        let sender_tx = shards
+            .of(sender_id)
+            .peers
+            .get(sender_id)
+            .map(|peer| peer.tx.clone())
+            .ok_or(PairError::SenderNotConnected)?;
+        if !shards.of(recipient_id).peers.contains_key(recipient_id) {
+            return Err(PairError::RecipientNotConnected);
+        }
+        if let Some(current_sender) = shards.of(recipient_id).sender_of.get(recipient_id) {
+            return Err(PairError::RecipientBusy {
+                current_sender: current_sender.clone(),
+            });
+        }
+
+        let sender_shard = shards.of(sender_id);
+        let current_recipients = sender_shard
+            .recipients_of
+            .get(sender_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let Some(session) = sender_shard.sessions.get_mut(sender_id) else {
+            return Err(PairError::InvalidState(InvalidTransition {
+                state: None,
+                event: TransferEvent::Pair,
+            }));
+        };
+
+        let event = match (current_recipients.first(), session.mode()) {
+            (None, _) => TransferEvent::Pair,
+            (Some(current_recipient), ShareMode::Single) => {
+                return Err(PairError::SenderBusy {
+                    current_recipient: current_recipient.clone(),
+                });
+            }
+            (Some(_), ShareMode::Broadcast) if current_recipients.len() >= max_recipients => {
+                return Err(PairError::ShareFull {
+                    limit: max_recipients,
+                });
+            }
+            (Some(_), ShareMode::Broadcast) => TransferEvent::Join,
+        };
+        session.apply(event).map_err(PairError::InvalidState)?;
+
+        let resume_from = sender_shard.unpark(sender_id, recipient_id, self.resume_window);
+        if let (Some(resume_from), Some(session)) =
+            (resume_from, sender_shard.sessions.get_mut(sender_id))
+        {
+            session.restore_resume_point(recipient_id, resume_from);
+        }
+
+        sender_shard
+            .recipients_of
+            .entry(sender_id.to_owned())
+            .or_default()
+            .push(recipient_id.to_owned());
+        shards
+            .of(recipient_id)
+            .sender_of
+            .insert(recipient_id.to_owned(), sender_id.to_owned());
+
+        Ok(Pairing {
+            sender_tx,
+            resume_from,
+        })
+    }
+
+    /// Removes every pairing of `sender_id`, returning the recipients it had.
+    pub fn unpair(&self, sender_id: &str) -> Result<Vec<String>, UnpairError> {
+        self.detach_sender(sender_id, true)
+    }
+
+    /// Removes `recipient_id` from the share of `sender_id`. The session goes
+    /// back to `Announced` once its last recipient is gone. Returns an ack
+    /// the sender can now receive, if the leaving recipient was holding one.
+    pub fn unpair_recipient(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+    ) -> Result<Option<Outgoing>, UnpairError> {
+        self.leave(sender_id, recipient_id, OnLastRecipient::Unpair)
+    }
+
+    /// Cancels the transfer on behalf of `recipient_id`. For the last
+    /// recipient the whole session is cancelled but stays paired so it can
+    /// be restarted; in a broadcast share with others still paired, only
+    /// this recipient leaves.
+    pub fn cancel_recipient(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+    ) -> Result<Option<Outgoing>, UnpairError> {
+        self.leave(sender_id, recipient_id, OnLastRecipient::Cancel)
+    }
+
+    /// Detaches every recipient of `sender_id` whatever the session state,
+    /// sending the session back to `Announced`. Nothing is parked, so the
+    /// pairs cannot resume.
+    pub fn evict_recipients(&self, sender_id: &str) -> Result<Vec<String>, UnpairError> {
+        self.detach_sender(sender_id, false)
+    }
+
+    /// Detaches `recipient_id` from the share of `sender_id` whatever the
+    /// session state, without parking its progress.
+    pub fn evict_recipient(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+    ) -> Result<Option<Outgoing>, UnpairError> {
+        self.leave(sender_id, recipient_id, OnLastRecipient::Evict)
+    }
+
+    /// Forgets the share of `sender_id`: its file metadata, its session and
+    /// any parked transfers, then detaches its recipients. The sender stays
+    /// connected. Returns the recipients it had, or `None` if it had not
+    /// announced anything.
+    pub fn purge_share(&self, sender_id: &str) -> Option<Vec<String>> {
+        {
+            // Dropping the session first means no new recipient can pair
+            // while the current ones are detached.
+            let mut shard = self.lock(sender_id);
+            let shared_files = shard.shared_files.remove(sender_id);
+            let session = shard.sessions.remove(sender_id);
+            shard.parked.remove(sender_id);
+            if shared_files.is_none() && session.is_none() {
+                return None;
+            }
+        }
+        Some(self.detach_sender(sender_id, false).unwrap_or_default())
+    }
+
+    /// Records a `fileTransferAck` from `recipient_id`. In a broadcast share
+    /// the ack is held until every recipient has acknowledged the same chunk.
+    pub fn record_ack(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+        chunk_index: u32,
+        chunk_data_size: u32,
+        status: &str,
+        message: Outgoing,
+    ) -> Result<AckOutcome, UnpairError> {
+        let mut shard = self.lock(sender_id);
+        let shard = &mut *shard;
+
+        let recipients = shard
+            .recipients_of
+            .get(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+        if !recipients.iter().any(|r| r == recipient_id) {
+            return Err(UnpairError::RecipientMismatch {
+                current_recipient: recipients.join(", "),
+            });
+        }
+        let session = shard
+            .sessions
+            .get_mut(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+
+        if status == "acknowledged" {
+            session.record_progress(recipient_id, chunk_index, chunk_data_size);
+        }
+        Ok(session.record_ack(recipient_id, chunk_index, status, message, recipients))
+    }
+
+    /// Charges every recipient of `sender_id` for a binary frame of `len`
+    /// bytes, or blocks it if one of them has no room left.
+    pub fn admit_chunk(&self, sender_id: &str, len: u32) -> Admission {
+        let mut shard = self.lock(sender_id);
+        let shard = &mut *shard;
+
+        match (
+            shard.sessions.get_mut(sender_id),
+            shard.recipients_of.get(sender_id),
+        ) {
+            (Some(session), Some(recipients)) => {
+                session.admit_chunk(len, recipients, self.max_queued_bytes)
+            }
+            _ => Admission::Forward { pause: None },
+        }
+    }
+
+    /// Adds `credits` to the window of `recipient_id`. Returns the flow
+    /// status if the sender may resume.
+    pub fn grant_credits(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+        credits: u32,
+    ) -> Result<Option<FlowStatus>, UnpairError> {
+        self.with_recipient_session(sender_id, recipient_id, |session, recipients| {
+            session.grant_credits(recipient_id, credits, recipients, self.max_queued_bytes)
+        })
+    }
+
+    /// Frees the oldest chunk queued for `recipient_id` once it has been
+    /// acknowledged. Returns the flow status if the sender may resume.
+    pub fn release_chunk(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+    ) -> Result<Option<FlowStatus>, UnpairError> {
+        self.with_recipient_session(sender_id, recipient_id, |session, recipients| {
+            session.release_chunk(recipient_id, recipients, self.max_queued_bytes)
+        })
+    }
+
+    // Runs `f` on the session of `sender_id` if `recipient_id` is one of its
+    // recipients.
+    fn with_recipient_session<T>(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+        f: impl FnOnce(&mut TransferSession, &[String]) -> T,
+    ) -> Result<T, UnpairError> {
+        let mut shard = self.lock(sender_id);
+        let shard = &mut *shard;
+
+        let recipients = shard
+            .recipients_of
+            .get(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+        if !recipients.iter().any(|r| r == recipient_id) {
+            return Err(UnpairError::RecipientMismatch {
+                current_recipient: recipients.join(", "),
+            });
+        }
+        let session = shard
+            .sessions
+            .get_mut(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+
+        Ok(f(session, recipients))
+    }
+
+    /// Drops the recipients of a broadcast share that still have not
+    /// acknowledged `chunk_index`.
+    pub fn drop_lagging(&self, sender_id: &str, chunk_index: u32) -> DroppedRecipients {
+        let lagging = {
+            let shard = self.lock(sender_id);
+            match (
+                shard.sessions.get(sender_id),
+                shard.recipients_of.get(sender_id),
+            ) {
+                (Some(session), Some(recipients)) => {
+                    session.lagging_recipients(chunk_index, recipients)
+                }
+                _ => Vec::new(),
+            }
+        };
+
+        let mut dropped = DroppedRecipients::default();
+        for recipient_id in lagging {
+            if let Ok(released_ack) = self.leave(sender_id, &recipient_id, OnLastRecipient::Reset) {
+                dropped.released_ack = released_ack.or(dropped.released_ack);
+                dropped.recipient_ids.push(recipient_id);
+            }
+        }
+        dropped
+    }
+
+    fn detach_sender(&self, sender_id: &str, validate: bool) -> Result<Vec<String>, UnpairError> {
+        loop {
+            let recipient_ids = self.recipients_of(sender_id);
+            if recipient_ids.is_empty() {
+                return Err(UnpairError::NotPaired);
+            }
+
+            let mut peer_ids: Vec<&str> = recipient_ids.iter().map(String::as_str).collect();
+            peer_ids.push(sender_id);
+            let mut shards = self.lock_many(&peer_ids);
+
+            // The pairings changed between the lookup and the lock; retry.
+            if shards.of(sender_id).recipients_of.get(sender_id) != Some(&recipient_ids) {
+                continue;
+            }
+
+            if let Some(session) = shards.of(sender_id).sessions.get_mut(sender_id) {
+                if validate {
+                    session
+                        .apply(TransferEvent::Unpair)
+                        .map_err(UnpairError::InvalidState)?;
+                } else {
+                    session.reset();
+                }
+                for recipient_id in &recipient_ids {
+                    session.forget_keys(recipient_id);
+                }
+            }
+
+            shards.of(sender_id).recipients_of.remove(sender_id);
+            for recipient_id in &recipient_ids {
+                shards.of(recipient_id).sender_of.remove(recipient_id);
+            }
+            return Ok(recipient_ids);
+        }
+    }
+
+    fn leave(
+        &self,
+        sender_id: &str,
+        recipient_id: &str,
+        on_last: OnLastRecipient,
+    ) -> Result<Option<Outgoing>, UnpairError> {
+        let mut shards = self.lock_many(&[sender_id, recipient_id]);
+        let sender_shard = shards.of(sender_id);
+
+        let recipients = sender_shard
+            .recipients_of
+            .get(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+        let Some(position) = recipients.iter().position(|r| r == recipient_id) else {
+            return Err(UnpairError::RecipientMismatch {
+                current_recipient: recipients.join(", "),
+            });
+        };
+        let is_last = recipients.len() == 1;
+        if on_last == OnLastRecipient::Reset {
+            sender_shard.park(sender_id, recipient_id);
+        }
+
+        let recipients = sender_shard
+            .recipients_of
+            .get_mut(sender_id)
+            .ok_or(UnpairError::NotPaired)?;
+        let session = sender_shard.sessions.get_mut(sender_id);
+
+        if is_last {
+            match (session, on_last) {
+                (Some(session), OnLastRecipient::Unpair) => {
+                    session
+                        .apply(TransferEvent::Unpair)
+                        .map_err(UnpairError::InvalidState)?;
+                    session.forget_keys(recipient_id);
+                }
+                (Some(session), OnLastRecipient::Cancel) => {
+                    session
+                        .apply(TransferEvent::Cancel)
+                        .map_err(UnpairError::InvalidState)?;
+                    return Ok(None);
+                }
+                (Some(session), OnLastRecipient::Reset | OnLastRecipient::Evict) => {
+                    session.reset();
+                    session.forget_keys(recipient_id);
+                }
+                (None, _) => {}
+            }
+            sender_shard.recipients_of.remove(sender_id);
+            shards.of(recipient_id).sender_of.remove(recipient_id);
+            return Ok(None);
+        }
+
+        recipients.remove(position);
+        let released_ack =
+            session.and_then(|session| session.remove_recipient(recipient_id, recipients));
+        shards.of(recipient_id).sender_of.remove(recipient_id);
+        Ok(released_ack)
+    }
+
+    /// Every connected peer, in no particular order. Shards are visited one
+    /// at a time, so the list is consistent per peer but not across peers.
+    pub fn peer_snapshots(&self) -> Vec<PeerSnapshot> {
+        let mut snapshots = Vec::new();
+        for index in 0..self.shards.len() {
+            let shard = self.lock_index(index);
+            snapshots.extend(shard.peers.iter().map(|(peer_id, peer)| {
+                let sender_id = shard.sender_of.get(peer_id).cloned();
+                let role = if shard.sessions.contains_key(peer_id) {
+                    PeerRole::Sender
+                } else if sender_id.is_some() {
+                    PeerRole::Recipient
+                } else {
+                    PeerRole::Idle
+                };
+                PeerSnapshot {
+                    peer_id: peer_id.clone(),
+                    role,
+                    connected_secs: peer.connected_at.elapsed().as_secs(),
+                    sender_id,
+                    recipient_ids: shard
+                        .recipients_of
+                        .get(peer_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                }
+            }));
+        }
+        snapshots
+    }
+
+    /// Every transfer session, in no particular order.
+    pub fn session_snapshots(&self) -> Vec<SessionSnapshot> {
+        let mut snapshots = Vec::new();
+        for index in 0..self.shards.len() {
+            let shard = self.lock_index(index);
+            snapshots.extend(shard.sessions.iter().map(|(sender_id, session)| {
+                let files = shard.shared_files.get(sender_id).cloned();
+                let recipients = shard
+                    .recipients_of
+                    .get(sender_id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|recipient_id| {
+                        let acknowledged = session.acknowledged(recipient_id);
+                        RecipientProgress {
+                            recipient_id: recipient_id.clone(),
+                            chunks_acknowledged: acknowledged.chunk_index,
+                            bytes_acknowledged: acknowledged.byte_offset,
+                            completed: session.has_completed(recipient_id),
+                        }
+                    })
+                    .collect();
+                SessionSnapshot {
+                    sender_id: sender_id.clone(),
+                    state: session.state(),
+                    mode: session.mode(),
+                    e2ee: session.is_e2ee(),
+                    total_size: files.as_ref().map_or(0, SharedFiles::total_size),
+                    files,
+                    bytes_relayed: session.bytes_relayed(),
+                    recipients,
+                    stored: false,
+                }
+            }));
+        }
+        snapshots
+    }
+
+    /// Removes every trace of `peer_id`: its channel, its file metadata, its
+    /// transfer session and any pairing it takes part in, on either side.
+    /// Pairings cut off mid-transfer are parked for the resume window.
+    ///
+    /// Nothing is removed if `peer_id` is now owned by a connection other
+    /// than `tx`, which happens after a take-over. The channel is dropped
+    /// first, so no new pairing involving the peer can be created while the
+    /// existing ones are torn down.
+    pub fn remove_peer(&self, peer_id: &str, tx: &Sender<Outgoing>) -> Option<RemovedPeer> {
+        {
+            let mut shard = self.lock(peer_id);
+            match shard.peers.get(peer_id) {
+                Some(current) if current.tx.same_channel(tx) => {}
+                _ => return None,
+            }
+            shard.peers.remove(peer_id);
+            for recipient_id in shard
+                .recipients_of
+                .get(peer_id)
+                .cloned()
+                .unwrap_or_default()
+            {
+                shard.park(peer_id, &recipient_id);
+            }
+            shard.shared_files.remove(peer_id);
+            shard.sessions.remove(peer_id);
+        }
+
+        let mut removed = RemovedPeer {
+            recipient_ids: self.detach_sender(peer_id, false).unwrap_or_default(),
+            ..Default::default()
+        };
+
+        while let Some(sender_id) = self.sender_of(peer_id) {
+            if let Ok(released_ack) = self.leave(&sender_id, peer_id, OnLastRecipient::Reset) {
+                removed.sender_id = Some(sender_id);
+                removed.released_ack = released_ack;
+                break;
+            }
+        }
+
+        Some(removed)
+    }
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,15 +1,45 @@
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    routing::{delete, get, post, put},
+};
 
-use super::{handlers, state::RelayState};
+use crate::config::CONFIG;
+
+use super::{handlers, spool::spawn_spool_janitor, state::RelayState};
 
 pub fn relay_router() -> Router {
+    let state = RelayState::new();
+    if let Some(spool) = &state.spool {
+        spawn_spool_janitor(spool.clone(), CONFIG.spool_janitor_interval);
+    }
+
     Router::new()
         .route("/", get(handlers::handle_relay_ws_upgrade))
         .route(
             "/file-meta/{sender_id}",
             get(handlers::handle_get_file_metadata),
         )
-        .route("/debug/state", get(handlers::handle_debug_state))
+        .route(
+            "/pairing-codes/{code}/redeem",
+            post(handlers::handle_redeem_pairing_code),
+        )
+        .route("/upload/{name}", put(handlers::handle_upload))
+        .route("/download/{sender_id}", get(handlers::handle_download))
+        .route("/admin/peers", get(handlers::handle_admin_peers))
+        .route("/admin/sessions", get(handlers::handle_admin_sessions))
+        .route(
+            "/admin/peers/{peer_id}",
+            delete(handlers::handle_admin_kick_peer),
+        )
+        .route(
+            "/admin/sessions/{sender_id}",
+            delete(handlers::handle_admin_purge_share),
+        )
+        .route(
+            "/admin/sessions/{sender_id}/pairings",
+            delete(handlers::handle_admin_cancel_pairing),
+        )
         .route("/ping", get(handlers::handle_ping))
-        .with_state(RelayState::new())
+        .route("/asyncapi.json", get(handlers::handle_get_asyncapi))
+        .with_state(state)
 }
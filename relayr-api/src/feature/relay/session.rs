@@ -0,0 +1,548 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::feature::relay::{
+    codec::Outgoing,
+    flow::{Admission, FlowStatus, FlowWindow},
+};
+
+/// How many recipients a share accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ShareMode {
+    /// One recipient per share.
+    #[default]
+    Single,
+    /// Several recipients pair before the transfer starts and every chunk is
+    /// fanned out to all of them.
+    Broadcast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferState {
+    Announced,
+    Paired,
+    Transferring,
+    Completed,
+    Cancelled,
+}
+
+impl TransferState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Announced => "announced",
+            Self::Paired => "paired",
+            Self::Transferring => "transferring",
+            Self::Completed => "completed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// The transition table. `None` means `event` is not allowed in `self`.
+    pub fn next(self, event: TransferEvent) -> Option<TransferState> {
+        use TransferEvent as E;
+        use TransferState as S;
+
+        match (self, event) {
+            (S::Announced, E::Announce) => Some(S::Announced),
+            (S::Announced, E::Pair) => Some(S::Paired),
+            (S::Paired, E::Join) => Some(S::Paired),
+            (S::Paired | S::Completed | S::Cancelled, E::Unpair) => Some(S::Announced),
+            (S::Paired | S::Transferring, E::SenderAck) => Some(self),
+            (S::Paired | S::Transferring, E::Chunk) => Some(S::Transferring),
+            (S::Transferring | S::Completed, E::Ack) => Some(self),
+            (S::Transferring, E::End) => Some(S::Completed),
+            (S::Paired | S::Transferring, E::Cancel) => Some(S::Cancelled),
+            // A recipient can still reject a completed transfer, e.g. when its
+            // own checks of the file fail, and the sender then starts over.
+            (S::Transferring | S::Completed | S::Cancelled, E::Restart) => Some(S::Paired),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransferState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Something a peer asks the relay to do with a transfer session. Several
+/// incoming message types can map to the same event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEvent {
+    /// `fileMeta`
+    Announce,
+    /// `recipientReady`
+    Pair,
+    /// `recipientReady` for a broadcast share that already has a recipient
+    Join,
+    /// `cancelSenderReady`, `cancelRecipientReady`
+    Unpair,
+    /// `senderAck`
+    SenderAck,
+    /// `fileChunk` and binary frames
+    Chunk,
+    /// `fileTransferAck`
+    Ack,
+    /// `fileEnd`
+    End,
+    /// `cancelSenderTransfer`, `cancelRecipientTransfer`
+    Cancel,
+    /// `restartTransfer`
+    Restart,
+}
+
+impl TransferEvent {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Announce => "announce a file",
+            Self::Pair => "pair a recipient",
+            Self::Join => "add a recipient",
+            Self::Unpair => "cancel the pairing",
+            Self::SenderAck => "acknowledge the recipient",
+            Self::Chunk => "send a file chunk",
+            Self::Ack => "acknowledge a chunk",
+            Self::End => "end the file",
+            Self::Cancel => "cancel the transfer",
+            Self::Restart => "restart the transfer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub state: Option<TransferState>,
+    pub event: TransferEvent,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            Some(state) => write!(
+                f,
+                "cannot {} while the transfer session is `{}`",
+                self.event.describe(),
+                state
+            ),
+            None => write!(
+                f,
+                "cannot {} before a file has been announced",
+                self.event.describe()
+            ),
+        }
+    }
+}
+
+/// Lifecycle of one sender's share, from `fileMeta` to completion or
+/// cancellation.
+#[derive(Debug, Clone)]
+pub struct TransferSession {
+    state: TransferState,
+    mode: ShareMode,
+    // Highest chunk index acknowledged by each recipient.
+    acked: HashMap<String, u32>,
+    // Recipients that reported the whole file as received.
+    completed: HashSet<String>,
+    held_ack: Option<HeldAck>,
+    // Contiguous prefix of chunks each recipient has acknowledged.
+    progress: HashMap<String, AckProgress>,
+    flow: HashMap<String, FlowWindow>,
+    // Set once the sender has been told to pause, until it is told to resume.
+    paused: bool,
+    // Announced with `e2ee`: chunks must be encrypted envelopes, and only
+    // flow once every recipient has swapped keys with the sender.
+    e2ee: bool,
+    // Recipients the sender has sent its public key to, and recipients that
+    // have sent theirs back.
+    keys_to_recipient: HashSet<String>,
+    keys_from_recipient: HashSet<String>,
+    // Binary payload bytes relayed for this share, counted once per frame
+    // however many recipients it fanned out to. Survives restarts.
+    bytes_relayed: u64,
+}
+
+/// Where a transfer picks up again after a reconnect: the first chunk the
+/// recipient has not acknowledged and the number of bytes before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResumePoint {
+    pub chunk_index: u32,
+    pub byte_offset: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AckProgress {
+    resume_from: ResumePoint,
+    // Acks that arrived ahead of a gap, by chunk index, with their sizes.
+    ahead: BTreeMap<u32, u32>,
+}
+
+impl AckProgress {
+    fn record(&mut self, chunk_index: u32, chunk_data_size: u32) {
+        if chunk_index < self.resume_from.chunk_index {
+            return;
+        }
+        self.ahead.insert(chunk_index, chunk_data_size);
+        while let Some(size) = self.ahead.remove(&self.resume_from.chunk_index) {
+            self.resume_from.chunk_index += 1;
+            self.resume_from.byte_offset += u64::from(size);
+        }
+    }
+}
+
+/// An ack that reached the relay before every recipient had acknowledged the
+/// same chunk. It is forwarded to the sender once the slowest one catches up.
+#[derive(Debug, Clone)]
+struct HeldAck {
+    chunk_index: u32,
+    completed: bool,
+    message: Outgoing,
+}
+
+/// What the relay should do with an ack it just recorded.
+#[derive(Debug)]
+pub enum AckOutcome {
+    /// Every recipient is there; forward this message to the sender.
+    Forward(Outgoing),
+    /// Other recipients are still behind. `first` is set for the first ack
+    /// held for this chunk.
+    Hold { first: bool },
+}
+
+impl TransferSession {
+    pub fn new(mode: ShareMode) -> Self {
+        Self {
+            state: TransferState::Announced,
+            mode,
+            acked: HashMap::new(),
+            completed: HashSet::new(),
+            held_ack: None,
+            progress: HashMap::new(),
+            flow: HashMap::new(),
+            paused: false,
+            e2ee: false,
+            keys_to_recipient: HashSet::new(),
+            keys_from_recipient: HashSet::new(),
+            bytes_relayed: 0,
+        }
+    }
+
+    pub fn with_e2ee(mut self, e2ee: bool) -> Self {
+        self.e2ee = e2ee;
+        self
+    }
+
+    pub fn is_e2ee(&self) -> bool {
+        self.e2ee
+    }
+
+    /// Notes that a public key went from the sender to `recipient_id`, or
+    /// the other way round.
+    pub fn record_public_key(&mut self, recipient_id: &str, from_sender: bool) {
+        let keys = if from_sender {
+            &mut self.keys_to_recipient
+        } else {
+            &mut self.keys_from_recipient
+        };
+        keys.insert(recipient_id.to_owned());
+    }
+
+    /// Drops the keys swapped with `recipient_id` once it is no longer
+    /// paired; a new pairing has to exchange fresh ones.
+    pub fn forget_keys(&mut self, recipient_id: &str) {
+        self.keys_to_recipient.remove(recipient_id);
+        self.keys_from_recipient.remove(recipient_id);
+    }
+
+    /// Recipients that have not swapped keys with the sender both ways yet.
+    pub fn awaiting_keys(&self, recipients: &[String]) -> Vec<String> {
+        recipients
+            .iter()
+            .filter(|recipient_id| {
+                !self.keys_to_recipient.contains(*recipient_id)
+                    || !self.keys_from_recipient.contains(*recipient_id)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn state(&self) -> TransferState {
+        self.state
+    }
+
+    pub fn mode(&self) -> ShareMode {
+        self.mode
+    }
+
+    pub fn apply(&mut self, event: TransferEvent) -> Result<TransferState, InvalidTransition> {
+        let next = self.state.next(event).ok_or(InvalidTransition {
+            state: Some(self.state),
+            event,
+        })?;
+        if event == TransferEvent::Restart {
+            self.clear_acks();
+        }
+        self.state = next;
+        Ok(next)
+    }
+
+    /// Drops back to `Announced` when the last recipient goes away, whatever
+    /// the current state.
+    pub fn reset(&mut self) {
+        self.state = TransferState::Announced;
+        self.clear_acks();
+    }
+
+    fn clear_acks(&mut self) {
+        self.acked.clear();
+        self.completed.clear();
+        self.held_ack = None;
+        self.progress.clear();
+        self.flow.clear();
+        self.paused = false;
+    }
+
+    /// Moves the resume point of `recipient_id` past `chunk_index` once every
+    /// chunk before it has been acknowledged too.
+    pub fn record_progress(&mut self, recipient_id: &str, chunk_index: u32, chunk_data_size: u32) {
+        self.progress
+            .entry(recipient_id.to_owned())
+            .or_default()
+            .record(chunk_index, chunk_data_size);
+    }
+
+    /// Contiguous prefix of chunks `recipient_id` has acknowledged so far.
+    pub fn acknowledged(&self, recipient_id: &str) -> ResumePoint {
+        self.progress
+            .get(recipient_id)
+            .map(|progress| progress.resume_from)
+            .unwrap_or_default()
+    }
+
+    pub fn has_completed(&self, recipient_id: &str) -> bool {
+        self.completed.contains(recipient_id)
+    }
+
+    pub fn bytes_relayed(&self) -> u64 {
+        self.bytes_relayed
+    }
+
+    pub fn resume_point(&self, recipient_id: &str) -> Option<ResumePoint> {
+        self.progress
+            .get(recipient_id)
+            .map(|progress| progress.resume_from)
+            .filter(|resume_from| resume_from.chunk_index > 0)
+    }
+
+    /// Carries a recipient's progress over from a transfer that was cut off.
+    pub fn restore_resume_point(&mut self, recipient_id: &str, resume_from: ResumePoint) {
+        self.progress.insert(
+            recipient_id.to_owned(),
+            AckProgress {
+                resume_from,
+                ahead: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Records an ack from `recipient_id` and decides whether `message`
+    /// should reach the sender now. `recipients` is everyone currently paired
+    /// with the sender.
+    pub fn record_ack(
+        &mut self,
+        recipient_id: &str,
+        chunk_index: u32,
+        status: &str,
+        message: Outgoing,
+        recipients: &[String],
+    ) -> AckOutcome {
+        match status {
+            "acknowledged" => {
+                let acked = self.acked.entry(recipient_id.to_owned()).or_default();
+                *acked = (*acked).max(chunk_index);
+            }
+            "completed" => {
+                self.completed.insert(recipient_id.to_owned());
+            }
+            // Errors and unknown statuses are the sender's business.
+            _ => return AckOutcome::Forward(message),
+        }
+
+        let completed = status == "completed";
+        if self.is_satisfied(chunk_index, completed, recipients) {
+            self.held_ack = None;
+            return AckOutcome::Forward(message);
+        }
+
+        let first = !matches!(
+            &self.held_ack,
+            Some(held) if held.chunk_index == chunk_index && held.completed == completed
+        );
+        if first {
+            self.held_ack = Some(HeldAck {
+                chunk_index,
+                completed,
+                message,
+            });
+        }
+        AckOutcome::Hold { first }
+    }
+
+    /// Forgets a recipient that left the share and releases the held ack if
+    /// the remaining recipients have all caught up.
+    pub fn remove_recipient(
+        &mut self,
+        recipient_id: &str,
+        recipients: &[String],
+    ) -> Option<Outgoing> {
+        self.acked.remove(recipient_id);
+        self.completed.remove(recipient_id);
+        self.progress.remove(recipient_id);
+        self.flow.remove(recipient_id);
+        self.forget_keys(recipient_id);
+
+        let held = self.held_ack.as_ref()?;
+        if recipients.is_empty() || !self.is_satisfied(held.chunk_index, held.completed, recipients)
+        {
+            return None;
+        }
+        self.held_ack.take().map(|held| held.message)
+    }
+
+    /// Recipients that have not acknowledged the held ack's chunk yet, or an
+    /// empty list if `chunk_index` is no longer the one being held.
+    pub fn lagging_recipients(&self, chunk_index: u32, recipients: &[String]) -> Vec<String> {
+        match &self.held_ack {
+            Some(held) if held.chunk_index == chunk_index => recipients
+                .iter()
+                .filter(|recipient_id| !self.has_reached(recipient_id, chunk_index, held.completed))
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks every recipient's window before a binary frame of `len` bytes
+    /// is relayed, and charges them for it if all have room.
+    pub fn admit_chunk(
+        &mut self,
+        len: u32,
+        recipients: &[String],
+        max_queued_bytes: u64,
+    ) -> Admission {
+        let admitted = recipients.iter().all(|recipient_id| {
+            self.flow
+                .get(recipient_id)
+                .is_none_or(|window| window.admits(len, max_queued_bytes))
+        });
+        if !admitted {
+            self.paused = true;
+            return Admission::Blocked(self.flow_status(recipients));
+        }
+
+        for recipient_id in recipients {
+            self.flow
+                .entry(recipient_id.clone())
+                .or_default()
+                .record_sent(len);
+        }
+        self.bytes_relayed += u64::from(len);
+        if !self.paused && !self.windows_open(recipients, max_queued_bytes) {
+            self.paused = true;
+            return Admission::Forward {
+                pause: Some(self.flow_status(recipients)),
+            };
+        }
+        Admission::Forward { pause: None }
+    }
+
+    /// Adds credits to the window of `recipient_id`. Returns the new status
+    /// if the sender was paused and may now resume.
+    pub fn grant_credits(
+        &mut self,
+        recipient_id: &str,
+        credits: u32,
+        recipients: &[String],
+        max_queued_bytes: u64,
+    ) -> Option<FlowStatus> {
+        self.flow
+            .entry(recipient_id.to_owned())
+            .or_default()
+            .grant(credits);
+        self.resume_if_open(recipients, max_queued_bytes)
+    }
+
+    /// Frees the oldest chunk queued for `recipient_id`. Returns the new
+    /// status if the sender was paused and may now resume.
+    pub fn release_chunk(
+        &mut self,
+        recipient_id: &str,
+        recipients: &[String],
+        max_queued_bytes: u64,
+    ) -> Option<FlowStatus> {
+        if let Some(window) = self.flow.get_mut(recipient_id) {
+            window.record_acked();
+        }
+        self.resume_if_open(recipients, max_queued_bytes)
+    }
+
+    fn resume_if_open(
+        &mut self,
+        recipients: &[String],
+        max_queued_bytes: u64,
+    ) -> Option<FlowStatus> {
+        if !self.paused || !self.windows_open(recipients, max_queued_bytes) {
+            return None;
+        }
+        self.paused = false;
+        Some(self.flow_status(recipients))
+    }
+
+    fn windows_open(&self, recipients: &[String], max_queued_bytes: u64) -> bool {
+        recipients.iter().all(|recipient_id| {
+            self.flow
+                .get(recipient_id)
+                .is_none_or(|window| window.is_open(max_queued_bytes))
+        })
+    }
+
+    fn flow_status(&self, recipients: &[String]) -> FlowStatus {
+        let windows = || recipients.iter().filter_map(|r| self.flow.get(r));
+        FlowStatus {
+            credits: windows().filter_map(FlowWindow::credits).min(),
+            queued_bytes: windows()
+                .map(FlowWindow::queued_bytes)
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn is_satisfied(&self, chunk_index: u32, completed: bool, recipients: &[String]) -> bool {
+        recipients
+            .iter()
+            .all(|recipient_id| self.has_reached(recipient_id, chunk_index, completed))
+    }
+
+    fn has_reached(&self, recipient_id: &str, chunk_index: u32, completed: bool) -> bool {
+        if completed {
+            self.completed.contains(recipient_id)
+        } else {
+            self.acked
+                .get(recipient_id)
+                .is_some_and(|acked| *acked >= chunk_index)
+        }
+    }
+}
+
+impl Default for TransferSession {
+    fn default() -> Self {
+        Self::new(ShareMode::default())
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use nanoid::nanoid;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::interval,
+};
+
+use crate::feature::relay::types::SharedFiles;
+
+/// Stands in for the recipient in the acks the relay sends a spooling sender.
+pub const SPOOL_PEER_ID: &str = "spool";
+
+/// What the sender said about a chunk in its `fileChunk` message.
+#[derive(Debug, Clone)]
+pub struct ChunkInfo {
+    pub file_index: Option<u32>,
+    pub file_name: String,
+    pub total_size: u64,
+    pub total_chunks: u16,
+    pub uploaded_size: u64,
+    pub chunk_index: u32,
+    pub chunk_data_size: u32,
+    pub chunk_sha256: Option<String>,
+    pub sender_transfer_progress: u8,
+}
+
+/// What the sender said in its `fileEnd` message.
+#[derive(Debug, Clone)]
+pub struct FileEndInfo {
+    pub file_index: Option<u32>,
+    pub file_name: String,
+    pub total_size: u64,
+    pub total_chunks: u16,
+    pub uploaded_size: u64,
+    pub last_chunk_index: u32,
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpooledChunk {
+    pub info: ChunkInfo,
+    offset: u64,
+    len: u32,
+}
+
+impl SpooledChunk {
+    /// Bytes actually stored for the chunk.
+    pub fn data_len(&self) -> u32 {
+        self.len
+    }
+}
+
+/// One recorded message of the upload, replayed in order to recipients.
+#[derive(Debug, Clone)]
+pub enum SpooledFrame {
+    Chunk(SpooledChunk),
+    End(FileEndInfo),
+}
+
+#[derive(Debug)]
+pub enum SpoolError {
+    QuotaExceeded {
+        quota: u64,
+    },
+    /// The stored share was uploaded by a connection that has since left.
+    LeftBehind,
+    NotUploading,
+    MissingChunkInfo,
+    Io(io::Error),
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QuotaExceeded { quota } => {
+                write!(f, "share exceeds the spool quota of {quota} bytes")
+            }
+            Self::LeftBehind => write!(
+                f,
+                "a stored share from an earlier connection cannot be replaced"
+            ),
+            Self::NotUploading => write!(f, "no spool upload in progress"),
+            Self::MissingChunkInfo => {
+                write!(f, "binary frame was not preceded by a `fileChunk` message")
+            }
+            Self::Io(err) => write!(f, "spool i/o error: {err}"),
+        }
+    }
+}
+
+impl From<io::Error> for SpoolError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Flow control from a recipient of a replayed share, routed to the replay
+/// serving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayEvent {
+    /// The recipient acknowledged the oldest chunk it had queued.
+    Acked,
+    Credits(u32),
+}
+
+// (sender, recipient) of a replay in progress.
+type ReplayKey = (String, String);
+// ID of the replay serving a pair, and where its events go.
+type Replay = (String, UnboundedSender<ReplayEvent>);
+
+/// Flow control for one replay, from `Spool::begin_replay`.
+#[derive(Debug)]
+pub struct ReplayEvents {
+    key: ReplayKey,
+    replay_id: String,
+    events_rx: UnboundedReceiver<ReplayEvent>,
+}
+
+impl ReplayEvents {
+    /// Waits for the next event. `None` once a newer replay took over.
+    pub async fn recv(&mut self) -> Option<ReplayEvent> {
+        self.events_rx.recv().await
+    }
+
+    pub fn try_recv(&mut self) -> Option<ReplayEvent> {
+        self.events_rx.try_recv().ok()
+    }
+}
+
+#[derive(Debug)]
+struct SpooledShare {
+    shared_files: SharedFiles,
+    // Random name, so peer IDs never end up in a path.
+    data_path: PathBuf,
+    frames: Vec<SpooledFrame>,
+    pending_chunk: Option<ChunkInfo>,
+    bytes: u64,
+    complete: bool,
+    // Set once the uploading connection is gone, after which the share is
+    // only removed by its TTL or an admin.
+    left_behind: bool,
+    created_at: Instant,
+}
+
+/// Store-and-forward storage. A sender uploads a share once; its chunks are
+/// appended to a file in the spool directory and replayed to recipients who
+/// connect later, until the share expires.
+///
+/// The index lives in memory, so a restart forgets every share and the
+/// janitor removes the leftover files.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    share_quota: u64,
+    ttl: Duration,
+    shares: Mutex<HashMap<String, SpooledShare>>,
+    replays: Mutex<HashMap<ReplayKey, Replay>>,
+}
+
+impl Spool {
+    pub fn new(dir: PathBuf, share_quota: u64, ttl: Duration) -> Self {
+        Self {
+            dir,
+            share_quota,
+            ttl,
+            shares: Mutex::new(HashMap::new()),
+            replays: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SpooledShare>> {
+        self.shares
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Starts a new upload for `sender_id`, replacing any earlier share
+    /// unless its uploader has left it behind.
+    pub async fn begin_upload(
+        &self,
+        sender_id: &str,
+        shared_files: SharedFiles,
+    ) -> Result<(), SpoolError> {
+        let announced_size = match &shared_files {
+            SharedFiles::Single(file) => file.size,
+            SharedFiles::Manifest(manifest) => manifest.total_size,
+        };
+        if announced_size > self.share_quota {
+            return Err(SpoolError::QuotaExceeded {
+                quota: self.share_quota,
+            });
+        }
+
+        if self.is_left_behind(sender_id) {
+            return Err(SpoolError::LeftBehind);
+        }
+
+        fs::create_dir_all(&self.dir).await?;
+        let share = SpooledShare {
+            shared_files,
+            data_path: self.dir.join(format!("{}.bin", nanoid!())),
+            frames: Vec::new(),
+            pending_chunk: None,
+            bytes: 0,
+            complete: false,
+            left_behind: false,
+            created_at: Instant::now(),
+        };
+        let replaced = self.lock().insert(sender_id.to_owned(), share);
+        if let Some(replaced) = replaced {
+            remove_data(&replaced).await;
+        }
+        Ok(())
+    }
+
+    pub fn is_uploading(&self, sender_id: &str) -> bool {
+        self.lock()
+            .get(sender_id)
+            .is_some_and(|share| !share.complete)
+    }
+
+    pub fn is_stored(&self, sender_id: &str) -> bool {
+        self.lock()
+            .get(sender_id)
+            .is_some_and(|share| share.complete)
+    }
+
+    /// Marks the stored share of `sender_id` as left behind by the
+    /// connection that uploaded it, so a later one using the same ID cannot
+    /// replace it.
+    pub fn leave_behind(&self, sender_id: &str) {
+        if let Some(share) = self.lock().get_mut(sender_id)
+            && share.complete
+        {
+            share.left_behind = true;
+        }
+    }
+
+    pub fn is_left_behind(&self, sender_id: &str) -> bool {
+        self.lock()
+            .get(sender_id)
+            .is_some_and(|share| share.left_behind)
+    }
+
+    /// Every finished share, by sender.
+    pub fn stored_shares(&self) -> Vec<(String, SharedFiles)> {
+        self.lock()
+            .iter()
+            .filter(|(_, share)| share.complete)
+            .map(|(sender_id, share)| (sender_id.clone(), share.shared_files.clone()))
+            .collect()
+    }
+
+    /// Starts routing flow control from `recipient_id` to a replay of the
+    /// share of `sender_id`. A replay started later for the same pair takes
+    /// over, which ends this one's events.
+    pub fn begin_replay(&self, sender_id: &str, recipient_id: &str) -> ReplayEvents {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let key = (sender_id.to_owned(), recipient_id.to_owned());
+        let replay_id = nanoid!();
+        self.lock_replays()
+            .insert(key.clone(), (replay_id.clone(), events_tx));
+        ReplayEvents {
+            key,
+            replay_id,
+            events_rx,
+        }
+    }
+
+    /// Stops routing flow control to `events`, unless a newer replay of the
+    /// same pair has taken over.
+    pub fn end_replay(&self, events: &ReplayEvents) {
+        let mut replays = self.lock_replays();
+        if replays
+            .get(&events.key)
+            .is_some_and(|(replay_id, _)| *replay_id == events.replay_id)
+        {
+            replays.remove(&events.key);
+        }
+    }
+
+    /// Hands `event` to the replay serving `recipient_id`. Returns whether
+    /// there was one.
+    pub fn pace_replay(&self, sender_id: &str, recipient_id: &str, event: ReplayEvent) -> bool {
+        self.lock_replays()
+            .get(&(sender_id.to_owned(), recipient_id.to_owned()))
+            .is_some_and(|(_, events_tx)| events_tx.send(event).is_ok())
+    }
+
+    fn lock_replays(&self) -> MutexGuard<'_, HashMap<ReplayKey, Replay>> {
+        self.replays
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn shared_files(&self, sender_id: &str) -> Option<SharedFiles> {
+        self.lock()
+            .get(sender_id)
+            .map(|share| share.shared_files.clone())
+    }
+
+    /// Remembers the `fileChunk` message the next binary frame belongs to.
+    pub fn set_pending_chunk(&self, sender_id: &str, info: ChunkInfo) -> Result<(), SpoolError> {
+        match self.lock().get_mut(sender_id) {
+            Some(share) if !share.complete => {
+                share.pending_chunk = Some(info);
+                Ok(())
+            }
+            _ => Err(SpoolError::NotUploading),
+        }
+    }
+
+    /// Appends a binary frame to the upload of `sender_id`. An upload that
+    /// goes over quota or fails to write is dropped.
+    pub async fn append_chunk(
+        &self,
+        sender_id: &str,
+        data: &[u8],
+    ) -> Result<ChunkInfo, SpoolError> {
+        let (info, offset, data_path) = {
+            let mut shares = self.lock();
+            let share = match shares.get_mut(sender_id) {
+                Some(share) if !share.complete => share,
+                _ => return Err(SpoolError::NotUploading),
+            };
+            let info = share
+                .pending_chunk
+                .take()
+                .ok_or(SpoolError::MissingChunkInfo)?;
+
+            let offset = share.bytes;
+            if offset + data.len() as u64 > self.share_quota {
+                if let Some(share) = shares.remove(sender_id) {
+                    tokio::spawn(async move { remove_data(&share).await });
+                }
+                return Err(SpoolError::QuotaExceeded {
+                    quota: self.share_quota,
+                });
+            }
+            share.bytes += data.len() as u64;
+            (info, offset, share.data_path.clone())
+        };
+
+        let written = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&data_path)
+                .await?;
+            file.write_all(data).await?;
+            file.flush().await
+        }
+        .await;
+        if let Err(err) = written {
+            self.abandon_upload(sender_id).await;
+            return Err(err.into());
+        }
+
+        if let Some(share) = self.lock().get_mut(sender_id) {
+            share.frames.push(SpooledFrame::Chunk(SpooledChunk {
+                info: info.clone(),
+                offset,
+                len: data.len() as u32,
+            }));
+        }
+        Ok(info)
+    }
+
+    /// Records a `fileEnd`. Returns `true` once the last file of the share
+    /// has ended and the share can be served.
+    pub fn end_file(&self, sender_id: &str, info: FileEndInfo) -> Result<bool, SpoolError> {
+        let mut shares = self.lock();
+        let share = match shares.get_mut(sender_id) {
+            Some(share) if !share.complete => share,
+            _ => return Err(SpoolError::NotUploading),
+        };
+
+        let file_count = share.shared_files.file_count();
+        share.complete = info
+            .file_index
+            .is_none_or(|file_index| file_index as usize + 1 >= file_count);
+        share.frames.push(SpooledFrame::End(info));
+        Ok(share.complete)
+    }
+
+    /// Drops an upload that never finished, e.g. because the sender left.
+    pub async fn abandon_upload(&self, sender_id: &str) {
+        let share = {
+            let mut shares = self.lock();
+            match shares.get(sender_id) {
+                Some(share) if !share.complete => shares.remove(sender_id),
+                _ => None,
+            }
+        };
+        if let Some(share) = share {
+            remove_data(&share).await;
+        }
+    }
+
+    /// Deletes the share of `sender_id`, finished or not. Returns whether
+    /// there was one.
+    pub async fn remove(&self, sender_id: &str) -> bool {
+        let share = self.lock().remove(sender_id);
+        match share {
+            Some(share) => {
+                remove_data(&share).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Everything a recipient needs to replay a stored share.
+    pub fn frames(&self, sender_id: &str) -> Option<Vec<SpooledFrame>> {
+        self.lock()
+            .get(sender_id)
+            .filter(|share| share.complete)
+            .map(|share| share.frames.clone())
+    }
+
+    pub async fn read_chunk(&self, sender_id: &str, chunk: &SpooledChunk) -> io::Result<Vec<u8>> {
+        let data_path = self
+            .lock()
+            .get(sender_id)
+            .map(|share| share.data_path.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "share has expired"))?;
+
+        let mut file = fs::File::open(data_path).await?;
+        file.seek(SeekFrom::Start(chunk.offset)).await?;
+        let mut data = vec![0; chunk.len as usize];
+        file.read_exact(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Removes shares older than the TTL. Returns how many were removed.
+    pub async fn purge_expired(&self) -> usize {
+        let expired: Vec<SpooledShare> = {
+            let mut shares = self.lock();
+            let expired_ids: Vec<String> = shares
+                .iter()
+                .filter(|(_, share)| share.created_at.elapsed() >= self.ttl)
+                .map(|(sender_id, _)| sender_id.clone())
+                .collect();
+            expired_ids
+                .iter()
+                .filter_map(|sender_id| shares.remove(sender_id))
+                .collect()
+        };
+
+        for share in &expired {
+            remove_data(share).await;
+        }
+        expired.len()
+    }
+
+    /// Deletes `.bin` files in the spool directory that no share refers to,
+    /// such as those left behind by a previous run.
+    pub async fn remove_orphans(&self) -> io::Result<()> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "bin") {
+                continue;
+            }
+            let in_use = self.lock().values().any(|share| share.data_path == path);
+            if !in_use && entry.file_type().await?.is_file() {
+                fs::remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn remove_data(share: &SpooledShare) {
+    if let Err(err) = fs::remove_file(&share.data_path).await
+        && err.kind() != io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %share.data_path.display(), error = %err, "failed to remove spool file");
+    }
+}
+
+/// Clears out files from earlier runs, then purges expired shares every
+/// `period`.
+pub fn spawn_spool_janitor(spool: Arc<Spool>, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = spool.remove_orphans().await {
+            tracing::warn!(error = %err, "failed to clean up the spool directory");
+        }
+
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            let purged = spool.purge_expired().await;
+            if purged > 0 {
+                tracing::info!(purged, "purged expired spool shares");
+            }
+        }
+    })
+}
@@ -0,0 +1,60 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct FailedAttempts {
+    window_start: Instant,
+    count: u32,
+}
+
+/// Counts failed guesses per key and refuses a key once it reaches
+/// `max_failed_attempts` within `window` of its first failure.
+///
+/// It does no locking of its own; owners keep it behind the same mutex as
+/// the secrets it protects.
+#[derive(Debug)]
+pub struct AttemptLimiter<K> {
+    failures: HashMap<K, FailedAttempts>,
+    max_failed_attempts: u32,
+    window: Duration,
+}
+
+impl<K: Hash + Eq> AttemptLimiter<K> {
+    pub fn new(max_failed_attempts: u32, window: Duration) -> Self {
+        Self {
+            failures: HashMap::new(),
+            max_failed_attempts,
+            window,
+        }
+    }
+
+    /// `Err` holds how long `key` must wait before trying again.
+    pub fn check(&mut self, key: &K, now: Instant) -> Result<(), Duration> {
+        self.failures
+            .retain(|_, failed| now.duration_since(failed.window_start) < self.window);
+
+        match self.failures.get(key) {
+            Some(failed) if failed.count >= self.max_failed_attempts => {
+                Err(self.window - now.duration_since(failed.window_start))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&mut self, key: K, now: Instant) {
+        self.failures
+            .entry(key)
+            .or_insert(FailedAttempts {
+                window_start: now,
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    pub fn reset(&mut self, key: &K) {
+        self.failures.remove(key);
+    }
+}
@@ -1,16 +1,154 @@
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
+use crate::feature::relay::{
+    integrity::{Sha256Digest, parse_sha256},
+    manifest::FileManifest,
+};
+
 #[derive(Deserialize)]
 pub struct RelayQueryParams {
-    pub id: String,
+    pub id: Option<String>,
+}
+
+/// The sender a pairing code stood for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedeemedPairingCode {
+    pub sender_id: String,
+}
+
+/// What to do when a client asks for a peer ID that is already connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerIdPolicy {
+    /// Refuse the new connection.
+    Reject,
+    /// Close the existing socket and hand the ID to the new one.
+    TakeOver,
+    /// Register the new connection under the requested ID plus a random suffix.
+    Suffix,
+}
+
+impl FromStr for PeerIdPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "takeover" | "take_over" | "take-over" => Ok(Self::TakeOver),
+            "suffix" => Ok(Self::Suffix),
+            other => Err(format!("unknown peer id policy `{other}`")),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct FileMetadata {
     pub name: String,
     pub size: u64,
     #[serde(rename = "type")]
     pub mime_type: String,
+    /// Hex-encoded SHA-256 of the whole file, if the sender declared one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// What a sender has announced: a single file via `fileMeta`, or several via
+/// `fileManifest`. Serialized as the inner value, so single-file shares keep
+/// their original shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum SharedFiles {
+    Single(FileMetadata),
+    Manifest(FileManifest),
+}
+
+impl SharedFiles {
+    pub fn file_count(&self) -> usize {
+        match self {
+            Self::Single(_) => 1,
+            Self::Manifest(manifest) => manifest.files.len(),
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        match self {
+            Self::Single(file) => file.size,
+            Self::Manifest(manifest) => manifest.total_size,
+        }
+    }
+
+    /// Declared whole-file hashes, by file index.
+    pub fn file_hashes(&self) -> Vec<Option<Sha256Digest>> {
+        let parse = |hash: &Option<String>| hash.as_deref().and_then(|h| parse_sha256(h).ok());
+        match self {
+            Self::Single(file) => vec![parse(&file.sha256)],
+            Self::Manifest(manifest) => manifest
+                .files
+                .iter()
+                .map(|entry| parse(&entry.sha256))
+                .collect(),
+        }
+    }
+}
+
+/// Whether the peer IDs a payload names must belong to the socket that sent
+/// it, or to the peer that socket is paired with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityPolicy {
+    /// Reject payloads that act for another peer.
+    Strict,
+    /// Trust payload IDs, for clients written before IDs were bound to
+    /// sockets.
+    Compat,
+}
+
+impl FromStr for IdentityPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "compat" => Ok(Self::Compat),
+            other => Err(format!("unknown identity policy `{other}`")),
+        }
+    }
+}
+
+impl IdentityPolicy {
+    /// The ID a payload acts as: the one it claims, if any and if allowed,
+    /// otherwise the connection's own. Under `Strict`, a claimed ID other
+    /// than `conn_id` is returned as the error.
+    pub fn resolve(self, claimed: Option<String>, conn_id: &str) -> Result<String, String> {
+        match claimed {
+            Some(claimed) if claimed != conn_id && self == Self::Strict => Err(claimed),
+            Some(claimed) => Ok(claimed),
+            None => Ok(conn_id.to_owned()),
+        }
+    }
+}
+
+/// What a broadcast share does when some recipients acknowledge a chunk and
+/// others do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowRecipientPolicy {
+    /// Hold the sender until every recipient has acknowledged.
+    Wait,
+    /// Drop recipients that have not acknowledged within the timeout.
+    Drop,
+}
+
+impl FromStr for SlowRecipientPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wait" => Ok(Self::Wait),
+            "drop" => Ok(Self::Drop),
+            other => Err(format!("unknown slow recipient policy `{other}`")),
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -0,0 +1,85 @@
+use ts_rs::TS;
+
+use crate::feature::relay::{
+    error::{ErrorCode, ErrorContext, ErrorMessage},
+    pairing::PairingCodeStyle,
+    protocol::{Capability, Limits},
+    session::ShareMode,
+    ws::dto::{request::*, response::*},
+};
+
+const HEADER: &str = "\
+// Generated from the relayr-api protocol types. Do not edit by hand;
+// regenerate with `UPDATE_TS_BINDINGS=1 cargo test --test typescript`.
+";
+
+macro_rules! declarations {
+    ($($ty:ty),* $(,)?) => {
+        vec![$(<$ty as TS>::decl()),*]
+    };
+}
+
+/// TypeScript declarations for every message exchanged on the relay socket
+/// and the types they refer to, as one module.
+pub fn protocol_declarations() -> String {
+    let declarations = declarations!(
+        // Shared
+        Capability,
+        Limits,
+        ShareMode,
+        PairingCodeStyle,
+        ErrorCode,
+        ErrorContext,
+        ErrorMessage,
+        // Incoming
+        RelayIncomingMessage,
+        RelayIncomingPayload,
+        HelloPayload,
+        RecipientReadyPayload,
+        CreatePairingCodePayload,
+        KeyExchangePayload,
+        FileMetadataPayload,
+        FileManifestPayload,
+        ManifestEntryPayload,
+        CancelRecipientReadyPayload,
+        CancelSenderReadyPayload,
+        FileChunkPayload,
+        FileTransferAckPayload,
+        GrantCreditsPayload,
+        FileEndPayload,
+        CancelSenderTransferPayload,
+        CancelRecipientTransferPayload,
+        SenderAckPayload,
+        UserClosePayload,
+        // Outgoing
+        RelayOutgoingMessage,
+        RelayOutgoingPayload,
+        RegisterResponseDto,
+        HelloResponseDto,
+        RecipientReadyResponseDto,
+        CancelRecipientReadyResponseDto,
+        CancelSenderReadyResponseDto,
+        FileChunkResponseDto,
+        FileTransferAckResponseDto,
+        FileEndResponseDto,
+        CancelSenderTransferResponseDto,
+        CancelRecipientTransferResponseDto,
+        SenderAckResponseDto,
+        RestartTransferResponseDto,
+        ResumeFromResponseDto,
+        PeerDisconnectedResponseDto,
+        RecipientDroppedResponseDto,
+        SpoolStoredResponseDto,
+        PairingCodeResponseDto,
+        KeyExchangeResponseDto,
+        FlowControlResponseDto,
+    );
+
+    let mut out = HEADER.to_owned();
+    for declaration in declarations {
+        out.push_str("\nexport ");
+        out.push_str(&declaration);
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::feature::relay::{
+    error::{ErrorCode, ErrorContext, ErrorMessage},
+    state::RelayState,
+    ws::dto::response::{AsWsMessage, RecipientDroppedResponseDto},
+};
+
+/// Waits `timeout` for the recipients of a broadcast share to acknowledge
+/// `chunk_index`, then drops the ones that have not and lets the sender carry
+/// on with the rest.
+pub fn spawn_slow_recipient_timer(
+    state: RelayState,
+    sender_id: String,
+    chunk_index: u32,
+    timeout: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        sleep(timeout).await;
+
+        let dropped = state.registry.drop_lagging(&sender_id, chunk_index);
+        if dropped.recipient_ids.is_empty() {
+            return;
+        }
+
+        for recipient_id in &dropped.recipient_ids {
+            tracing::warn!(
+                sender_id,
+                recipient_id,
+                chunk_index,
+                "dropping slow recipient from broadcast share"
+            );
+
+            if let Some(recipient_tx) = state.registry.peer_tx(recipient_id) {
+                let err_msg = ErrorMessage::new(
+                    ErrorCode::RecipientDropped,
+                    &format!(
+                        "dropped from sender `{}`: chunk {} was not acknowledged in time",
+                        sender_id, chunk_index
+                    ),
+                )
+                .with_context(ErrorContext {
+                    sender_id: Some(sender_id.clone()),
+                    recipient_id: Some(recipient_id.clone()),
+                    chunk_index: Some(chunk_index),
+                    ..Default::default()
+                })
+                .as_notification();
+                let _ = recipient_tx.send(err_msg).await;
+            }
+        }
+
+        if let Some(sender_tx) = state.registry.peer_tx(&sender_id) {
+            for recipient_id in &dropped.recipient_ids {
+                let msg = RecipientDroppedResponseDto::new(&sender_id, recipient_id, chunk_index)
+                    .as_notification();
+                let _ = sender_tx.send(msg).await;
+            }
+            if let Some(ack_msg) = dropped.released_ack {
+                let _ = sender_tx.send(ack_msg).await;
+            }
+        }
+    })
+}
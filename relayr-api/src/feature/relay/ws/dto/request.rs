@@ -1,12 +1,65 @@
+use schemars::JsonSchema;
 use serde::Deserialize;
+use ts_rs::TS;
 
+use crate::feature::relay::{pairing::PairingCodeStyle, protocol::Capability, session::ShareMode};
+
+/// A message from a client. `requestId` is optional and opaque; the relay
+/// echoes it on every reply it sends this client while handling this one.
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct RelayIncomingMessage {
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub payload: RelayIncomingPayload,
+}
+
+/// Just the `requestId` and `type` of a message, for reporting on it when
+/// the rest of the message does not parse.
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestProbe {
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default, rename = "type")]
+    pub message_type: Option<String>,
+}
+
+/// The incoming message being handled. Replies built within [`REQUEST`]'s
+/// scope echo its `requestId`, and errors name its type.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub request_id: Option<String>,
+    pub message_type: Option<String>,
+}
+
+tokio::task_local! {
+    pub static REQUEST: RequestContext;
+}
+
+impl RequestContext {
+    pub fn current() -> Self {
+        REQUEST.try_with(Clone::clone).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(tag = "type")]
 pub enum RelayIncomingPayload {
+    #[serde(rename = "hello")]
+    Hello(HelloPayload),
     #[serde(rename = "recipientReady")]
     RecipientReady(RecipientReadyPayload),
+    #[serde(rename = "createPairingCode")]
+    CreatePairingCode(CreatePairingCodePayload),
+    #[serde(rename = "keyExchange")]
+    KeyExchange(KeyExchangePayload),
     #[serde(rename = "fileMeta")]
     FileMetadata(FileMetadataPayload),
+    #[serde(rename = "fileManifest")]
+    FileManifest(FileManifestPayload),
     #[serde(rename = "cancelRecipientReady")]
     CancelRecipientReady(CancelRecipientReadyPayload),
     #[serde(rename = "cancelSenderReady")]
@@ -15,6 +68,8 @@ pub enum RelayIncomingPayload {
     FileChunk(FileChunkPayload),
     #[serde(rename = "fileTransferAck")]
     FileTransferAck(FileTransferAckPayload),
+    #[serde(rename = "grantCredits")]
+    GrantCredits(GrantCreditsPayload),
     #[serde(rename = "fileEnd")]
     FileEnd(FileEndPayload),
     #[serde(rename = "cancelSenderTransfer")]
@@ -30,91 +85,209 @@ pub enum RelayIncomingPayload {
     #[serde(rename = "terminate")]
     Terminate,
     #[serde(other)]
+    #[ts(skip)]
+    #[schemars(skip)]
     Unknown,
 }
 
-#[derive(Deserialize)]
+impl RelayIncomingPayload {
+    /// The `type` this message was sent with; unknown types are not kept.
+    pub fn message_type(&self) -> Option<&'static str> {
+        match self {
+            Self::Hello(_) => Some("hello"),
+            Self::RecipientReady(_) => Some("recipientReady"),
+            Self::CreatePairingCode(_) => Some("createPairingCode"),
+            Self::KeyExchange(_) => Some("keyExchange"),
+            Self::FileMetadata(_) => Some("fileMeta"),
+            Self::FileManifest(_) => Some("fileManifest"),
+            Self::CancelRecipientReady(_) => Some("cancelRecipientReady"),
+            Self::CancelSenderReady(_) => Some("cancelSenderReady"),
+            Self::FileChunk(_) => Some("fileChunk"),
+            Self::FileTransferAck(_) => Some("fileTransferAck"),
+            Self::GrantCredits(_) => Some("grantCredits"),
+            Self::FileEnd(_) => Some("fileEnd"),
+            Self::CancelSenderTransfer(_) => Some("cancelSenderTransfer"),
+            Self::CancelRecipientTransfer(_) => Some("cancelRecipientTransfer"),
+            Self::SenderAck(_) => Some("senderAck"),
+            Self::RestartTransfer => Some("restartTransfer"),
+            Self::UserClose(_) => Some("userClose"),
+            Self::Terminate => Some("terminate"),
+            Self::Unknown => None,
+        }
+    }
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+pub struct HelloPayload {
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct RecipientReadyPayload {
-    pub sender_id: String,
+    /// Either `senderId` or a pairing `code` minted for the sender.
+    pub sender_id: Option<String>,
+    pub code: Option<String>,
     pub recipient_id: Option<String>,
+    pub passphrase: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct CreatePairingCodePayload {
+    pub sender_id: Option<String>,
+    pub style: Option<PairingCodeStyle>,
+}
+
+/// An ephemeral public key for the peer this connection is paired with.
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct KeyExchangePayload {
+    pub from_id: Option<String>,
+    pub to_id: String,
+    /// Hex-encoded X25519 public key.
+    pub public_key: String,
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct FileMetadataPayload {
     pub sender_id: Option<String>,
     pub name: String,
+    #[ts(type = "number")]
     pub size: u64,
     pub mime_type: String,
+    pub sha256: Option<String>,
+    pub share_mode: Option<ShareMode>,
+    pub store_and_forward: Option<bool>,
+    /// Recipients must present this passphrase; the relay keeps only a hash.
+    pub passphrase: Option<String>,
+    pub e2ee: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct FileManifestPayload {
+    pub sender_id: Option<String>,
+    pub files: Vec<ManifestEntryPayload>,
+    pub share_mode: Option<ShareMode>,
+    pub store_and_forward: Option<bool>,
+    pub passphrase: Option<String>,
+    pub e2ee: Option<bool>,
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct ManifestEntryPayload {
+    pub path: String,
+    #[ts(type = "number")]
+    pub size: u64,
+    pub mime_type: String,
+    pub sha256: Option<String>,
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct CancelRecipientReadyPayload {
     pub sender_id: String,
     pub recipient_id: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct CancelSenderReadyPayload {
     pub sender_id: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct FileChunkPayload {
     pub sender_id: Option<String>,
+    pub file_index: Option<u32>,
     pub file_name: String,
+    #[ts(type = "number")]
     pub total_size: u64,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub chunk_index: u32,
     pub chunk_data_size: u32,
+    pub chunk_sha256: Option<String>,
     pub sender_transfer_progress: u8,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct FileTransferAckPayload {
     pub recipient_id: Option<String>,
     pub sender_id: String,
     pub status: String,
+    pub file_index: Option<u32>,
     pub file_name: String,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub chunk_index: u32,
     pub chunk_data_size: u32,
     pub recipient_transfer_progress: u8,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
+pub struct GrantCreditsPayload {
+    pub sender_id: String,
+    pub recipient_id: Option<String>,
+    pub credits: u32,
+}
+
+#[derive(Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct FileEndPayload {
     pub sender_id: Option<String>,
+    pub file_index: Option<u32>,
     pub file_name: String,
+    #[ts(type = "number")]
     pub total_size: u64,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub last_chunk_index: u32,
+    pub sha256: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct CancelSenderTransferPayload {
     pub sender_id: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct CancelRecipientTransferPayload {
     pub sender_id: String,
     pub recipient_id: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct SenderAckPayload {
     pub request_type: String,
     pub sender_id: Option<String>,
@@ -122,8 +295,9 @@ pub struct SenderAckPayload {
     pub message: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[ts(optional_fields)]
 pub struct UserClosePayload {
     pub user_id: Option<String>,
     pub role: String,
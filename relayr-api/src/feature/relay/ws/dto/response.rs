@@ -1,121 +1,246 @@
-use axum::extract::ws::Message;
 use chrono::Utc;
-use serde::Serialize;
-use std::fmt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use ts_rs::TS;
 
-use crate::impl_ws_text_response;
+use crate::feature::relay::{
+    codec::Outgoing,
+    error::{ErrorCode, ErrorMessage},
+    flow::FlowStatus,
+    protocol::{Capability, Limits, Negotiated},
+    ws::dto::request::RequestContext,
+};
 
-// Define a trait for shared functionality
-pub trait AsWsTextMessage {
-    fn as_ws_text_message(&self) -> Message;
+/// Every message the relay sends, tagged by `type`. The envelope fields all
+/// of them share are added by [`RelayOutgoingMessage`].
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RelayOutgoingPayload {
+    Register(RegisterResponseDto),
+    Hello(HelloResponseDto),
+    RecipientReady(RecipientReadyResponseDto),
+    CancelRecipientReady(CancelRecipientReadyResponseDto),
+    CancelSenderReady(CancelSenderReadyResponseDto),
+    FileChunk(FileChunkResponseDto),
+    FileTransferAck(FileTransferAckResponseDto),
+    FileEnd(FileEndResponseDto),
+    CancelSenderTransfer(CancelSenderTransferResponseDto),
+    CancelRecipientTransfer(CancelRecipientTransferResponseDto),
+    SenderAck(SenderAckResponseDto),
+    RestartTransfer(RestartTransferResponseDto),
+    ResumeFrom(ResumeFromResponseDto),
+    PeerDisconnected(PeerDisconnectedResponseDto),
+    RecipientDropped(RecipientDroppedResponseDto),
+    SpoolStored(SpoolStoredResponseDto),
+    PairingCode(PairingCodeResponseDto),
+    KeyExchange(KeyExchangeResponseDto),
+    Pause(FlowControlResponseDto),
+    Resume(FlowControlResponseDto),
+    Error(ErrorMessage),
 }
 
-#[derive(Debug, Serialize)]
+/// A payload plus the fields every message carries. `success` is `false`
+/// for errors only.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct RegisterResponseDto {
+pub struct RelayOutgoingMessage {
     pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
-    pub conn_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub payload: RelayOutgoingPayload,
+    #[ts(type = "number")]
     pub timestamp: i64,
 }
+
+impl RelayOutgoingMessage {
+    /// A reply to the peer whose message is being handled, echoing its
+    /// `requestId`.
+    pub fn new(payload: RelayOutgoingPayload) -> Self {
+        Self {
+            request_id: RequestContext::current().request_id,
+            ..Self::notification(payload)
+        }
+    }
+
+    /// A message relayed or pushed to any other peer. The `requestId` belongs
+    /// to whoever sent the message being handled, so it is never passed on.
+    pub fn notification(payload: RelayOutgoingPayload) -> Self {
+        Self {
+            success: !matches!(payload, RelayOutgoingPayload::Error(_)),
+            request_id: None,
+            payload,
+            timestamp: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            tracing::error!(message = ?self, error = %e, "failed to serialize outgoing message");
+            let fallback = ErrorMessage::new(ErrorCode::Unknown, "internal serialization error");
+            serde_json::json!({
+                "success": false,
+                "requestId": self.request_id,
+                "type": "error",
+                "code": fallback.code,
+                "message": fallback.message,
+                "timestamp": self.timestamp,
+            })
+            .to_string()
+        })
+    }
+}
+
+pub trait AsWsMessage {
+    /// See [`RelayOutgoingMessage::new`].
+    fn as_ws_message(&self) -> Outgoing;
+    /// See [`RelayOutgoingMessage::notification`].
+    fn as_notification(&self) -> Outgoing;
+}
+
+impl<T: Clone + Into<RelayOutgoingPayload>> AsWsMessage for T {
+    fn as_ws_message(&self) -> Outgoing {
+        Outgoing::Control(Box::new(RelayOutgoingMessage::new(self.clone().into())))
+    }
+
+    fn as_notification(&self) -> Outgoing {
+        Outgoing::Control(Box::new(RelayOutgoingMessage::notification(
+            self.clone().into(),
+        )))
+    }
+}
+
+macro_rules! impl_from_dto {
+    ($($variant:ident($dto:ty)),* $(,)?) => {
+        $(
+            impl From<$dto> for RelayOutgoingPayload {
+                fn from(dto: $dto) -> Self {
+                    Self::$variant(dto)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_dto!(
+    Register(RegisterResponseDto),
+    Hello(HelloResponseDto),
+    RecipientReady(RecipientReadyResponseDto),
+    CancelRecipientReady(CancelRecipientReadyResponseDto),
+    CancelSenderReady(CancelSenderReadyResponseDto),
+    FileChunk(FileChunkResponseDto),
+    FileTransferAck(FileTransferAckResponseDto),
+    FileEnd(FileEndResponseDto),
+    CancelSenderTransfer(CancelSenderTransferResponseDto),
+    CancelRecipientTransfer(CancelRecipientTransferResponseDto),
+    SenderAck(SenderAckResponseDto),
+    RestartTransfer(RestartTransferResponseDto),
+    ResumeFrom(ResumeFromResponseDto),
+    PeerDisconnected(PeerDisconnectedResponseDto),
+    RecipientDropped(RecipientDroppedResponseDto),
+    SpoolStored(SpoolStoredResponseDto),
+    PairingCode(PairingCodeResponseDto),
+    KeyExchange(KeyExchangeResponseDto),
+    Error(ErrorMessage),
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterResponseDto {
+    pub conn_id: String,
+}
 impl RegisterResponseDto {
     pub fn new(conn_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "register".to_string(),
             conn_id: conn_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(RegisterResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloResponseDto {
+    pub protocol_version: u32,
+    pub features: Vec<Capability>,
+    pub limits: Limits,
+}
+impl HelloResponseDto {
+    pub fn new(negotiated: Negotiated, limits: Limits) -> Self {
+        Self {
+            protocol_version: negotiated.version,
+            features: negotiated.features,
+            limits,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RecipientReadyResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub recipient_id: String,
     pub sender_id: String,
-    pub timestamp: i64,
 }
 impl RecipientReadyResponseDto {
     pub fn new(recipient_id: &str, sender_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "recipientReady".to_string(),
             recipient_id: recipient_id.to_owned(),
             sender_id: sender_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(RecipientReadyResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelRecipientReadyResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub recipient_id: String,
     pub sender_id: String,
-    pub timestamp: i64,
 }
 impl CancelRecipientReadyResponseDto {
     pub fn new(recipient_id: &str, sender_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "cancelRecipientReady".to_string(),
             recipient_id: recipient_id.to_owned(),
             sender_id: sender_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(CancelRecipientReadyResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelSenderReadyResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub sender_id: String,
     pub recipient_id: String,
-    pub timestamp: i64,
 }
 impl CancelSenderReadyResponseDto {
     pub fn new(sender_id: &str, recipient_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "cancelSenderReady".to_string(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(CancelSenderReadyResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FileChunkResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub sender_id: String,
     pub recipient_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub file_index: Option<u32>,
     pub file_name: String,
+    #[ts(type = "number")]
     pub total_size: u64,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub chunk_index: u32,
     pub chunk_data_size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub chunk_sha256: Option<String>,
     pub sender_transfer_progress: u8,
-    pub timestamp: i64,
 }
 
 impl FileChunkResponseDto {
@@ -132,39 +257,47 @@ impl FileChunkResponseDto {
         sender_transfer_progress: u8,
     ) -> Self {
         Self {
-            success: true,
-            msg_type: "fileChunk".to_owned(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
+            file_index: None,
             file_name: file_name.to_owned(),
             total_size,
             total_chunks,
             uploaded_size,
             chunk_index,
             chunk_data_size,
+            chunk_sha256: None,
             sender_transfer_progress,
-            timestamp: Utc::now().timestamp(),
         }
     }
+
+    pub fn with_file_index(mut self, file_index: Option<u32>) -> Self {
+        self.file_index = file_index;
+        self
+    }
+
+    pub fn with_chunk_sha256(mut self, chunk_sha256: Option<String>) -> Self {
+        self.chunk_sha256 = chunk_sha256;
+        self
+    }
 }
-impl_ws_text_response!(FileChunkResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FileTransferAckResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub recipient_id: String,
     pub sender_id: String,
     pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub file_index: Option<u32>,
     pub file_name: String,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub chunk_index: u32,
     pub chunk_data_size: u32,
     pub recipient_transfer_progress: u8,
-    pub timestamp: i64,
 }
 
 impl FileTransferAckResponseDto {
@@ -181,37 +314,43 @@ impl FileTransferAckResponseDto {
         recipient_transfer_progress: u8,
     ) -> Self {
         Self {
-            success: true,
-            msg_type: "fileTransferAck".to_owned(),
             recipient_id: recipient_id.to_owned(),
             sender_id: sender_id.to_owned(),
             status: status.to_owned(),
+            file_index: None,
             file_name: file_name.to_owned(),
             total_chunks,
             uploaded_size,
             chunk_index,
             chunk_data_size,
             recipient_transfer_progress,
-            timestamp: Utc::now().timestamp(),
         }
     }
+
+    pub fn with_file_index(mut self, file_index: Option<u32>) -> Self {
+        self.file_index = file_index;
+        self
+    }
 }
-impl_ws_text_response!(FileTransferAckResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FileEndResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub sender_id: String,
     pub recipient_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub file_index: Option<u32>,
     pub file_name: String,
+    #[ts(type = "number")]
     pub total_size: u64,
     pub total_chunks: u16,
+    #[ts(type = "number")]
     pub uploaded_size: u64,
     pub last_chunk_index: u32,
-    pub timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub sha256: Option<String>,
 }
 impl FileEndResponseDto {
     pub fn new(
@@ -224,79 +363,68 @@ impl FileEndResponseDto {
         last_chunk_index: u32,
     ) -> Self {
         Self {
-            success: true,
-            msg_type: "fileEnd".to_owned(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
+            file_index: None,
             file_name: file_name.to_owned(),
             total_size,
             total_chunks,
             uploaded_size,
             last_chunk_index,
-            timestamp: Utc::now().timestamp(),
+            sha256: None,
         }
     }
+
+    pub fn with_file_index(mut self, file_index: Option<u32>) -> Self {
+        self.file_index = file_index;
+        self
+    }
+
+    pub fn with_sha256(mut self, sha256: Option<String>) -> Self {
+        self.sha256 = sha256;
+        self
+    }
 }
-impl_ws_text_response!(FileEndResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelSenderTransferResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub sender_id: String,
     pub recipient_id: String,
-    pub timestamp: i64,
 }
 impl CancelSenderTransferResponseDto {
     pub fn new(sender_id: &str, recipient_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "cancelSenderTransfer".to_owned(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(CancelSenderTransferResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelRecipientTransferResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub recipient_id: String,
     pub sender_id: String,
-    pub timestamp: i64,
 }
 impl CancelRecipientTransferResponseDto {
     pub fn new(recipient_id: &str, sender_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "cancelRecipientTransfer".to_owned(),
             recipient_id: recipient_id.to_owned(),
             sender_id: sender_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(CancelRecipientTransferResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SenderAckResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub request_type: String,
     pub sender_id: String,
     pub recipient_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
     pub message: Option<String>,
-    pub timestamp: i64,
 }
 impl SenderAckResponseDto {
     pub fn new(
@@ -306,60 +434,155 @@ impl SenderAckResponseDto {
         message: Option<String>,
     ) -> Self {
         Self {
-            success: true,
-            msg_type: "senderAck".to_owned(),
             request_type: request_type.to_owned(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
             message,
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(SenderAckResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RestartTransferResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub sender_id: String,
     pub recipient_id: String,
-    pub timestamp: i64,
 }
 impl RestartTransferResponseDto {
     pub fn new(sender_id: &str, recipient_id: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "restartTransfer".to_owned(),
             sender_id: sender_id.to_owned(),
             recipient_id: recipient_id.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(RestartTransferResponseDto);
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumeFromResponseDto {
+    pub sender_id: String,
+    pub recipient_id: String,
+    pub chunk_index: u32,
+    #[ts(type = "number")]
+    pub byte_offset: u64,
+}
+impl ResumeFromResponseDto {
+    pub fn new(sender_id: &str, recipient_id: &str, chunk_index: u32, byte_offset: u64) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            recipient_id: recipient_id.to_owned(),
+            chunk_index,
+            byte_offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PeerDisconnectedResponseDto {
-    pub success: bool,
-    #[serde(rename = "type")]
-    pub msg_type: String,
     pub peer_id: String,
     pub role: String,
-    pub timestamp: i64,
 }
 impl PeerDisconnectedResponseDto {
     pub fn new(peer_id: &str, role: &str) -> Self {
         Self {
-            success: true,
-            msg_type: "peerDisconnected".to_owned(),
             peer_id: peer_id.to_owned(),
             role: role.to_owned(),
-            timestamp: Utc::now().timestamp(),
         }
     }
 }
-impl_ws_text_response!(PeerDisconnectedResponseDto);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecipientDroppedResponseDto {
+    pub sender_id: String,
+    pub recipient_id: String,
+    pub chunk_index: u32,
+}
+impl RecipientDroppedResponseDto {
+    pub fn new(sender_id: &str, recipient_id: &str, chunk_index: u32) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            recipient_id: recipient_id.to_owned(),
+            chunk_index,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SpoolStoredResponseDto {
+    pub sender_id: String,
+    #[ts(type = "number")]
+    pub expires_at: i64,
+}
+impl SpoolStoredResponseDto {
+    pub fn new(sender_id: &str, ttl: Duration) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            expires_at: Utc::now().timestamp().saturating_add(ttl.as_secs() as i64),
+        }
+    }
+}
+
+/// A pairing code minted for the sender; recipients may send it in place of
+/// `senderId` until `expiresAt` or its first use.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingCodeResponseDto {
+    pub sender_id: String,
+    pub code: String,
+    #[ts(type = "number")]
+    pub expires_at: i64,
+}
+impl PairingCodeResponseDto {
+    pub fn new(sender_id: &str, code: &str, ttl: Duration) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            code: code.to_owned(),
+            expires_at: Utc::now().timestamp().saturating_add(ttl.as_secs() as i64),
+        }
+    }
+}
+
+/// A public key relayed from `fromId` to the other side of the pair.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyExchangeResponseDto {
+    pub sender_id: String,
+    pub recipient_id: String,
+    pub from_id: String,
+    pub public_key: String,
+}
+impl KeyExchangeResponseDto {
+    pub fn new(sender_id: &str, recipient_id: &str, from_id: &str, public_key: &str) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            recipient_id: recipient_id.to_owned(),
+            from_id: from_id.to_owned(),
+            public_key: public_key.to_owned(),
+        }
+    }
+}
+
+/// Body of `pause` and `resume`, sent to a sender when its recipients'
+/// windows close or reopen.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowControlResponseDto {
+    pub sender_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub credits: Option<u32>,
+    #[ts(type = "number")]
+    pub queued_bytes: u64,
+}
+impl FlowControlResponseDto {
+    pub fn new(sender_id: &str, status: FlowStatus) -> Self {
+        Self {
+            sender_id: sender_id.to_owned(),
+            credits: status.credits,
+            queued_bytes: status.queued_bytes,
+        }
+    }
+}
@@ -1,8 +1,10 @@
-mod dto;
-mod peer_disconnect;
+mod broadcast;
+pub mod dto;
+pub mod peer_disconnect;
 mod ping;
-mod read;
-mod read_handlers;
+pub mod read;
+pub(crate) mod read_handlers;
+pub mod replay;
 pub mod socket;
 mod task_manager;
 mod write;
@@ -1,35 +1,110 @@
+use tokio::sync::mpsc::Sender;
+
 use crate::feature::relay::{
+    codec::Outgoing,
+    registry::RemovedPeer,
     state::RelayState,
     types::DisconnectReason,
-    ws::dto::response::{AsWsTextMessage, PeerDisconnectedResponseDto},
+    ws::dto::response::{AsWsMessage, PeerDisconnectedResponseDto},
 };
 
+/// Tears down everything `tx` registered as `peer_id`, drops an unfinished
+/// spool upload and tells its counterparts. Returns the counterparts that
+/// were told, or `None` if `tx` no longer owns `peer_id`, in which case
+/// whoever took it over already did this.
+pub async fn disconnect_peer(
+    state: &RelayState,
+    peer_id: &str,
+    tx: &Sender<Outgoing>,
+    reason: DisconnectReason,
+) -> Option<Vec<String>> {
+    let removed = cleanup_peer_state(state, peer_id, tx)?;
+    if let Some(spool) = &state.spool {
+        spool.abandon_upload(peer_id).await;
+    }
+    Some(notify_peers_on_disconnect(state, peer_id, &removed, reason).await)
+}
+
+/// Sends `peerDisconnected` to the counterparts of a removed peer, unless it
+/// left after completing its transfer. Returns the ones it reached.
 pub async fn notify_peers_on_disconnect(
     state: &RelayState,
     peer_id: &str,
+    removed: &RemovedPeer,
     reason: DisconnectReason,
-) {
-    if reason != DisconnectReason::TransferCompleted {
-        if let Some(recipient_peer_id) = state.get_connected_recipient(peer_id).await {
-            if let Some(recipient_tx) = state.get_peer_tx(&recipient_peer_id).await {
-                let msg = PeerDisconnectedResponseDto::new(peer_id, "sender").as_ws_text_message();
-                let _ = recipient_tx.send(msg).await;
-            }
-        }
+) -> Vec<String> {
+    // A recipient leaving a broadcast share can unblock an ack the sender is
+    // still waiting on, however it left.
+    if let Some(ack_msg) = &removed.released_ack
+        && let Some(sender_peer_id) = &removed.sender_id
+        && let Some(sender_tx) = state.registry.peer_tx(sender_peer_id)
+    {
+        let _ = sender_tx.send(ack_msg.clone()).await;
+    }
 
-        if let Some(sender_peer_id) = state.get_connected_sender(peer_id).await {
-            if let Some(sender_tx) = state.get_peer_tx(&sender_peer_id).await {
-                state.remove_active_connection(&sender_peer_id).await;
+    let mut notified = Vec::new();
+    if reason == DisconnectReason::TransferCompleted {
+        return notified;
+    }
 
-                let msg =
-                    PeerDisconnectedResponseDto::new(peer_id, "recipient").as_ws_text_message();
-                let _ = sender_tx.send(msg).await;
+    for recipient_peer_id in &removed.recipient_ids {
+        if let Some(recipient_tx) = state.registry.peer_tx(recipient_peer_id) {
+            let msg = PeerDisconnectedResponseDto::new(peer_id, "sender").as_notification();
+            if recipient_tx.send(msg).await.is_ok() {
+                notified.push(recipient_peer_id.clone());
             }
         }
     }
+
+    if let Some(sender_peer_id) = &removed.sender_id
+        && let Some(sender_tx) = state.registry.peer_tx(sender_peer_id)
+    {
+        let msg = PeerDisconnectedResponseDto::new(peer_id, "recipient").as_notification();
+        if sender_tx.send(msg).await.is_ok() {
+            notified.push(sender_peer_id.clone());
+        }
+    }
+    notified
+}
+
+pub fn cleanup_peer_state(
+    state: &RelayState,
+    peer_id: &str,
+    tx: &Sender<Outgoing>,
+) -> Option<RemovedPeer> {
+    let removed = state.registry.remove_peer(peer_id, tx)?;
+    release_share(state, peer_id);
+    Some(removed)
+}
+
+/// Forgets the share `sender_id` announced, live or stored in the spool,
+/// while the sender stays connected. Returns the recipients it had, or
+/// `None` if there was nothing to forget.
+pub async fn purge_share(state: &RelayState, sender_id: &str) -> Option<Vec<String>> {
+    let recipient_ids = state.registry.purge_share(sender_id);
+    let spooled = match &state.spool {
+        Some(spool) => spool.remove(sender_id).await,
+        None => false,
+    };
+    if recipient_ids.is_none() && !spooled {
+        return None;
+    }
+    release_share(state, sender_id);
+    Some(recipient_ids.unwrap_or_default())
 }
 
-pub async fn cleanup_peer_state(state: &RelayState, peer_id: &str) {
-    state.clear_file_metadata(peer_id).await;
-    state.remove_peer_connection(peer_id).await;
+// Lets go of what a share keeps outside the registry once its sender no
+// longer serves it live. A spooled share keeps its passphrase and pairing
+// code for as long as it can be served.
+fn release_share(state: &RelayState, sender_id: &str) {
+    match &state.spool {
+        Some(spool) if spool.is_stored(sender_id) => {
+            spool.leave_behind(sender_id);
+            state.share_locks.release_after(sender_id, spool.ttl())
+        }
+        _ => {
+            state.share_locks.release(sender_id);
+            state.pairing.revoke(sender_id);
+        }
+    }
 }
@@ -7,10 +7,15 @@ use tokio::{
     time::{Instant, interval},
 };
 
+use crate::feature::relay::codec::Outgoing;
+
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
 
-pub fn spawn_ping_task(tx: Sender<Message>, last_heartbeat: Arc<Mutex<Instant>>) -> JoinHandle<()> {
+pub fn spawn_ping_task(
+    tx: Sender<Outgoing>,
+    last_heartbeat: Arc<Mutex<Instant>>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
         let mut interval = interval(HEARTBEAT_INTERVAL);
 
@@ -25,7 +30,7 @@ pub fn spawn_ping_task(tx: Sender<Message>, last_heartbeat: Arc<Mutex<Instant>>)
                 tracing::warn!("No pong received for more than half of timeout period")
             }
 
-            if tx.send(Message::Ping(vec![].into())).await.is_err() {
+            if tx.send(Message::Ping(vec![].into()).into()).await.is_err() {
                 break;
             }
         }
@@ -1,106 +1,149 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use axum::extract::ws::{Message, WebSocket};
-use futures::{StreamExt, stream::SplitStream};
+use axum::extract::ws::Message;
+use futures::{Stream, StreamExt};
 use tokio::{
     sync::{Mutex, mpsc::Sender},
     task::JoinHandle,
     time::Instant,
 };
+use tracing::Instrument;
 
 use crate::{
     feature::relay::{
+        codec::{Outgoing, WireFormat},
         error::{ErrorCode, ErrorMessage},
+        frame::FrameHeader,
+        integrity::StreamVerifier,
         state::RelayState,
         types::DisconnectReason,
         ws::dto::{
-            request::RelayIncomingPayload,
-            response::{AsWsTextMessage, RegisterResponseDto},
+            request::{REQUEST, RelayIncomingMessage, RequestContext, RequestProbe},
+            response::{AsWsMessage, RegisterResponseDto},
         },
-        ws::read_handlers::handle_text_message_payload,
+        ws::read_handlers::{handle_binary_message, handle_text_message_payload},
     },
     send_or_stop,
 };
 
+/// Handles the messages `peer_id` sends on `read`, until its socket closes or
+/// the relay stops reading from it.
 pub fn spawn_read_task(
-    mut read: SplitStream<WebSocket>,
-    tx: Sender<Message>,
+    mut read: impl Stream<Item = Result<Message, axum::Error>> + Unpin + Send + 'static,
+    tx: Sender<Outgoing>,
     state: RelayState,
     peer_id: String,
+    client_ip: IpAddr,
+    wire_format: WireFormat,
     last_heartbeat: Arc<Mutex<Instant>>,
 ) -> JoinHandle<DisconnectReason> {
     tokio::spawn(async move {
         let stop_flag = Arc::new(AtomicBool::new(false));
+        let mut verifier = StreamVerifier::default();
 
         send_or_stop!(
             tx,
-            RegisterResponseDto::new(&peer_id).as_ws_text_message(),
+            RegisterResponseDto::new(&peer_id).as_ws_message(),
             stop_flag
         );
 
         while let Some(Ok(msg_stream)) = read.next().await {
-            match msg_stream {
+            // A connection that was kicked or taken over no longer speaks for
+            // `peer_id`. It only waits for its close handshake; ignoring pongs
+            // lets the heartbeat end it if that never comes.
+            if !matches!(msg_stream, Message::Close(_))
+                && !state.registry.is_registered(&peer_id, &tx)
+            {
+                continue;
+            }
+
+            // Control messages are JSON text, or MessagePack binary frames on
+            // a MessagePack socket; enveloped chunks are never control messages.
+            let control = match &msg_stream {
                 Message::Text(text) => {
-                    let incoming_payload = serde_json::from_str::<RelayIncomingPayload>(&text);
-                    match incoming_payload {
-                        Ok(payload) => {
-                            handle_text_message_payload(
-                                payload,
-                                &tx,
-                                &state,
-                                &peer_id,
-                                stop_flag.clone(),
-                            )
-                            .await
+                    Some(WireFormat::Json.decode::<RelayIncomingMessage>(text.as_bytes()))
+                }
+                Message::Binary(bin_data)
+                    if wire_format == WireFormat::MessagePack
+                        && !FrameHeader::is_envelope(bin_data) =>
+                {
+                    Some(wire_format.decode(bin_data))
+                }
+                _ => None,
+            };
+
+            match (control, msg_stream) {
+                (Some(Ok(message)), _) => {
+                    let span = tracing::info_span!(
+                        "relay_message",
+                        peer_id = %peer_id,
+                        request_id = message.request_id.as_deref(),
+                    );
+                    let message_type = message.payload.message_type().map(str::to_owned);
+                    let handled = handle_text_message_payload(
+                        message.payload,
+                        &tx,
+                        &state,
+                        &peer_id,
+                        client_ip,
+                        stop_flag.clone(),
+                        &mut verifier,
+                    );
+                    let context = RequestContext {
+                        request_id: message.request_id,
+                        message_type,
+                    };
+                    REQUEST.scope(context, handled.instrument(span)).await
+                }
+                (Some(Err(e)), msg_stream) => {
+                    let probe = match &msg_stream {
+                        Message::Text(text) => WireFormat::Json
+                            .decode::<RequestProbe>(text.as_bytes())
+                            .ok(),
+                        Message::Binary(bin_data) => {
+                            wire_format.decode::<RequestProbe>(bin_data).ok()
                         }
-                        Err(e) => {
-                            let err_msg = ErrorMessage::new(
-                                ErrorCode::InvalidPayload,
-                                "failed to parse payload",
-                            )
+                        _ => None,
+                    };
+                    let context = probe
+                        .map(|probe| RequestContext {
+                            request_id: probe.request_id,
+                            message_type: probe.message_type,
+                        })
+                        .unwrap_or_default();
+                    let err_msg = REQUEST.sync_scope(context, || {
+                        ErrorMessage::new(ErrorCode::InvalidPayload, "failed to parse payload")
                             .with_details(&e.to_string())
-                            .as_ws_text_message();
-                            send_or_stop!(tx, err_msg, stop_flag);
-                        }
-                    }
+                            .as_ws_message()
+                    });
+                    send_or_stop!(tx, err_msg, stop_flag);
                 }
-                Message::Binary(bin_data) => {
-                    if let Some(current_recipient) = state.get_connected_recipient(&peer_id).await {
-                        if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                            send_or_stop!(recipient_tx, Message::binary(bin_data), stop_flag);
-                        } else {
-                            let err_msg = ErrorMessage::new(
-                                ErrorCode::RecipientDisconnected,
-                                &format!(
-                                    "recipient `{}` is no longer connected",
-                                    current_recipient
-                                ),
-                            )
-                            .as_ws_text_message();
-                            send_or_stop!(tx, err_msg, stop_flag);
-                        }
-                    } else {
-                        tracing::warn!(
-                            "sender `{}` attempted to send a file, but no recipient is connected",
-                            peer_id
-                        );
-                        let err_msg = ErrorMessage::new(
-                            ErrorCode::ActiveConnectionNotFound,
-                            "active connection for sender_id: `{}` not found",
-                        )
-                        .as_ws_text_message();
-
-                        send_or_stop!(tx, err_msg, stop_flag);
-                    }
+                (None, Message::Binary(bin_data)) => {
+                    let context = RequestContext {
+                        request_id: None,
+                        message_type: Some("binary".to_owned()),
+                    };
+                    let handled = handle_binary_message(
+                        bin_data,
+                        &tx,
+                        &state,
+                        &peer_id,
+                        stop_flag.clone(),
+                        &mut verifier,
+                    );
+                    REQUEST.scope(context, handled).await
                 }
-                Message::Pong(_) => {
+                (None, Message::Pong(_)) => {
                     let mut last_heartbeat = last_heartbeat.lock().await;
                     *last_heartbeat = Instant::now();
                 }
-                Message::Close(reason) => {
+                (None, Message::Close(reason)) => {
                     if let Some(reason) = &reason {
                         tracing::info!(
                             code = reason.code,
@@ -120,7 +163,7 @@ pub fn spawn_read_task(
                         ErrorCode::UnsupportedWsMessageType,
                         "unsupported websocket message type",
                     )
-                    .as_ws_text_message();
+                    .as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
             }
@@ -1,25 +1,50 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use axum::extract::ws::{CloseFrame, Message};
+use axum::{
+    body::Bytes,
+    extract::ws::{CloseFrame, Message},
+};
 use tokio::sync::mpsc::Sender;
 
 use crate::{
+    config::CONFIG,
     feature::relay::{
-        error::{ErrorCode, ErrorMessage},
+        codec::Outgoing,
+        e2ee::parse_public_key,
+        error::{ErrorCode, ErrorContext, ErrorMessage},
+        flow::Admission,
+        frame::{FLAG_ENCRYPTED, FrameHeader},
+        integrity::{IntegrityError, StreamVerifier, parse_sha256},
+        manifest::{FileManifest, ManifestEntry},
+        pairing::PairingError,
+        passphrase::PassphraseError,
+        protocol::{Capability, Limits, negotiate},
+        registry::{PairError, UnpairError},
+        session::{AckOutcome, ShareMode, TransferEvent},
+        spool::{ChunkInfo, FileEndInfo, ReplayEvent, SPOOL_PEER_ID, SpoolError},
         state::RelayState,
-        types::FileMetadata,
-        ws::dto::{
-            request::RelayIncomingPayload,
-            response::{
-                AsWsTextMessage, CancelRecipientReadyResponseDto,
-                CancelRecipientTransferResponseDto, CancelSenderReadyResponseDto,
-                CancelSenderTransferResponseDto, FileChunkResponseDto, FileEndResponseDto,
-                FileTransferAckResponseDto, RecipientReadyResponseDto, RestartTransferResponseDto,
-                SenderAckResponseDto,
+        types::{FileMetadata, IdentityPolicy, SharedFiles, SlowRecipientPolicy},
+        ws::{
+            broadcast::spawn_slow_recipient_timer,
+            dto::{
+                request::RelayIncomingPayload,
+                response::{
+                    AsWsMessage, CancelRecipientReadyResponseDto,
+                    CancelRecipientTransferResponseDto, CancelSenderReadyResponseDto,
+                    CancelSenderTransferResponseDto, FileChunkResponseDto, FileEndResponseDto,
+                    FileTransferAckResponseDto, FlowControlResponseDto, HelloResponseDto,
+                    KeyExchangeResponseDto, PairingCodeResponseDto, RecipientReadyResponseDto,
+                    RelayOutgoingPayload, RestartTransferResponseDto, ResumeFromResponseDto,
+                    SenderAckResponseDto, SpoolStoredResponseDto,
+                },
             },
+            replay::spawn_spool_replay,
         },
     },
     send_or_stop,
@@ -27,351 +52,867 @@ use crate::{
 
 pub async fn handle_text_message_payload(
     message: RelayIncomingPayload,
-    tx: &Sender<Message>,
+    tx: &Sender<Outgoing>,
     state: &RelayState,
     base_conn_id: &str,
+    client_ip: IpAddr,
     stop_flag: Arc<AtomicBool>,
+    verifier: &mut StreamVerifier,
 ) {
     match message {
+        RelayIncomingPayload::Hello(payload) => {
+            let enabled = Capability::enabled(state.spool.is_some());
+            match negotiate(payload.protocol_version, &payload.capabilities, &enabled) {
+                Ok(negotiated) => {
+                    let limits = Limits {
+                        max_chunk_size: CONFIG.max_chunk_size,
+                        max_file_size: CONFIG.max_file_size,
+                        max_share_recipients: CONFIG.max_share_recipients,
+                    };
+                    let hello_msg = HelloResponseDto::new(negotiated, limits).as_ws_message();
+                    send_or_stop!(tx, hello_msg, stop_flag);
+                }
+                Err(err) => {
+                    let err_msg = ErrorMessage::new(
+                        ErrorCode::UnsupportedProtocolVersion,
+                        "unsupported protocol version",
+                    )
+                    .with_details(&err.to_string())
+                    .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+
+                    let close_msg = Message::Close(Some(CloseFrame {
+                        code: 1002,
+                        reason: "unsupported protocol version".into(),
+                    }));
+                    send_or_stop!(tx, close_msg.into(), stop_flag);
+                    // Nothing else the client sends is answered.
+                    stop_flag.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        RelayIncomingPayload::KeyExchange(payload) => {
+            let Some(from_id) = own_id(state, payload.from_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            let public_key = match parse_public_key(&payload.public_key) {
+                Ok(public_key) => hex::encode(public_key),
+                Err(err) => {
+                    let err_msg =
+                        ErrorMessage::new(ErrorCode::InvalidPayload, "invalid public key")
+                            .with_details(&err.to_string())
+                            .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            };
+
+            // Keys only ever travel within a pair, whichever side sends them.
+            let (sender_id, recipient_id) =
+                if state.registry.sender_of(&from_id).as_deref() == Some(&payload.to_id) {
+                    (payload.to_id.clone(), from_id.clone())
+                } else if state
+                    .registry
+                    .recipients_of(&from_id)
+                    .contains(&payload.to_id)
+                {
+                    (from_id.clone(), payload.to_id.clone())
+                } else {
+                    let err_msg = forbidden(&format!(
+                        "peer `{}` is not paired with peer `{}`",
+                        from_id, payload.to_id
+                    ))
+                    .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                };
+            let from_sender = from_id == sender_id;
+
+            let Some(to_tx) = state.registry.peer_tx(&payload.to_id) else {
+                let err_msg = if from_sender {
+                    recipient_disconnected(&recipient_id)
+                } else {
+                    sender_disconnected(&sender_id)
+                }
+                .as_ws_message();
+                send_or_stop!(tx, err_msg, stop_flag);
+                return;
+            };
+
+            state
+                .registry
+                .record_public_key(&sender_id, &recipient_id, from_sender);
+            let key_msg =
+                KeyExchangeResponseDto::new(&sender_id, &recipient_id, &from_id, &public_key)
+                    .as_notification();
+            send_or_stop!(to_tx, key_msg, stop_flag);
+        }
         RelayIncomingPayload::FileMetadata(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
 
-            let new_file_metadata = FileMetadata {
+            if !check_file_size(payload.size, tx, &stop_flag).await {
+                return;
+            }
+
+            if let Some(hash) = &payload.sha256
+                && let Err(err) = parse_sha256(hash)
+            {
+                let err_msg = invalid_hash_message(&err).as_ws_message();
+                send_or_stop!(tx, err_msg, stop_flag);
+                return;
+            }
+            let new_file_metadata = SharedFiles::Single(FileMetadata {
                 name: payload.name.clone(),
                 size: payload.size,
                 mime_type: payload.mime_type.clone(),
+                sha256: payload.sha256.map(|hash| hash.to_ascii_lowercase()),
+            });
+            let file_hashes = new_file_metadata.file_hashes();
+
+            // The passphrase goes in first so no recipient can pair with the
+            // new share before it is protected.
+            let replaced_lock = match state
+                .share_locks
+                .protect(&sender_id, payload.passphrase)
+                .await
+            {
+                Ok(replaced_lock) => replaced_lock,
+                Err(err) => {
+                    let err_msg = passphrase_error_message(&err).as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
             };
 
-            state
-                .store_file_metadata(&sender_id, new_file_metadata)
-                .await;
+            let options = ShareOptions {
+                mode: payload.share_mode.unwrap_or_default(),
+                store_and_forward: payload.store_and_forward.unwrap_or_default(),
+                e2ee: payload.e2ee.unwrap_or_default(),
+            };
+            if announce_share(
+                state,
+                &sender_id,
+                new_file_metadata,
+                options,
+                tx,
+                &stop_flag,
+            )
+            .await
+            {
+                verifier.reset(file_hashes);
+            } else {
+                state.share_locks.restore(&sender_id, replaced_lock);
+            }
+        }
+        RelayIncomingPayload::FileManifest(payload) => {
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            let largest = payload.files.iter().map(|file| file.size).max();
+            if !check_file_size(largest.unwrap_or_default(), tx, &stop_flag).await {
+                return;
+            }
+
+            let entries = payload
+                .files
+                .into_iter()
+                .map(|file| ManifestEntry {
+                    path: file.path,
+                    size: file.size,
+                    mime_type: file.mime_type,
+                    sha256: file.sha256.map(|hash| hash.to_ascii_lowercase()),
+                })
+                .collect();
+            let manifest = match FileManifest::new(entries) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    let err_msg =
+                        ErrorMessage::new(ErrorCode::InvalidManifest, "invalid file manifest")
+                            .with_details(&err.to_string())
+                            .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            };
+
+            let manifest = SharedFiles::Manifest(manifest);
+            let file_hashes = manifest.file_hashes();
+
+            let replaced_lock = match state
+                .share_locks
+                .protect(&sender_id, payload.passphrase)
+                .await
+            {
+                Ok(replaced_lock) => replaced_lock,
+                Err(err) => {
+                    let err_msg = passphrase_error_message(&err).as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            };
+
+            let options = ShareOptions {
+                mode: payload.share_mode.unwrap_or_default(),
+                store_and_forward: payload.store_and_forward.unwrap_or_default(),
+                e2ee: payload.e2ee.unwrap_or_default(),
+            };
+            if announce_share(state, &sender_id, manifest, options, tx, &stop_flag).await {
+                verifier.reset(file_hashes);
+            } else {
+                state.share_locks.restore(&sender_id, replaced_lock);
+            }
         }
         RelayIncomingPayload::RecipientReady(payload) => {
-            let connected_recipient = state.get_connected_recipient(&payload.sender_id).await;
-
-            if let Some(current_recipient) = connected_recipient {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::SenderAlreadyConnected,
-                    &format!(
-                        "sender `{}` is already connected to recipient `{}`",
-                        &payload.sender_id, current_recipient
-                    ),
-                )
-                .as_ws_text_message();
+            let Some(recipient_id) =
+                own_id(state, payload.recipient_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            // A code is only used up once the passphrase checks out, so a
+            // recipient who mistyped it can try again.
+            let sender_id = match (payload.sender_id, &payload.code) {
+                (Some(sender_id), None) => sender_id,
+                (None, Some(code)) => match state.pairing.peek(code, client_ip) {
+                    Ok(sender_id) => sender_id,
+                    Err(err) => {
+                        let err_msg = pairing_error_message(&err).as_ws_message();
+                        send_or_stop!(tx, err_msg, stop_flag);
+                        return;
+                    }
+                },
+                _ => {
+                    let err_msg = ErrorMessage::new(
+                        ErrorCode::InvalidPayload,
+                        "`recipientReady` needs exactly one of `senderId` and `code`",
+                    )
+                    .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            };
+
+            if let Err(err) = state
+                .share_locks
+                .verify(&sender_id, payload.passphrase.as_deref(), client_ip)
+                .await
+            {
+                let err_msg = passphrase_error_message(&err).as_ws_message();
                 send_or_stop!(tx, err_msg, stop_flag);
-            } else {
-                let recipient_id = payload.recipient_id.unwrap_or(base_conn_id.to_owned());
-                if let Some(sender_tx) = state.get_peer_tx(&payload.sender_id).await {
-                    state
-                        .create_active_connection(&payload.sender_id, &recipient_id)
-                        .await;
-                    let success_msg =
-                        RecipientReadyResponseDto::new(&recipient_id, &payload.sender_id)
-                            .as_ws_text_message();
-                    send_or_stop!(sender_tx, success_msg, stop_flag);
-                } else {
+                return;
+            }
+            if let Some(code) = &payload.code {
+                // Another recipient may have used the code up meanwhile.
+                let redeemed = state.pairing.redeem(code, client_ip);
+                if redeemed.as_ref() != Ok(&sender_id) {
+                    let err = redeemed.err().unwrap_or(PairingError::InvalidCode);
+                    let err_msg = pairing_error_message(&err).as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            }
+
+            if let Some(spool) = &state.spool {
+                if spool.is_stored(&sender_id) {
+                    spawn_spool_replay(
+                        spool.clone(),
+                        sender_id.clone(),
+                        recipient_id,
+                        tx.clone(),
+                        CONFIG.flow_max_queued_bytes,
+                    );
+                    return;
+                }
+                if spool.is_uploading(&sender_id) {
                     let err_msg = ErrorMessage::new(
-                        ErrorCode::SenderDisconnected,
-                        &format!("sender `{}` is no longer connected", &payload.sender_id),
+                        ErrorCode::ShareNotReady,
+                        &format!("sender `{}` is still uploading", &sender_id),
                     )
-                    .as_ws_text_message();
+                    .with_context(ErrorContext {
+                        sender_id: Some(sender_id.clone()),
+                        ..Default::default()
+                    })
+                    .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            }
+
+            match state
+                .registry
+                .pair(&sender_id, &recipient_id, CONFIG.max_share_recipients)
+            {
+                Ok(pairing) => {
+                    let success_msg =
+                        RecipientReadyResponseDto::new(&recipient_id, &sender_id).as_notification();
+                    send_or_stop!(pairing.sender_tx, success_msg, stop_flag);
+
+                    if let Some(resume_from) = pairing.resume_from {
+                        let resume = ResumeFromResponseDto::new(
+                            &sender_id,
+                            &recipient_id,
+                            resume_from.chunk_index,
+                            resume_from.byte_offset,
+                        );
+                        send_or_stop!(pairing.sender_tx, resume.as_notification(), stop_flag);
+                        send_or_stop!(tx, resume.as_ws_message(), stop_flag);
+                    }
+                }
+                Err(err) => {
+                    let err_msg = match err {
+                        PairError::SenderBusy { current_recipient } => ErrorMessage::new(
+                            ErrorCode::SenderAlreadyConnected,
+                            &format!(
+                                "sender `{}` is already connected to recipient `{}`",
+                                &sender_id, current_recipient
+                            ),
+                        )
+                        .with_context(ErrorContext {
+                            sender_id: Some(sender_id.clone()),
+                            recipient_id: Some(current_recipient),
+                            ..Default::default()
+                        }),
+                        PairError::RecipientBusy { current_sender } => ErrorMessage::new(
+                            ErrorCode::RecipientAlreadyConnected,
+                            &format!(
+                                "recipient `{}` is already connected to sender `{}`",
+                                recipient_id, current_sender
+                            ),
+                        )
+                        .with_context(ErrorContext {
+                            sender_id: Some(current_sender),
+                            recipient_id: Some(recipient_id.clone()),
+                            ..Default::default()
+                        }),
+                        PairError::ShareFull { limit } => ErrorMessage::new(
+                            ErrorCode::ShareFull,
+                            &format!(
+                                "sender `{}` already has the maximum of {} recipients",
+                                &sender_id, limit
+                            ),
+                        )
+                        .with_context(ErrorContext {
+                            sender_id: Some(sender_id.clone()),
+                            limit: Some(limit as u64),
+                            ..Default::default()
+                        }),
+                        PairError::SenderNotConnected => sender_disconnected(&sender_id),
+                        PairError::RecipientNotConnected => recipient_disconnected(&recipient_id),
+                        PairError::InvalidState(err) => {
+                            ErrorMessage::new(ErrorCode::InvalidStateTransition, &err.to_string())
+                        }
+                    }
+                    .as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
             }
         }
+        RelayIncomingPayload::CreatePairingCode(payload) => {
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            let code = state
+                .pairing
+                .mint(&sender_id, payload.style.unwrap_or_default());
+            let success_msg =
+                PairingCodeResponseDto::new(&sender_id, &code, state.pairing.ttl()).as_ws_message();
+            send_or_stop!(tx, success_msg, stop_flag);
+        }
         RelayIncomingPayload::CancelRecipientReady(payload) => {
-            let recipient_id = payload.recipient_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&payload.sender_id).await;
+            let Some(recipient_id) =
+                own_id(state, payload.recipient_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
 
-            if let Some(current_recipient) = connected_recipient {
-                if current_recipient == recipient_id {
-                    if let Some(sender_tx) = state.get_peer_tx(&payload.sender_id).await {
-                        state.remove_active_connection(&payload.sender_id).await;
+            match state
+                .registry
+                .unpair_recipient(&payload.sender_id, &recipient_id)
+            {
+                Ok(released_ack) => {
+                    if let Some(sender_tx) = state.registry.peer_tx(&payload.sender_id) {
                         let success_msg =
                             CancelRecipientReadyResponseDto::new(&recipient_id, &payload.sender_id)
-                                .as_ws_text_message();
+                                .as_notification();
                         send_or_stop!(sender_tx, success_msg, stop_flag);
+                        if let Some(ack_msg) = released_ack {
+                            send_or_stop!(sender_tx, ack_msg, stop_flag);
+                        }
                     } else {
-                        let err_msg = ErrorMessage::new(
-                            ErrorCode::SenderDisconnected,
-                            &format!("sender `{}` is no longer connected", &payload.sender_id),
-                        )
-                        .as_ws_text_message();
+                        let err_msg = sender_disconnected(&payload.sender_id).as_ws_message();
                         send_or_stop!(tx, err_msg, stop_flag);
                     }
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientMismatch,
-                        &format!(
-                            "recipient ID mismatch. expected `{}`, got `{}`",
-                            current_recipient, recipient_id
-                        ),
-                    )
-                    .as_ws_text_message();
+                }
+                Err(err) => {
+                    let err_msg =
+                        unpair_error_message(state, err, &payload.sender_id, &recipient_id)
+                            .as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!(
-                        "active connection for sender_id: `{}` not found",
-                        &payload.sender_id
-                    ),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
             }
         }
         RelayIncomingPayload::CancelSenderReady(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&sender_id).await;
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
 
-            if let Some(current_recipient) = connected_recipient {
-                state.remove_active_connection(&sender_id).await;
-                if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                    let success_msg =
-                        CancelSenderReadyResponseDto::new(&sender_id, &current_recipient)
-                            .as_ws_text_message();
-                    send_or_stop!(recipient_tx, success_msg, stop_flag);
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientDisconnected,
-                        &format!(
-                            "recipient with id `{}` has no active connection",
-                            current_recipient
-                        ),
-                    )
-                    .as_ws_text_message();
+            match state.registry.unpair(&sender_id) {
+                Ok(recipients) => {
+                    for current_recipient in recipients {
+                        if let Some(recipient_tx) = state.registry.peer_tx(&current_recipient) {
+                            let success_msg =
+                                CancelSenderReadyResponseDto::new(&sender_id, &current_recipient)
+                                    .as_notification();
+                            send_or_stop!(recipient_tx, success_msg, stop_flag);
+                        } else {
+                            let err_msg =
+                                recipient_disconnected(&current_recipient).as_ws_message();
+                            send_or_stop!(tx, err_msg, stop_flag);
+                        }
+                    }
+                }
+                Err(err) => {
+                    let err_msg =
+                        unpair_error_message(state, err, &sender_id, base_conn_id).as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!("active connection for sender_id: `{}` not found", sender_id),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
             }
         }
         RelayIncomingPayload::FileChunk(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&sender_id).await;
-
-            if let Some(current_recipient) = connected_recipient {
-                if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                    let success_msg = FileChunkResponseDto::new(
-                        &sender_id,
-                        &current_recipient,
-                        &payload.file_name,
-                        payload.total_size,
-                        payload.total_chunks,
-                        payload.uploaded_size,
-                        payload.chunk_index,
-                        payload.chunk_data_size,
-                        payload.sender_transfer_progress,
-                    )
-                    .as_ws_text_message();
-                    send_or_stop!(recipient_tx, success_msg, stop_flag);
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientDisconnected,
-                        &format!("recipient `{}` is no longer connected", current_recipient),
-                    )
-                    .as_ws_text_message();
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            let chunk_hash = match payload
+                .chunk_sha256
+                .as_deref()
+                .map(parse_sha256)
+                .transpose()
+            {
+                Ok(chunk_hash) => chunk_hash,
+                Err(err) => {
+                    let err_msg = invalid_hash_message(&err).as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
+                    return;
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!("active connection for sender_id: `{}` not found", sender_id),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
+            };
+
+            if let Some(spool) = &state.spool
+                && spool.is_uploading(&sender_id)
+            {
+                let chunk_info = ChunkInfo {
+                    file_index: payload.file_index,
+                    file_name: payload.file_name,
+                    total_size: payload.total_size,
+                    total_chunks: payload.total_chunks,
+                    uploaded_size: payload.uploaded_size,
+                    chunk_index: payload.chunk_index,
+                    chunk_data_size: payload.chunk_data_size,
+                    chunk_sha256: payload.chunk_sha256,
+                    sender_transfer_progress: payload.sender_transfer_progress,
+                };
+                match spool.set_pending_chunk(&sender_id, chunk_info) {
+                    Ok(()) => {
+                        verifier.expect_chunk(payload.file_index, payload.chunk_index, chunk_hash)
+                    }
+                    Err(err) => {
+                        let err_msg = spool_error_message(&err).as_ws_message();
+                        send_or_stop!(tx, err_msg, stop_flag);
+                    }
+                }
+                return;
             }
-        }
-        RelayIncomingPayload::FileTransferAck(payload) => {
-            let recipient_id = payload.recipient_id.unwrap_or(base_conn_id.to_owned());
-            if let Some(sender_tx) = state.get_peer_tx(&payload.sender_id).await {
-                let success_msg = FileTransferAckResponseDto::new(
-                    &recipient_id,
-                    &payload.sender_id,
-                    &payload.status,
+
+            if !check_file_index(state, &sender_id, payload.file_index, tx, &stop_flag).await {
+                return;
+            }
+            if !apply_transition(state, &sender_id, TransferEvent::Chunk, tx, &stop_flag).await {
+                return;
+            }
+            verifier.expect_chunk(payload.file_index, payload.chunk_index, chunk_hash);
+            relay_to_recipients(state, &sender_id, tx, &stop_flag, |current_recipient| {
+                FileChunkResponseDto::new(
+                    &sender_id,
+                    current_recipient,
                     &payload.file_name,
+                    payload.total_size,
                     payload.total_chunks,
                     payload.uploaded_size,
                     payload.chunk_index,
                     payload.chunk_data_size,
-                    payload.recipient_transfer_progress,
+                    payload.sender_transfer_progress,
                 )
-                .as_ws_text_message();
-                send_or_stop!(sender_tx, success_msg, stop_flag);
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::SenderDisconnected,
-                    &format!("sender `{}` is no longer connected", &payload.sender_id),
-                )
-                .as_ws_text_message();
+                .with_file_index(payload.file_index)
+                .with_chunk_sha256(payload.chunk_sha256.clone())
+                .as_notification()
+            })
+            .await;
+        }
+        RelayIncomingPayload::FileTransferAck(payload) => {
+            let Some(recipient_id) =
+                own_id(state, payload.recipient_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            // Acks for a share replayed from the spool only pace the replay.
+            if state.registry.sender_of(&recipient_id).is_none()
+                && let Some(spool) = &state.spool
+                && spool.is_stored(&payload.sender_id)
+            {
+                if payload.status == "acknowledged" {
+                    spool.pace_replay(&payload.sender_id, &recipient_id, ReplayEvent::Acked);
+                }
+                return;
+            }
+
+            let paired_sender = state.registry.sender_of(&recipient_id);
+            if !check_counterpart(
+                state,
+                paired_sender.as_deref() == Some(&payload.sender_id),
+                &format!(
+                    "recipient `{}` is not paired with sender `{}`",
+                    recipient_id, payload.sender_id
+                ),
+                tx,
+                &stop_flag,
+            )
+            .await
+            {
+                return;
+            }
+
+            if !apply_transition(
+                state,
+                &payload.sender_id,
+                TransferEvent::Ack,
+                tx,
+                &stop_flag,
+            )
+            .await
+            {
+                return;
+            }
+
+            let Some(sender_tx) = state.registry.peer_tx(&payload.sender_id) else {
+                let err_msg = sender_disconnected(&payload.sender_id).as_ws_message();
                 send_or_stop!(tx, err_msg, stop_flag);
+                return;
+            };
+
+            let success_msg = FileTransferAckResponseDto::new(
+                &recipient_id,
+                &payload.sender_id,
+                &payload.status,
+                &payload.file_name,
+                payload.total_chunks,
+                payload.uploaded_size,
+                payload.chunk_index,
+                payload.chunk_data_size,
+                payload.recipient_transfer_progress,
+            )
+            .with_file_index(payload.file_index)
+            .as_notification();
+
+            match state.registry.record_ack(
+                &payload.sender_id,
+                &recipient_id,
+                payload.chunk_index,
+                payload.chunk_data_size,
+                &payload.status,
+                success_msg,
+            ) {
+                Ok(AckOutcome::Forward(ack_msg)) => {
+                    send_or_stop!(sender_tx, ack_msg, stop_flag);
+                }
+                Ok(AckOutcome::Hold { first }) => {
+                    if first && CONFIG.slow_recipient_policy == SlowRecipientPolicy::Drop {
+                        spawn_slow_recipient_timer(
+                            state.clone(),
+                            payload.sender_id.clone(),
+                            payload.chunk_index,
+                            CONFIG.slow_recipient_timeout,
+                        );
+                    }
+                }
+                Err(err) => {
+                    let err_msg =
+                        unpair_error_message(state, err, &payload.sender_id, &recipient_id)
+                            .as_ws_message();
+                    send_or_stop!(tx, err_msg, stop_flag);
+                    return;
+                }
+            }
+
+            if payload.status == "acknowledged"
+                && let Ok(Some(status)) = state
+                    .registry
+                    .release_chunk(&payload.sender_id, &recipient_id)
+            {
+                let resume_msg = RelayOutgoingPayload::Resume(FlowControlResponseDto::new(
+                    &payload.sender_id,
+                    status,
+                ))
+                .as_notification();
+                let _ = sender_tx.send(resume_msg).await;
             }
         }
-        RelayIncomingPayload::FileEnd(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&sender_id).await;
-
-            if let Some(current_recipient) = connected_recipient {
-                if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                    let success_msg = FileEndResponseDto::new(
-                        &sender_id,
-                        &current_recipient,
-                        &payload.file_name,
-                        payload.total_size,
-                        payload.total_chunks,
-                        payload.uploaded_size,
-                        payload.last_chunk_index,
-                    )
-                    .as_ws_text_message();
-                    send_or_stop!(recipient_tx, success_msg, stop_flag);
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientDisconnected,
-                        &format!("recipient `{}` is no longer connected", current_recipient),
-                    )
-                    .as_ws_text_message();
+        RelayIncomingPayload::GrantCredits(payload) => {
+            let Some(recipient_id) =
+                own_id(state, payload.recipient_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            if state.registry.sender_of(&recipient_id).is_none()
+                && let Some(spool) = &state.spool
+                && spool.pace_replay(
+                    &payload.sender_id,
+                    &recipient_id,
+                    ReplayEvent::Credits(payload.credits),
+                )
+            {
+                return;
+            }
+
+            match state
+                .registry
+                .grant_credits(&payload.sender_id, &recipient_id, payload.credits)
+            {
+                Ok(Some(status)) => {
+                    if let Some(sender_tx) = state.registry.peer_tx(&payload.sender_id) {
+                        let resume_msg = RelayOutgoingPayload::Resume(FlowControlResponseDto::new(
+                            &payload.sender_id,
+                            status,
+                        ))
+                        .as_notification();
+                        let _ = sender_tx.send(resume_msg).await;
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    let err_msg =
+                        unpair_error_message(state, err, &payload.sender_id, &recipient_id)
+                            .as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!("active connection for sender_id: `{}` not found", sender_id),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
             }
         }
-        RelayIncomingPayload::CancelSenderTransfer(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&sender_id).await;
+        RelayIncomingPayload::FileEnd(payload) => {
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
 
-            if let Some(current_recipient) = connected_recipient {
-                if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                    let success_msg =
-                        CancelSenderTransferResponseDto::new(&sender_id, &current_recipient)
-                            .as_ws_text_message();
-                    send_or_stop!(recipient_tx, success_msg, stop_flag);
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientDisconnected,
-                        &format!("recipient `{}` is no longer connected", current_recipient),
-                    )
-                    .as_ws_text_message();
+            let file_hash = match payload.sha256.as_deref().map(parse_sha256).transpose() {
+                Ok(file_hash) => file_hash,
+                Err(err) => {
+                    let err_msg = invalid_hash_message(&err).as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
+                    return;
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!("active connection for sender_id: `{}` not found", sender_id),
+            };
+
+            if let Some(spool) = &state.spool
+                && spool.is_uploading(&sender_id)
+            {
+                if let Err(err) = verifier.check_file(payload.file_index, file_hash) {
+                    report_integrity_mismatch(state, &sender_id, &err, tx, &stop_flag).await;
+                    return;
+                }
+                let end_info = FileEndInfo {
+                    file_index: payload.file_index,
+                    file_name: payload.file_name,
+                    total_size: payload.total_size,
+                    total_chunks: payload.total_chunks,
+                    uploaded_size: payload.uploaded_size,
+                    last_chunk_index: payload.last_chunk_index,
+                    sha256: payload.sha256,
+                };
+                let ended = spool.end_file(&sender_id, end_info);
+                if ended.is_ok() {
+                    verifier.finish_file();
+                }
+                match ended {
+                    Ok(true) => {
+                        let stored_msg =
+                            SpoolStoredResponseDto::new(&sender_id, spool.ttl()).as_ws_message();
+                        send_or_stop!(tx, stored_msg, stop_flag);
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        let err_msg = spool_error_message(&err).as_ws_message();
+                        send_or_stop!(tx, err_msg, stop_flag);
+                    }
+                }
+                return;
+            }
+
+            if !check_file_index(state, &sender_id, payload.file_index, tx, &stop_flag).await {
+                return;
+            }
+            // Checked before the transition, so a mismatching file does not
+            // complete the transfer.
+            if let Err(err) = verifier.check_file(payload.file_index, file_hash) {
+                report_integrity_mismatch(state, &sender_id, &err, tx, &stop_flag).await;
+                return;
+            }
+            // Only the end of the last file in a manifest completes the
+            // transfer; earlier ones just move on to the next file.
+            let event = match (payload.file_index, state.registry.file_count(&sender_id)) {
+                (Some(file_index), Some(file_count)) if (file_index as usize) + 1 < file_count => {
+                    TransferEvent::Chunk
+                }
+                _ => TransferEvent::End,
+            };
+            if !apply_transition(state, &sender_id, event, tx, &stop_flag).await {
+                return;
+            }
+            verifier.finish_file();
+            relay_to_recipients(state, &sender_id, tx, &stop_flag, |current_recipient| {
+                FileEndResponseDto::new(
+                    &sender_id,
+                    current_recipient,
+                    &payload.file_name,
+                    payload.total_size,
+                    payload.total_chunks,
+                    payload.uploaded_size,
+                    payload.last_chunk_index,
                 )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
+                .with_file_index(payload.file_index)
+                .with_sha256(payload.sha256.clone())
+                .as_notification()
+            })
+            .await;
+        }
+        RelayIncomingPayload::CancelSenderTransfer(payload) => {
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            if !apply_transition(state, &sender_id, TransferEvent::Cancel, tx, &stop_flag).await {
+                return;
             }
+            relay_to_recipients(state, &sender_id, tx, &stop_flag, |current_recipient| {
+                CancelSenderTransferResponseDto::new(&sender_id, current_recipient)
+                    .as_notification()
+            })
+            .await;
         }
         RelayIncomingPayload::CancelRecipientTransfer(payload) => {
-            let recipient_id = payload.recipient_id.unwrap_or(base_conn_id.to_owned());
-            let connected_recipient = state.get_connected_recipient(&payload.sender_id).await;
+            let Some(recipient_id) =
+                own_id(state, payload.recipient_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
 
-            if let Some(current_recipient) = connected_recipient {
-                if current_recipient == recipient_id {
-                    if let Some(sender_tx) = state.get_peer_tx(&payload.sender_id).await {
+            match state
+                .registry
+                .cancel_recipient(&payload.sender_id, &recipient_id)
+            {
+                Ok(released_ack) => {
+                    if let Some(sender_tx) = state.registry.peer_tx(&payload.sender_id) {
                         let success_msg = CancelRecipientTransferResponseDto::new(
                             &recipient_id,
                             &payload.sender_id,
                         )
-                        .as_ws_text_message();
+                        .as_notification();
                         send_or_stop!(sender_tx, success_msg, stop_flag);
+                        if let Some(ack_msg) = released_ack {
+                            send_or_stop!(sender_tx, ack_msg, stop_flag);
+                        }
                     } else {
-                        let err_msg = ErrorMessage::new(
-                            ErrorCode::SenderDisconnected,
-                            &format!("sender `{}` is no longer connected", &payload.sender_id),
-                        )
-                        .as_ws_text_message();
+                        let err_msg = sender_disconnected(&payload.sender_id).as_ws_message();
                         send_or_stop!(tx, err_msg, stop_flag);
                     }
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientMismatch,
-                        &format!(
-                            "recipient ID mismatch. Expected `{}`, `{}`",
-                            current_recipient, recipient_id
-                        ),
-                    )
-                    .as_ws_text_message();
+                }
+                Err(err) => {
+                    let err_msg =
+                        unpair_error_message(state, err, &payload.sender_id, &recipient_id)
+                            .as_ws_message();
                     send_or_stop!(tx, err_msg, stop_flag);
                 }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!(
-                        "active connection for sender_id: `{}` not found",
-                        &payload.sender_id
-                    ),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
             }
         }
         RelayIncomingPayload::SenderAck(payload) => {
-            let sender_id = payload.sender_id.unwrap_or(base_conn_id.to_owned());
+            let Some(sender_id) =
+                own_id(state, payload.sender_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
+
+            let paired = state
+                .registry
+                .recipients_of(&sender_id)
+                .contains(&payload.recipient_id);
+            if !check_counterpart(
+                state,
+                paired,
+                &format!(
+                    "recipient `{}` is not paired with sender `{}`",
+                    payload.recipient_id, sender_id
+                ),
+                tx,
+                &stop_flag,
+            )
+            .await
+            {
+                return;
+            }
 
-            if let Some(recipient_tx) = state.get_peer_tx(&payload.recipient_id).await {
+            if !apply_transition(state, &sender_id, TransferEvent::SenderAck, tx, &stop_flag).await
+            {
+                return;
+            }
+            if let Some(recipient_tx) = state.registry.peer_tx(&payload.recipient_id) {
                 let success_msg = SenderAckResponseDto::new(
                     &payload.request_type,
                     &sender_id,
                     &payload.recipient_id,
                     payload.message,
                 )
-                .as_ws_text_message();
+                .as_notification();
                 send_or_stop!(recipient_tx, success_msg, stop_flag);
             } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::RecipientDisconnected,
-                    &format!(
-                        "recipient `{}` is no longer connected",
-                        &payload.recipient_id
-                    ),
-                )
-                .as_ws_text_message();
+                let err_msg = recipient_disconnected(&payload.recipient_id).as_ws_message();
                 send_or_stop!(tx, err_msg, stop_flag);
             }
         }
         RelayIncomingPayload::RestartTransfer => {
             let sender_id = base_conn_id.to_owned();
-            let connected_recipient = state.get_connected_recipient(&sender_id).await;
-
-            if let Some(current_recipient) = connected_recipient {
-                if let Some(recipient_tx) = state.get_peer_tx(&current_recipient).await {
-                    let response_message =
-                        RestartTransferResponseDto::new(&sender_id, &current_recipient)
-                            .as_ws_text_message();
-                    send_or_stop!(recipient_tx, response_message, stop_flag);
-                } else {
-                    let err_msg = ErrorMessage::new(
-                        ErrorCode::RecipientDisconnected,
-                        &format!("recipient `{}` is no longer connected", current_recipient),
-                    )
-                    .as_ws_text_message();
-                    send_or_stop!(tx, err_msg, stop_flag);
-                }
-            } else {
-                let err_msg = ErrorMessage::new(
-                    ErrorCode::ActiveConnectionNotFound,
-                    &format!("active connection for sender_id: `{}` not found", sender_id),
-                )
-                .as_ws_text_message();
-                send_or_stop!(tx, err_msg, stop_flag);
+
+            if !apply_transition(state, &sender_id, TransferEvent::Restart, tx, &stop_flag).await {
+                return;
             }
+            relay_to_recipients(state, &sender_id, tx, &stop_flag, |current_recipient| {
+                RestartTransferResponseDto::new(&sender_id, current_recipient).as_notification()
+            })
+            .await;
         }
         RelayIncomingPayload::UserClose(payload) => {
-            let user_id = payload.user_id.unwrap_or(base_conn_id.to_owned());
+            let Some(user_id) = own_id(state, payload.user_id, base_conn_id, tx, &stop_flag).await
+            else {
+                return;
+            };
             let role = payload.role;
             let reason = payload
                 .reason
@@ -388,7 +929,7 @@ pub async fn handle_text_message_payload(
                 reason: close_reason.into(),
             }));
 
-            send_or_stop!(tx, close_msg, stop_flag);
+            send_or_stop!(tx, close_msg.into(), stop_flag);
         }
         RelayIncomingPayload::Terminate => stop_flag.store(true, Ordering::Relaxed),
         RelayIncomingPayload::Unknown => {
@@ -396,9 +937,609 @@ pub async fn handle_text_message_payload(
                 ErrorCode::UnsupportedWsMessageTextType,
                 "unknown json message type",
             )
-            .as_ws_text_message();
+            .as_ws_message();
 
             send_or_stop!(tx, err_msg, stop_flag);
         }
     }
 }
+
+pub async fn handle_binary_message(
+    bin_data: Bytes,
+    tx: &Sender<Outgoing>,
+    state: &RelayState,
+    base_conn_id: &str,
+    stop_flag: Arc<AtomicBool>,
+    verifier: &mut StreamVerifier,
+) {
+    // Enveloped frames are relayed whole, but only their payload is hashed
+    // and spooled.
+    let (payload, unannounced) = if FrameHeader::is_envelope(&bin_data) {
+        match open_envelope(state, base_conn_id, &bin_data, verifier, tx, &stop_flag).await {
+            Some(opened) => opened,
+            None => return,
+        }
+    } else {
+        (bin_data.clone(), None)
+    };
+
+    if payload.len() > CONFIG.max_chunk_size as usize {
+        let err_msg = limit_exceeded("chunk", payload.len() as u64, CONFIG.max_chunk_size.into())
+            .as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return;
+    }
+
+    if !check_encryption(state, base_conn_id, &bin_data, tx, &stop_flag).await {
+        return;
+    }
+
+    // A corrupt chunk never reaches a recipient. The verifier only takes the
+    // frame in once it is accepted, so one turned away can be sent again.
+    if let Err(err) = verifier.check_chunk(&payload) {
+        report_integrity_mismatch(state, base_conn_id, &err, tx, &stop_flag).await;
+        return;
+    }
+
+    if let Some(spool) = &state.spool
+        && spool.is_uploading(base_conn_id)
+    {
+        let reply = match spool.append_chunk(base_conn_id, &payload).await {
+            // The relay acknowledges spooled chunks itself so the sender keeps
+            // going at disk speed.
+            Ok(info) => {
+                commit_frame(verifier, unannounced, &payload);
+                FileTransferAckResponseDto::new(
+                    SPOOL_PEER_ID,
+                    base_conn_id,
+                    "acknowledged",
+                    &info.file_name,
+                    info.total_chunks,
+                    info.uploaded_size,
+                    info.chunk_index,
+                    info.chunk_data_size,
+                    info.sender_transfer_progress,
+                )
+                .with_file_index(info.file_index)
+                .as_ws_message()
+            }
+            Err(err) => spool_error_message(&err).as_ws_message(),
+        };
+        send_or_stop!(tx, reply, stop_flag);
+        return;
+    }
+
+    if !apply_transition(state, base_conn_id, TransferEvent::Chunk, tx, &stop_flag).await {
+        return;
+    }
+
+    if state.registry.recipients_of(base_conn_id).is_empty() {
+        tracing::warn!(
+            "sender `{}` attempted to send a file, but no recipient is connected",
+            base_conn_id
+        );
+    }
+
+    let pause = match state
+        .registry
+        .admit_chunk(base_conn_id, payload.len() as u32)
+    {
+        Admission::Forward { pause } => pause,
+        Admission::Blocked(status) => {
+            let err_msg = ErrorMessage::new(
+                ErrorCode::FlowControlBlocked,
+                "recipient has no room for more chunks, wait for `resume`",
+            )
+            .with_details(&format!(
+                "credits: {:?}, queued bytes: {}",
+                status.credits, status.queued_bytes
+            ))
+            .as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+            return;
+        }
+    };
+
+    commit_frame(verifier, unannounced, &payload);
+    relay_to_recipients(state, base_conn_id, tx, &stop_flag, |_| {
+        Message::binary(bin_data.clone()).into()
+    })
+    .await;
+
+    if let Some(status) = pause {
+        let pause_msg =
+            RelayOutgoingPayload::Pause(FlowControlResponseDto::new(base_conn_id, status))
+                .as_ws_message();
+        send_or_stop!(tx, pause_msg, stop_flag);
+    }
+}
+
+/// Validates the header of an enveloped frame against the sending connection,
+/// the announced share and any `fileChunk` message sent for the same chunk.
+/// Returns the payload, along with the file and chunk index if no
+/// `fileChunk` announced it, or `None` after replying with `InvalidFrame`.
+async fn open_envelope(
+    state: &RelayState,
+    sender_id: &str,
+    frame: &Bytes,
+    verifier: &StreamVerifier,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> Option<(Bytes, Option<(u32, u32)>)> {
+    let invalid_frame = |details: String| {
+        ErrorMessage::new(ErrorCode::InvalidFrame, "invalid binary frame")
+            .with_details(&details)
+            .as_ws_message()
+    };
+
+    let header = match FrameHeader::parse(frame) {
+        Ok((header, _)) => header,
+        Err(err) => {
+            send_or_stop!(tx, invalid_frame(err.to_string()), stop_flag);
+            return None;
+        }
+    };
+    if header.session_id != sender_id {
+        let details = format!(
+            "session id `{}` does not belong to sender `{}`",
+            header.session_id, sender_id
+        );
+        send_or_stop!(tx, invalid_frame(details), stop_flag);
+        return None;
+    }
+    if !check_file_index(state, sender_id, Some(header.file_index), tx, stop_flag).await {
+        return None;
+    }
+
+    let unannounced = match verifier.pending_frame() {
+        Some(announced) if announced != (header.file_index, header.chunk_index) => {
+            let details = format!(
+                "header is for chunk {} of file {}, but `fileChunk` announced chunk {} of file {}",
+                header.chunk_index, header.file_index, announced.1, announced.0
+            );
+            send_or_stop!(tx, invalid_frame(details), stop_flag);
+            return None;
+        }
+        Some(_) => None,
+        None => Some((header.file_index, header.chunk_index)),
+    };
+
+    let payload = frame.slice(frame.len() - header.length as usize..);
+    Some((payload, unannounced))
+}
+
+/// Hands an accepted frame to the verifier, first standing in for the
+/// `fileChunk` message if its envelope was not announced by one.
+fn commit_frame(verifier: &mut StreamVerifier, unannounced: Option<(u32, u32)>, payload: &[u8]) {
+    if let Some((file_index, chunk_index)) = unannounced {
+        verifier.expect_chunk(Some(file_index), chunk_index, None);
+    }
+    verifier.commit_chunk(payload);
+}
+
+/// Applies `event` to the session of `sender_id`, replying with
+/// `InvalidStateTransition` when the session does not allow it.
+pub async fn apply_transition(
+    state: &RelayState,
+    sender_id: &str,
+    event: TransferEvent,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> bool {
+    match state.registry.transition(sender_id, event) {
+        Ok(_) => true,
+        Err(err) => {
+            let err_msg = ErrorMessage::new(ErrorCode::InvalidStateTransition, &err.to_string())
+                .as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+            false
+        }
+    }
+}
+
+/// How a sender asked for its share to be handled in `fileMeta` or
+/// `fileManifest`.
+struct ShareOptions {
+    mode: ShareMode,
+    store_and_forward: bool,
+    e2ee: bool,
+}
+
+/// Announces a share in the registry and, for store-and-forward shares,
+/// starts the spool upload. Returns `false` after replying with an error.
+async fn announce_share(
+    state: &RelayState,
+    sender_id: &str,
+    shared_files: SharedFiles,
+    options: ShareOptions,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> bool {
+    if options.e2ee && options.store_and_forward {
+        // The spool would have to hold the recipient's key before the
+        // recipient exists.
+        let err_msg = ErrorMessage::new(
+            ErrorCode::InvalidPayload,
+            "end-to-end encrypted shares cannot be stored and forwarded",
+        )
+        .as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return false;
+    }
+
+    // Only the connection that stored a share may replace it, whether with
+    // another stored share or a live one.
+    if let Some(spool) = &state.spool
+        && spool.is_left_behind(sender_id)
+    {
+        let err_msg = spool_error_message(&SpoolError::LeftBehind).as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return false;
+    }
+
+    if options.store_and_forward {
+        let Some(spool) = &state.spool else {
+            let err_msg = ErrorMessage::new(
+                ErrorCode::SpoolUnavailable,
+                "store-and-forward is not enabled on this relay",
+            )
+            .as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+            return false;
+        };
+        if let Err(err) = spool.begin_upload(sender_id, shared_files.clone()).await {
+            let err_msg = spool_error_message(&err).as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+            return false;
+        }
+    }
+
+    if let Err(err) = state
+        .registry
+        .announce(sender_id, shared_files, options.mode, options.e2ee)
+    {
+        let err_msg =
+            ErrorMessage::new(ErrorCode::InvalidStateTransition, &err.to_string()).as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return false;
+    }
+    true
+}
+
+fn spool_error_message(err: &SpoolError) -> ErrorMessage {
+    let code = match err {
+        SpoolError::QuotaExceeded { .. } => ErrorCode::SpoolQuotaExceeded,
+        SpoolError::LeftBehind => ErrorCode::Forbidden,
+        SpoolError::MissingChunkInfo => ErrorCode::InvalidPayload,
+        SpoolError::NotUploading | SpoolError::Io(_) => ErrorCode::SpoolUnavailable,
+    };
+    ErrorMessage::new(code, &err.to_string())
+}
+
+/// Rejects files larger than `MAX_FILE_SIZE_BYTES` with `limitExceeded`.
+async fn check_file_size(size: u64, tx: &Sender<Outgoing>, stop_flag: &Arc<AtomicBool>) -> bool {
+    if size <= CONFIG.max_file_size {
+        return true;
+    }
+
+    let err_msg = limit_exceeded("file", size, CONFIG.max_file_size).as_ws_message();
+    send_or_stop!(tx, err_msg, stop_flag);
+    false
+}
+
+/// Rejects a `fileIndex` that does not point into the sender's announced
+/// files. Messages without one are left alone.
+async fn check_file_index(
+    state: &RelayState,
+    sender_id: &str,
+    file_index: Option<u32>,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> bool {
+    let Some(file_index) = file_index else {
+        return true;
+    };
+    let file_count = state.registry.file_count(sender_id).unwrap_or_default();
+    if (file_index as usize) < file_count {
+        return true;
+    }
+
+    let err_msg = ErrorMessage::new(
+        ErrorCode::InvalidPayload,
+        &format!(
+            "file index {} is out of range: sender `{}` announced {} file(s)",
+            file_index, sender_id, file_count
+        ),
+    )
+    .with_context(ErrorContext {
+        sender_id: Some(sender_id.to_owned()),
+        file_index: Some(file_index),
+        limit: Some(file_count as u64),
+        ..Default::default()
+    })
+    .as_ws_message();
+    send_or_stop!(tx, err_msg, stop_flag);
+    false
+}
+
+/// Sends a message built by `build` to every recipient paired with
+/// `sender_id`, reporting missing recipients back on `tx`.
+async fn relay_to_recipients(
+    state: &RelayState,
+    sender_id: &str,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+    build: impl Fn(&str) -> Outgoing,
+) {
+    let recipients = state.registry.recipients_of(sender_id);
+
+    if recipients.is_empty() {
+        let err_msg = connection_not_found(sender_id).as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return;
+    }
+
+    for current_recipient in recipients {
+        // A recipient whose socket is going away must not take the sender
+        // down with it; its own cleanup unpairs it.
+        let delivered = match state.registry.peer_tx(&current_recipient) {
+            Some(recipient_tx) => recipient_tx.send(build(&current_recipient)).await.is_ok(),
+            None => false,
+        };
+        if !delivered {
+            let err_msg = recipient_disconnected(&current_recipient).as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+        }
+    }
+}
+
+fn invalid_hash_message(err: &IntegrityError) -> ErrorMessage {
+    ErrorMessage::new(ErrorCode::InvalidPayload, "invalid SHA-256 digest")
+        .with_details(&err.to_string())
+}
+
+/// On an `e2ee` share, refuses frames that are not encrypted envelopes, and
+/// any frame while a recipient is still missing keys.
+async fn check_encryption(
+    state: &RelayState,
+    sender_id: &str,
+    frame: &Bytes,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> bool {
+    if !state.registry.is_e2ee(sender_id) {
+        return true;
+    }
+
+    let encrypted =
+        FrameHeader::parse(frame).is_ok_and(|(header, _)| header.flags & FLAG_ENCRYPTED != 0);
+    if !encrypted {
+        let err_msg = ErrorMessage::new(
+            ErrorCode::EncryptionRequired,
+            "this share is end-to-end encrypted, send chunks as encrypted envelopes",
+        )
+        .as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return false;
+    }
+
+    if let Some(recipient_id) = state.registry.awaiting_keys(sender_id).into_iter().next() {
+        let err_msg = ErrorMessage::new(
+            ErrorCode::KeyExchangeIncomplete,
+            &format!(
+                "recipient `{}` has not finished the key exchange",
+                recipient_id
+            ),
+        )
+        .with_context(ErrorContext {
+            sender_id: Some(sender_id.to_owned()),
+            recipient_id: Some(recipient_id),
+            ..Default::default()
+        })
+        .as_ws_message();
+        send_or_stop!(tx, err_msg, stop_flag);
+        return false;
+    }
+    true
+}
+
+/// Tells the sender and every recipient of its share that the data failed
+/// verification. A spool upload is dropped, since it can no longer be served.
+async fn report_integrity_mismatch(
+    state: &RelayState,
+    sender_id: &str,
+    err: &IntegrityError,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) {
+    tracing::warn!(sender_id, error = %err, "integrity check failed");
+    if let Some(spool) = &state.spool {
+        spool.abandon_upload(sender_id).await;
+    }
+
+    let (file_index, chunk_index) = match *err {
+        IntegrityError::ChunkMismatch {
+            file_index,
+            chunk_index,
+        } => (file_index, Some(chunk_index)),
+        IntegrityError::FileMismatch { file_index } => (file_index, None),
+        IntegrityError::InvalidHash(_) => (None, None),
+    };
+    let err_msg = ErrorMessage::new(ErrorCode::IntegrityMismatch, "data failed verification")
+        .with_details(&err.to_string())
+        .with_context(ErrorContext {
+            sender_id: Some(sender_id.to_owned()),
+            file_index: Some(file_index.unwrap_or_default()),
+            chunk_index,
+            ..Default::default()
+        });
+    for recipient_id in state.registry.recipients_of(sender_id) {
+        if let Some(recipient_tx) = state.registry.peer_tx(&recipient_id) {
+            let _ = recipient_tx.send(err_msg.as_notification()).await;
+        }
+    }
+    send_or_stop!(tx, err_msg.as_ws_message(), stop_flag);
+}
+
+/// Under [`IdentityPolicy::Strict`], a recipient that is not paired with
+/// `sender_id` gets `Forbidden` without learning who is.
+fn unpair_error_message(
+    state: &RelayState,
+    err: UnpairError,
+    sender_id: &str,
+    recipient_id: &str,
+) -> ErrorMessage {
+    match err {
+        UnpairError::NotPaired => connection_not_found(sender_id),
+        UnpairError::RecipientMismatch { .. }
+            if state.identity_policy == IdentityPolicy::Strict =>
+        {
+            forbidden(&format!(
+                "recipient `{}` is not paired with sender `{}`",
+                recipient_id, sender_id
+            ))
+        }
+        UnpairError::RecipientMismatch { current_recipient } => ErrorMessage::new(
+            ErrorCode::RecipientMismatch,
+            &format!(
+                "recipient ID mismatch. expected `{}`, got `{}`",
+                current_recipient, recipient_id
+            ),
+        )
+        .with_context(ErrorContext {
+            sender_id: Some(sender_id.to_owned()),
+            expected_recipient_id: Some(current_recipient),
+            actual_recipient_id: Some(recipient_id.to_owned()),
+            ..Default::default()
+        }),
+        UnpairError::InvalidState(err) => {
+            ErrorMessage::new(ErrorCode::InvalidStateTransition, &err.to_string())
+        }
+    }
+}
+
+fn sender_disconnected(sender_id: &str) -> ErrorMessage {
+    ErrorMessage::new(
+        ErrorCode::SenderDisconnected,
+        &format!("sender `{}` is no longer connected", sender_id),
+    )
+    .with_context(ErrorContext {
+        sender_id: Some(sender_id.to_owned()),
+        ..Default::default()
+    })
+}
+
+fn recipient_disconnected(recipient_id: &str) -> ErrorMessage {
+    ErrorMessage::new(
+        ErrorCode::RecipientDisconnected,
+        &format!("recipient `{}` is no longer connected", recipient_id),
+    )
+    .with_context(ErrorContext {
+        recipient_id: Some(recipient_id.to_owned()),
+        ..Default::default()
+    })
+}
+
+fn connection_not_found(sender_id: &str) -> ErrorMessage {
+    ErrorMessage::new(
+        ErrorCode::ActiveConnectionNotFound,
+        &format!("active connection for sender_id: `{}` not found", sender_id),
+    )
+    .with_context(ErrorContext {
+        sender_id: Some(sender_id.to_owned()),
+        ..Default::default()
+    })
+}
+
+/// `LimitExceeded` for a `what` of `actual` bytes, over `limit`.
+fn limit_exceeded(what: &str, actual: u64, limit: u64) -> ErrorMessage {
+    ErrorMessage::new(ErrorCode::LimitExceeded, &format!("{what} is too large"))
+        .with_details(&format!("{what} has {actual} bytes, the limit is {limit}"))
+        .with_context(ErrorContext {
+            limit: Some(limit),
+            actual: Some(actual),
+            ..Default::default()
+        })
+}
+
+/// Resolves the peer ID a payload acts as under the configured
+/// [`IdentityPolicy`], replying with `Forbidden` when it names another peer.
+async fn own_id(
+    state: &RelayState,
+    claimed: Option<String>,
+    base_conn_id: &str,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> Option<String> {
+    match state.identity_policy.resolve(claimed, base_conn_id) {
+        Ok(peer_id) => {
+            if peer_id != base_conn_id {
+                tracing::warn!(base_conn_id, peer_id, "payload acts for another peer");
+            }
+            Some(peer_id)
+        }
+        Err(claimed) => {
+            let err_msg = forbidden(&format!(
+                "connection `{}` cannot act as peer `{}`",
+                base_conn_id, claimed
+            ))
+            .as_ws_message();
+            send_or_stop!(tx, err_msg, stop_flag);
+            None
+        }
+    }
+}
+
+/// Under [`IdentityPolicy::Strict`], replies with `Forbidden` unless the
+/// peer a payload addresses is `paired` with the connection's peer.
+async fn check_counterpart(
+    state: &RelayState,
+    paired: bool,
+    message: &str,
+    tx: &Sender<Outgoing>,
+    stop_flag: &Arc<AtomicBool>,
+) -> bool {
+    if paired || state.identity_policy == IdentityPolicy::Compat {
+        return true;
+    }
+    let err_msg = forbidden(message).as_ws_message();
+    send_or_stop!(tx, err_msg, stop_flag);
+    false
+}
+
+fn forbidden(message: &str) -> ErrorMessage {
+    ErrorMessage::new(ErrorCode::Forbidden, message)
+}
+
+fn passphrase_error_message(err: &PassphraseError) -> ErrorMessage {
+    let code = match err {
+        PassphraseError::Required => ErrorCode::PassphraseRequired,
+        PassphraseError::Wrong => ErrorCode::WrongPassphrase,
+        PassphraseError::LockedOut { .. } => ErrorCode::PassphraseLockedOut,
+        PassphraseError::Hash(_) => ErrorCode::Unknown,
+    };
+    let message = ErrorMessage::new(code, &err.to_string());
+    match err {
+        PassphraseError::LockedOut { retry_after } => message.with_context(ErrorContext {
+            retry_after_secs: Some(retry_after.as_secs().max(1)),
+            ..Default::default()
+        }),
+        _ => message,
+    }
+}
+
+fn pairing_error_message(err: &PairingError) -> ErrorMessage {
+    match err {
+        PairingError::InvalidCode => {
+            ErrorMessage::new(ErrorCode::InvalidPairingCode, &err.to_string())
+        }
+        PairingError::Throttled { retry_after } => {
+            ErrorMessage::new(ErrorCode::PairingThrottled, &err.to_string()).with_context(
+                ErrorContext {
+                    retry_after_secs: Some(retry_after.as_secs().max(1)),
+                    ..Default::default()
+                },
+            )
+        }
+    }
+}
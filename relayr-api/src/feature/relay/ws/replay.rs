@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use axum::extract::ws::Message;
+use tokio::{sync::mpsc::Sender, task::JoinHandle};
+
+use crate::feature::relay::{
+    codec::Outgoing,
+    error::{ErrorCode, ErrorMessage},
+    flow::FlowWindow,
+    spool::{ReplayEvent, ReplayEvents, Spool, SpooledFrame},
+    ws::dto::response::{
+        AsWsMessage, FileChunkResponseDto, FileEndResponseDto, SenderAckResponseDto,
+    },
+};
+
+/// Serves a stored share to `recipient_id` with the same messages a live
+/// sender would produce: `senderAck`, then `fileChunk` + binary frame pairs
+/// and `fileEnd`. Chunks are paced by the recipient's acks and credits the
+/// same way a live sender is, with at most `max_queued_bytes` in flight.
+pub fn spawn_spool_replay(
+    spool: Arc<Spool>,
+    sender_id: String,
+    recipient_id: String,
+    tx: Sender<Outgoing>,
+    max_queued_bytes: u64,
+) -> JoinHandle<()> {
+    // Registered before the task starts, so credits granted right after
+    // `recipientReady` are not lost.
+    let mut events = spool.begin_replay(&sender_id, &recipient_id);
+    tokio::spawn(async move {
+        replay(
+            &spool,
+            &sender_id,
+            &recipient_id,
+            &tx,
+            &mut events,
+            max_queued_bytes,
+        )
+        .await;
+        spool.end_replay(&events);
+    })
+}
+
+async fn replay(
+    spool: &Spool,
+    sender_id: &str,
+    recipient_id: &str,
+    tx: &Sender<Outgoing>,
+    events: &mut ReplayEvents,
+    max_queued_bytes: u64,
+) {
+    let Some(frames) = spool.frames(sender_id) else {
+        return;
+    };
+    let mut window = FlowWindow::default();
+
+    let ready_msg =
+        SenderAckResponseDto::new("recipientReady", sender_id, recipient_id, None).as_ws_message();
+    if tx.send(ready_msg).await.is_err() {
+        return;
+    }
+
+    for frame in frames {
+        let messages = match frame {
+            SpooledFrame::Chunk(chunk) => {
+                let len = chunk.data_len();
+                if !wait_for_room(&mut window, len, max_queued_bytes, events, tx).await {
+                    return;
+                }
+                let data = match spool.read_chunk(sender_id, &chunk).await {
+                    Ok(data) => data,
+                    Err(err) => {
+                        tracing::warn!(sender_id, recipient_id, error = %err, "spool replay failed");
+                        let err_msg = ErrorMessage::new(
+                            ErrorCode::SpoolUnavailable,
+                            "the stored share is no longer available",
+                        )
+                        .as_ws_message();
+                        let _ = tx.send(err_msg).await;
+                        return;
+                    }
+                };
+                window.record_sent(len);
+                let info = chunk.info;
+                let chunk_msg = FileChunkResponseDto::new(
+                    sender_id,
+                    recipient_id,
+                    &info.file_name,
+                    info.total_size,
+                    info.total_chunks,
+                    info.uploaded_size,
+                    info.chunk_index,
+                    info.chunk_data_size,
+                    info.sender_transfer_progress,
+                )
+                .with_file_index(info.file_index)
+                .with_chunk_sha256(info.chunk_sha256)
+                .as_ws_message();
+                vec![chunk_msg, Message::Binary(data.into()).into()]
+            }
+            SpooledFrame::End(info) => vec![
+                FileEndResponseDto::new(
+                    sender_id,
+                    recipient_id,
+                    &info.file_name,
+                    info.total_size,
+                    info.total_chunks,
+                    info.uploaded_size,
+                    info.last_chunk_index,
+                )
+                .with_file_index(info.file_index)
+                .with_sha256(info.sha256)
+                .as_ws_message(),
+            ],
+        };
+
+        for msg in messages {
+            if tx.send(msg).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Waits until the recipient has room for a chunk of `len` bytes. Returns
+// false if the recipient went away or a newer replay took over.
+async fn wait_for_room(
+    window: &mut FlowWindow,
+    len: u32,
+    max_queued_bytes: u64,
+    events: &mut ReplayEvents,
+    tx: &Sender<Outgoing>,
+) -> bool {
+    while let Some(event) = events.try_recv() {
+        apply(window, event);
+    }
+    while !window.admits(len, max_queued_bytes) {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = tx.closed() => None,
+        };
+        match event {
+            Some(event) => apply(window, event),
+            None => return false,
+        }
+    }
+    true
+}
+
+fn apply(window: &mut FlowWindow, event: ReplayEvent) {
+    match event {
+        ReplayEvent::Acked => window.record_acked(),
+        ReplayEvent::Credits(credits) => window.grant(credits),
+    }
+}
@@ -1,36 +1,94 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use axum::extract::ws::WebSocket;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
 use futures::StreamExt;
 use tokio::{
     sync::{Mutex, mpsc},
     time::Instant,
 };
 
-use crate::feature::relay::{
-    state::RelayState,
-    ws::{
-        peer_disconnect, ping::spawn_ping_task, read::spawn_read_task,
-        task_manager::wait_socket_tasks, write::spawn_write_task,
+use crate::{
+    config::CONFIG,
+    feature::relay::{
+        codec::{OutgoingEncoder, WireFormat},
+        error::{ErrorCode, ErrorMessage},
+        registry::RegisterError,
+        state::RelayState,
+        ws::{
+            dto::response::AsWsMessage, peer_disconnect, ping::spawn_ping_task,
+            read::spawn_read_task, task_manager::wait_socket_tasks, write::spawn_write_task,
+        },
     },
 };
 
-pub async fn handle_socket(socket: WebSocket, state: RelayState, peer_id: String) {
+pub async fn handle_socket(
+    mut socket: WebSocket,
+    state: RelayState,
+    requested_id: Option<String>,
+    client_ip: IpAddr,
+) {
     let (tx, rx) = mpsc::channel(100);
-    state.add_peer_connection(&peer_id, tx.clone()).await;
+    let wire_format = socket
+        .protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(WireFormat::from_subprotocol)
+        .unwrap_or_default();
+
+    let registration = match state.registry.register_peer(
+        requested_id.as_deref(),
+        tx.clone(),
+        CONFIG.peer_id_policy,
+    ) {
+        Ok(registration) => registration,
+        Err(RegisterError::PeerIdTaken) => {
+            let err_msg = ErrorMessage::new(
+                ErrorCode::PeerIdTaken,
+                &format!(
+                    "peer id `{}` is already connected",
+                    requested_id.unwrap_or_default()
+                ),
+            )
+            .as_ws_message();
+            let _ = socket
+                .send(OutgoingEncoder::new(wire_format).encode(err_msg))
+                .await;
+            let _ = socket
+                .send(Message::Close(Some(CloseFrame {
+                    code: 1008,
+                    reason: "peer id is already connected".into(),
+                })))
+                .await;
+            return;
+        }
+    };
+
+    if let Some(replaced_tx) = registration.replaced {
+        let close_msg = Message::Close(Some(CloseFrame {
+            code: 1008,
+            reason: "peer id was taken over by a new connection".into(),
+        }));
+        let _ = replaced_tx.send(close_msg.into()).await;
+        // The new connection does not own what the old one stored.
+        if let Some(spool) = &state.spool {
+            spool.leave_behind(&registration.peer_id);
+        }
+    }
+
+    let peer_id = registration.peer_id;
     let (write, read) = socket.split();
     let last_heartbeat = Arc::new(Mutex::new(Instant::now()));
 
     let ping_task = spawn_ping_task(tx.clone(), last_heartbeat.clone());
     let read_task = spawn_read_task(
         read,
-        tx,
+        tx.clone(),
         state.clone(),
         peer_id.clone(),
+        client_ip,
+        wire_format,
         last_heartbeat.clone(),
     );
-    let write_task = spawn_write_task(write, rx);
+    let write_task = spawn_write_task(write, rx, wire_format);
     let disconnect_reason = wait_socket_tasks(ping_task, read_task, write_task).await;
-    peer_disconnect::notify_peers_on_disconnect(&state, &peer_id, disconnect_reason).await;
-    peer_disconnect::cleanup_peer_state(&state, &peer_id).await;
+    peer_disconnect::disconnect_peer(&state, &peer_id, &tx, disconnect_reason).await;
 }
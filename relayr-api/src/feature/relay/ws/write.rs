@@ -2,13 +2,17 @@ use axum::extract::ws::{Message, WebSocket};
 use futures::{SinkExt, stream::SplitSink};
 use tokio::{sync::mpsc::Receiver, task::JoinHandle};
 
+use crate::feature::relay::codec::{Outgoing, OutgoingEncoder, WireFormat};
+
 pub fn spawn_write_task(
     mut write: SplitSink<WebSocket, Message>,
-    mut rx: Receiver<Message>,
+    mut rx: Receiver<Outgoing>,
+    wire_format: WireFormat,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
+        let mut encoder = OutgoingEncoder::new(wire_format);
         while let Some(msg) = rx.recv().await {
-            if write.send(msg).await.is_err() {
+            if write.send(encoder.encode(msg)).await.is_err() {
                 break;
             }
         }
@@ -5,10 +5,13 @@ use tracing_subscriber::layer::SubscriberExt;
 #[allow(unused)]
 use tracing_subscriber::util::SubscriberInitExt;
 
+use axum::http::{HeaderName, header};
 use dotenv::dotenv;
 use tower_http::cors::{Any, CorsLayer};
 
-use relayr_api::{config::CONFIG, routes::app_routes};
+use relayr_api::{
+    config::CONFIG, feature::relay::passphrase::PASSPHRASE_HEADER, routes::app_routes,
+};
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
@@ -36,9 +39,16 @@ async fn main() -> std::io::Result<()> {
     let tcp_listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!("listening on https://{}", addr);
 
-    let cors = CorsLayer::new().allow_origin(Any);
+    let cors = CorsLayer::new().allow_origin(Any).allow_headers([
+        HeaderName::from_static(PASSPHRASE_HEADER),
+        header::AUTHORIZATION,
+    ]);
 
     let app = app_routes().layer(cors);
 
-    axum::serve(tcp_listener, app).await
+    axum::serve(
+        tcp_listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
 }
@@ -0,0 +1,310 @@
+mod common;
+
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::ws::Message,
+    http::{HeaderMap, HeaderValue, header},
+};
+use common::{Share, ShareBuilder, file_metadata, next_payload};
+use relayr_api::feature::relay::{
+    admin::{
+        AdminActionError, AdminAuthError, Page, PeerQuery, SessionQuery, authorize, cancel_pairing,
+        kick_peer, list_peers, list_sessions, purge_share,
+    },
+    codec::Outgoing,
+    pairing::{PairingCodeStyle, PairingError},
+    registry::PeerRole,
+    session::TransferState,
+    spool::{ChunkInfo, FileEndInfo, Spool},
+    state::RelayState,
+    ws::dto::response::RelayOutgoingPayload,
+};
+
+fn bearer(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+    );
+    headers
+}
+
+// `sender` is transferring a 300-byte file to `recipient`.
+fn paired_share() -> Share {
+    ShareBuilder::new(&["recipient"])
+        .file_size(300)
+        .transferring()
+        .build()
+}
+
+// `recipient` has acknowledged one of two relayed chunks; `idle` is just
+// connected.
+fn share_in_progress() -> Share {
+    let share = ShareBuilder::new(&["recipient"])
+        .idle(&["idle"])
+        .file_size(300)
+        .transferring()
+        .build();
+    let registry = share.registry();
+    registry.admit_chunk("sender", 100);
+    registry
+        .record_ack(
+            "sender",
+            "recipient",
+            0,
+            100,
+            "acknowledged",
+            Message::Text("ack".into()).into(),
+        )
+        .unwrap();
+    registry.admit_chunk("sender", 100);
+    share
+}
+
+#[test]
+fn admin_requests_need_the_configured_token() {
+    assert_eq!(
+        authorize(None, &bearer("secret")),
+        Err(AdminAuthError::Disabled)
+    );
+    assert_eq!(
+        authorize(Some("secret"), &HeaderMap::new()),
+        Err(AdminAuthError::MissingToken)
+    );
+    assert_eq!(
+        authorize(Some("secret"), &bearer("secret2")),
+        Err(AdminAuthError::WrongToken)
+    );
+    assert_eq!(authorize(Some("secret"), &bearer("secret")), Ok(()));
+}
+
+#[test]
+fn pages_report_the_total_and_cap_the_limit() {
+    let page = Page::new((0..10).collect(), Some(8), Some(5));
+    assert_eq!((page.items, page.total, page.limit), (vec![8, 9], 10, 5));
+
+    let page = Page::new(vec![0; 1000], None, Some(10_000));
+    assert_eq!((page.items.len(), page.offset, page.limit), (500, 0, 500));
+}
+
+#[test]
+fn peers_are_listed_with_their_role_and_pairing() {
+    let share = share_in_progress();
+    let registry = share.registry();
+
+    let page = list_peers(registry, &PeerQuery::default());
+    assert_eq!(page.total, 3);
+
+    let senders = list_peers(
+        registry,
+        &PeerQuery {
+            role: Some(PeerRole::Sender),
+            ..Default::default()
+        },
+    );
+    assert_eq!(senders.total, 1);
+    assert_eq!(senders.items[0].recipient_ids, vec!["recipient".to_owned()]);
+
+    let recipients = list_peers(
+        registry,
+        &PeerQuery {
+            peer_id: Some("rec".into()),
+            ..Default::default()
+        },
+    );
+    assert_eq!(recipients.items[0].role, PeerRole::Recipient);
+    assert_eq!(recipients.items[0].sender_id.as_deref(), Some("sender"));
+}
+
+#[test]
+fn sessions_report_progress_and_bytes_relayed() {
+    let state = share_in_progress().state;
+
+    let page = list_sessions(&state, &SessionQuery::default());
+    assert_eq!(page.total, 1);
+    let session = &page.items[0];
+    assert_eq!(session.state, TransferState::Transferring);
+    assert_eq!((session.total_size, session.bytes_relayed), (300, 200));
+    let recipient = &session.recipients[0];
+    assert_eq!(
+        (
+            recipient.chunks_acknowledged,
+            recipient.bytes_acknowledged,
+            recipient.completed
+        ),
+        (1, 100, false)
+    );
+
+    let by_recipient = list_sessions(
+        &state,
+        &SessionQuery {
+            peer_id: Some("recipient".into()),
+            ..Default::default()
+        },
+    );
+    assert_eq!(by_recipient.total, 1);
+    let announced = list_sessions(
+        &state,
+        &SessionQuery {
+            state: Some(TransferState::Announced),
+            ..Default::default()
+        },
+    );
+    assert_eq!(announced.total, 0);
+}
+
+#[tokio::test]
+async fn kicked_peers_are_closed_and_their_counterparts_told() {
+    let mut share = paired_share();
+    let state = share.state.clone();
+
+    let action = kick_peer(&state, "sender", "abuse").await.unwrap();
+    assert_eq!(action.notified, vec!["recipient".to_owned()]);
+    assert!(matches!(
+        share.rx("sender").try_recv(),
+        Ok(Outgoing::Frame(Message::Close(Some(frame)))) if frame.code == 1008 && frame.reason == "abuse"
+    ));
+    assert!(matches!(
+        next_payload(share.rx("recipient")),
+        RelayOutgoingPayload::PeerDisconnected(peer) if peer.peer_id == "sender"
+    ));
+    assert!(state.registry.peer_tx("sender").is_none());
+    assert!(state.registry.shared_files("sender").is_none());
+
+    assert_eq!(
+        kick_peer(&state, "sender", "abuse").await,
+        Err(AdminActionError::PeerNotConnected("sender".into()))
+    );
+}
+
+#[tokio::test]
+async fn kicks_only_report_counterparts_that_were_reached() {
+    let mut share = paired_share();
+    share.close("recipient");
+
+    let action = kick_peer(&share.state, "sender", "abuse").await.unwrap();
+    assert!(action.notified.is_empty());
+}
+
+#[tokio::test]
+async fn cancelled_pairings_tell_both_sides() {
+    let mut share = paired_share();
+    let state = share.state.clone();
+
+    let action = cancel_pairing(&state, "sender", Some("recipient"))
+        .await
+        .unwrap();
+    assert_eq!(
+        action.notified,
+        vec!["recipient".to_owned(), "sender".to_owned()]
+    );
+    assert!(matches!(
+        next_payload(share.rx("recipient")),
+        RelayOutgoingPayload::CancelSenderTransfer(_)
+    ));
+    assert!(matches!(
+        next_payload(share.rx("sender")),
+        RelayOutgoingPayload::PeerDisconnected(peer) if peer.peer_id == "recipient"
+    ));
+    assert_eq!(
+        state.registry.session_state("sender"),
+        Some(TransferState::Announced)
+    );
+    assert!(state.registry.sender_of("recipient").is_none());
+
+    assert_eq!(
+        cancel_pairing(&state, "sender", None).await,
+        Err(AdminActionError::NotPaired("sender".into()))
+    );
+}
+
+#[tokio::test]
+async fn cancelled_pairings_only_report_sides_that_were_reached() {
+    let mut share = paired_share();
+    share.close("sender");
+
+    let action = cancel_pairing(&share.state, "sender", None).await.unwrap();
+    assert_eq!(action.notified, vec!["recipient".to_owned()]);
+}
+
+#[tokio::test]
+async fn purged_shares_are_forgotten() {
+    let mut share = paired_share();
+    let state = share.state.clone();
+    let code = state.pairing.mint("sender", PairingCodeStyle::Digits);
+
+    let action = purge_share(&state, "sender").await.unwrap();
+    assert_eq!(action.notified, vec!["recipient".to_owned()]);
+    assert!(matches!(
+        next_payload(share.rx("recipient")),
+        RelayOutgoingPayload::CancelSenderTransfer(_)
+    ));
+    assert!(state.registry.shared_files("sender").is_none());
+    assert!(state.registry.session_state("sender").is_none());
+    assert!(state.registry.peer_tx("sender").is_some());
+    assert_eq!(
+        state.pairing.redeem(&code, Ipv4Addr::LOCALHOST.into()),
+        Err(PairingError::InvalidCode)
+    );
+
+    assert_eq!(
+        purge_share(&state, "sender").await,
+        Err(AdminActionError::ShareNotFound("sender".into()))
+    );
+}
+
+#[tokio::test]
+async fn stored_shares_are_listed_and_can_be_purged() {
+    let dir = std::env::temp_dir().join(format!("relayr-admin-{}", nanoid::nanoid!()));
+    let spool = Arc::new(Spool::new(dir.clone(), 1024, Duration::from_secs(60)));
+    spool
+        .begin_upload("gone", file_metadata(300))
+        .await
+        .unwrap();
+    spool
+        .set_pending_chunk(
+            "gone",
+            ChunkInfo {
+                file_index: None,
+                file_name: "file.bin".into(),
+                total_size: 300,
+                total_chunks: 1,
+                uploaded_size: 300,
+                chunk_index: 0,
+                chunk_data_size: 300,
+                chunk_sha256: None,
+                sender_transfer_progress: 100,
+            },
+        )
+        .unwrap();
+    spool.append_chunk("gone", &[0; 300]).await.unwrap();
+    spool
+        .end_file(
+            "gone",
+            FileEndInfo {
+                file_index: None,
+                file_name: "file.bin".into(),
+                total_size: 300,
+                total_chunks: 1,
+                uploaded_size: 300,
+                last_chunk_index: 0,
+                sha256: None,
+            },
+        )
+        .unwrap();
+    let state = RelayState {
+        spool: Some(spool),
+        ..RelayState::new()
+    };
+
+    let page = list_sessions(&state, &SessionQuery::default());
+    assert_eq!(page.total, 1);
+    assert_eq!(page.items[0].sender_id, "gone");
+    assert!(page.items[0].stored);
+    assert_eq!(page.items[0].total_size, 300);
+
+    purge_share(&state, "gone").await.unwrap();
+    assert_eq!(list_sessions(&state, &SessionQuery::default()).total, 0);
+    let _ = std::fs::remove_dir_all(dir);
+}
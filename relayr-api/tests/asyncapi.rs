@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use jsonschema::Validator;
+use relayr_api::feature::relay::{
+    asyncapi::asyncapi_document,
+    error::{ErrorCode, ErrorMessage},
+    flow::FlowStatus,
+    protocol::{Capability, Limits, Negotiated},
+    ws::dto::response::{
+        FileChunkResponseDto, FileEndResponseDto, FlowControlResponseDto, HelloResponseDto,
+        RegisterResponseDto, RelayOutgoingMessage, RelayOutgoingPayload, SpoolStoredResponseDto,
+    },
+};
+use serde_json::{Value, json};
+
+fn validator(message: &str) -> Validator {
+    let document = asyncapi_document();
+    let schema = json!({
+        "allOf": [document["components"]["messages"][message]["payload"]],
+        "components": document["components"],
+    });
+    jsonschema::draft7::new(&schema).unwrap()
+}
+
+fn produced(payload: RelayOutgoingPayload) -> Value {
+    serde_json::from_str(&RelayOutgoingMessage::new(payload).to_json()).unwrap()
+}
+
+#[test]
+fn document_describes_the_relay_channel() {
+    let document = asyncapi_document();
+
+    assert_eq!(document["asyncapi"], json!("3.0.0"));
+    assert_eq!(
+        document["channels"]["relay"]["address"],
+        json!("/api/v1/relay")
+    );
+    let schemas = document["components"]["schemas"].as_object().unwrap();
+    for name in ["RelayIncomingMessage", "RelayOutgoingMessage", "ErrorCode"] {
+        assert!(schemas.contains_key(name), "missing schema {name}");
+    }
+}
+
+#[test]
+fn outgoing_schema_accepts_what_the_relay_sends() {
+    let validator = validator("outgoing");
+    let hello = HelloResponseDto::new(
+        Negotiated {
+            version: 2,
+            features: vec![Capability::Broadcast, Capability::Integrity],
+        },
+        Limits {
+            max_chunk_size: 1024,
+            max_file_size: 1 << 40,
+            max_share_recipients: 10,
+        },
+    );
+    let messages = [
+        RegisterResponseDto::new("abc").into(),
+        hello.into(),
+        FileChunkResponseDto::new("sender", "recipient", "a.bin", 10, 1, 10, 0, 10, 100)
+            .with_file_index(Some(1))
+            .into(),
+        FileEndResponseDto::new("sender", "recipient", "a.bin", 10, 1, 10, 0)
+            .with_sha256(Some("00".repeat(32)))
+            .into(),
+        SpoolStoredResponseDto::new("sender", Duration::from_secs(60)).into(),
+        RelayOutgoingPayload::Pause(FlowControlResponseDto::new(
+            "sender",
+            FlowStatus {
+                credits: None,
+                queued_bytes: 42,
+            },
+        )),
+        ErrorMessage::new(ErrorCode::ShareFull, "share is full")
+            .with_details("10 of 10")
+            .into(),
+    ];
+
+    for message in messages {
+        let value = produced(message);
+        let errors: Vec<_> = validator
+            .iter_errors(&value)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{value} was rejected: {errors:?}");
+    }
+}
+
+#[test]
+fn outgoing_schema_rejects_malformed_messages() {
+    let validator = validator("outgoing");
+    let mut register = produced(RegisterResponseDto::new("abc").into());
+    register.as_object_mut().unwrap().remove("connId");
+
+    assert!(!validator.is_valid(&register));
+    assert!(!validator.is_valid(&json!({ "success": true, "type": "nope", "timestamp": 0 })));
+}
+
+#[test]
+fn incoming_schema_accepts_client_messages() {
+    let validator = validator("incoming");
+    let messages = [
+        json!({ "type": "hello", "protocolVersion": 2, "capabilities": ["flowControl"] }),
+        json!({ "type": "recipientReady", "senderId": "sender", "requestId": "r-1" }),
+        json!({
+            "type": "fileMeta",
+            "name": "a.bin",
+            "size": 10,
+            "mimeType": "application/octet-stream",
+            "shareMode": "broadcast",
+        }),
+        json!({ "type": "grantCredits", "senderId": "sender", "credits": 4 }),
+        json!({ "type": "terminate" }),
+    ];
+
+    for message in messages {
+        assert!(validator.is_valid(&message), "{message} was rejected");
+    }
+    assert!(!validator.is_valid(&json!({ "type": "grantCredits", "credits": 4 })));
+}
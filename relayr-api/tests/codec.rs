@@ -0,0 +1,174 @@
+use axum::{
+    Router,
+    extract::ws::{Message, WebSocketUpgrade},
+    routing::any,
+};
+use relayr_api::feature::relay::{
+    codec::{JSON_SUBPROTOCOL, MSGPACK_SUBPROTOCOL, OutgoingEncoder, WireFormat},
+    frame::{FRAME_VERSION, FrameHeader},
+    ws::dto::{
+        request::{RelayIncomingMessage, RelayIncomingPayload},
+        response::{AsWsMessage, FileChunkResponseDto, RegisterResponseDto},
+    },
+};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+fn binary(msg: Message) -> Vec<u8> {
+    match msg {
+        Message::Binary(data) => data.to_vec(),
+        other => panic!("expected a binary frame, got {other:?}"),
+    }
+}
+
+fn file_chunk() -> FileChunkResponseDto {
+    FileChunkResponseDto::new("sender", "recipient", "a.bin", 16, 4, 16, 3, 4, 100)
+        .with_file_index(Some(1))
+}
+
+#[test]
+fn subprotocols_map_to_wire_formats() {
+    assert_eq!(
+        WireFormat::from_subprotocol(JSON_SUBPROTOCOL),
+        Some(WireFormat::Json)
+    );
+    assert_eq!(
+        WireFormat::from_subprotocol(MSGPACK_SUBPROTOCOL),
+        Some(WireFormat::MessagePack)
+    );
+    assert_eq!(WireFormat::from_subprotocol("relayr.xml.v1"), None);
+    assert_eq!(WireFormat::default(), WireFormat::Json);
+}
+
+#[test]
+fn json_sockets_get_control_messages_as_text() {
+    let mut encoder = OutgoingEncoder::new(WireFormat::Json);
+
+    let Message::Text(text) = encoder.encode(RegisterResponseDto::new("abc").as_ws_message())
+    else {
+        panic!("expected a text frame");
+    };
+    let value: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["type"], json!("register"));
+    assert_eq!(value["connId"], json!("abc"));
+}
+
+#[test]
+fn msgpack_sockets_get_the_same_fields_in_msgpack() {
+    let msg = RegisterResponseDto::new("abc").as_ws_message();
+    let Message::Text(text) = OutgoingEncoder::new(WireFormat::Json).encode(msg.clone()) else {
+        panic!("expected a text frame");
+    };
+
+    let encoded = binary(OutgoingEncoder::new(WireFormat::MessagePack).encode(msg));
+    let decoded: Value = WireFormat::MessagePack.decode(&encoded).unwrap();
+    assert_eq!(decoded, serde_json::from_str::<Value>(&text).unwrap());
+}
+
+#[test]
+fn raw_chunks_are_enveloped_for_msgpack_sockets() {
+    let mut encoder = OutgoingEncoder::new(WireFormat::MessagePack);
+    encoder.encode(file_chunk().as_ws_message());
+
+    let frame = binary(encoder.encode(Message::Binary(b"data".to_vec().into()).into()));
+    let (header, payload) = FrameHeader::parse(&frame).unwrap();
+    assert_eq!(
+        header,
+        FrameHeader {
+            version: FRAME_VERSION,
+            flags: 0,
+            session_id: "sender".into(),
+            file_index: 1,
+            chunk_index: 3,
+            length: 4,
+        }
+    );
+    assert_eq!(payload, b"data");
+}
+
+#[test]
+fn json_sockets_get_raw_chunks_unchanged() {
+    let mut encoder = OutgoingEncoder::new(WireFormat::Json);
+    encoder.encode(file_chunk().as_ws_message());
+
+    assert_eq!(
+        binary(encoder.encode(Message::Binary(b"data".to_vec().into()).into())),
+        b"data"
+    );
+}
+
+#[test]
+fn enveloped_chunks_are_forwarded_unchanged() {
+    let mut encoder = OutgoingEncoder::new(WireFormat::MessagePack);
+    let header = FrameHeader {
+        version: FRAME_VERSION,
+        flags: 0,
+        session_id: "sender".into(),
+        file_index: 0,
+        chunk_index: 0,
+        length: 2,
+    };
+    let frame = header.encode(b"ok");
+
+    assert_eq!(
+        binary(encoder.encode(Message::Binary(frame.clone().into()).into())),
+        frame
+    );
+}
+
+#[test]
+fn msgpack_control_messages_decode_with_their_request_id() {
+    let encoded = binary(
+        WireFormat::MessagePack
+            .encode(&json!({
+                "type": "grantCredits",
+                "requestId": "r-1",
+                "senderId": "sender",
+                "credits": 4,
+            }))
+            .unwrap(),
+    );
+
+    let message: RelayIncomingMessage = WireFormat::MessagePack.decode(&encoded).unwrap();
+    assert_eq!(message.request_id.as_deref(), Some("r-1"));
+    assert!(matches!(
+        message.payload,
+        RelayIncomingPayload::GrantCredits(payload) if payload.credits == 4
+    ));
+}
+
+// A client offering both subprotocols gets the first of the relay's it offered.
+#[tokio::test]
+async fn clients_offering_both_subprotocols_get_json() {
+    let app = Router::new().route(
+        "/ws",
+        any(|ws: WebSocketUpgrade| async {
+            ws.protocols(WireFormat::SUBPROTOCOLS)
+                .on_upgrade(|_| async {})
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "GET /ws HTTP/1.1\r\n\
+         Host: {addr}\r\n\
+         Connection: Upgrade\r\n\
+         Upgrade: websocket\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Protocol: {MSGPACK_SUBPROTOCOL}, {JSON_SUBPROTOCOL}\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = vec![0; 1024];
+    let read = stream.read(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response[..read]).to_lowercase();
+    assert!(response.starts_with("http/1.1 101"), "{response}");
+    assert!(response.contains(&format!("sec-websocket-protocol: {JSON_SUBPROTOCOL}")));
+}
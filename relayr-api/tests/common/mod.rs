@@ -0,0 +1,280 @@
+// Each test crate uses its own subset of these helpers.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{body::Bytes, extract::ws::Message};
+use futures::channel::mpsc::{UnboundedSender, unbounded};
+use relayr_api::feature::relay::{
+    codec::{Outgoing, WireFormat},
+    error::ErrorCode,
+    registry::SessionRegistry,
+    session::{ShareMode, TransferEvent},
+    state::RelayState,
+    types::{DisconnectReason, FileMetadata, IdentityPolicy, PeerIdPolicy, SharedFiles},
+    ws::{
+        dto::response::{RelayOutgoingMessage, RelayOutgoingPayload},
+        read::spawn_read_task,
+    },
+};
+use serde_json::{Value, json};
+use tokio::{
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    task::JoinHandle,
+    time::{Instant, timeout},
+};
+
+const MAX_RECIPIENTS: usize = 10;
+
+/// A socket nobody reads from.
+pub fn peer_tx() -> Sender<Outgoing> {
+    mpsc::channel(1).0
+}
+
+pub fn file_metadata(size: u64) -> SharedFiles {
+    SharedFiles::Single(FileMetadata {
+        name: "file.bin".into(),
+        size,
+        mime_type: "application/octet-stream".into(),
+        sha256: None,
+    })
+}
+
+/// A registry with `peer_ids` connected through sockets nobody reads from.
+pub fn registry_with_peers(peer_ids: &[impl AsRef<str>]) -> Arc<SessionRegistry> {
+    let registry = Arc::new(SessionRegistry::new());
+    for peer_id in peer_ids {
+        registry
+            .register_peer(Some(peer_id.as_ref()), peer_tx(), PeerIdPolicy::Reject)
+            .unwrap();
+    }
+    registry
+}
+
+/// The next control message queued on `rx`.
+pub fn next_payload(rx: &mut Receiver<Outgoing>) -> RelayOutgoingPayload {
+    control(rx.try_recv().unwrap()).payload
+}
+
+/// Sets up a relay where `sender` has announced `file.bin` and the given
+/// recipients are paired with it.
+pub struct ShareBuilder {
+    state: RelayState,
+    recipients: Vec<String>,
+    idle: Vec<String>,
+    file_size: u64,
+    mode: ShareMode,
+    e2ee: bool,
+    transferring: bool,
+}
+
+impl ShareBuilder {
+    pub fn new(recipients: &[&str]) -> Self {
+        Self {
+            state: RelayState::new(),
+            recipients: recipients.iter().map(|&id| id.to_owned()).collect(),
+            idle: Vec::new(),
+            file_size: 1024,
+            mode: ShareMode::Single,
+            e2ee: false,
+            transferring: false,
+        }
+    }
+
+    /// Uses `registry` instead of a default one, e.g. for other limits.
+    pub fn registry(mut self, registry: SessionRegistry) -> Self {
+        self.state.registry = Arc::new(registry);
+        self
+    }
+
+    /// Also connects `peer_ids`, without pairing them.
+    pub fn idle(mut self, peer_ids: &[&str]) -> Self {
+        self.idle = peer_ids.iter().map(|&id| id.to_owned()).collect();
+        self
+    }
+
+    pub fn identity_policy(mut self, policy: IdentityPolicy) -> Self {
+        self.state.identity_policy = policy;
+        self
+    }
+
+    pub fn file_size(mut self, file_size: u64) -> Self {
+        self.file_size = file_size;
+        self
+    }
+
+    pub fn broadcast(mut self) -> Self {
+        self.mode = ShareMode::Broadcast;
+        self
+    }
+
+    pub fn e2ee(mut self) -> Self {
+        self.e2ee = true;
+        self
+    }
+
+    /// Moves the session on to `transferring`, as a first chunk would.
+    pub fn transferring(mut self) -> Self {
+        self.transferring = true;
+        self
+    }
+
+    pub fn build(self) -> Share {
+        let registry = &self.state.registry;
+        let mut receivers = HashMap::new();
+        for peer_id in ["sender".to_owned()]
+            .iter()
+            .chain(&self.recipients)
+            .chain(&self.idle)
+        {
+            let (tx, rx) = mpsc::channel(64);
+            registry
+                .register_peer(Some(peer_id), tx, PeerIdPolicy::Reject)
+                .unwrap();
+            receivers.insert(peer_id.clone(), rx);
+        }
+
+        registry
+            .announce(
+                "sender",
+                file_metadata(self.file_size),
+                self.mode,
+                self.e2ee,
+            )
+            .unwrap();
+        for recipient_id in &self.recipients {
+            registry
+                .pair("sender", recipient_id, MAX_RECIPIENTS)
+                .unwrap();
+        }
+        if self.transferring {
+            registry.transition("sender", TransferEvent::Chunk).unwrap();
+        }
+
+        Share {
+            state: self.state,
+            receivers,
+        }
+    }
+}
+
+/// A relay set up by [`ShareBuilder`], with the receiving end of every
+/// peer's socket.
+pub struct Share {
+    pub state: RelayState,
+    receivers: HashMap<String, Receiver<Outgoing>>,
+}
+
+impl Share {
+    pub fn registry(&self) -> &SessionRegistry {
+        &self.state.registry
+    }
+
+    /// What the relay has sent to `peer_id`.
+    pub fn rx(&mut self, peer_id: &str) -> &mut Receiver<Outgoing> {
+        self.receivers.get_mut(peer_id).unwrap()
+    }
+
+    /// Closes the receiving end of `peer_id`'s socket, so sends to it fail.
+    pub fn close(&mut self, peer_id: &str) {
+        self.receivers.remove(peer_id);
+    }
+
+    /// Starts reading from `peer_id`'s socket as the relay does once it
+    /// connects, and skips the `register` message.
+    pub async fn connect(&mut self, peer_id: &str) -> Client {
+        let (incoming, read) = unbounded();
+        let task = spawn_read_task(
+            read,
+            self.registry().peer_tx(peer_id).unwrap(),
+            self.state.clone(),
+            peer_id.to_owned(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            WireFormat::Json,
+            Arc::new(Mutex::new(Instant::now())),
+        );
+        let registered = self.rx(peer_id).recv().await.unwrap();
+        assert!(matches!(
+            control(registered).payload,
+            RelayOutgoingPayload::Register(_)
+        ));
+        Client {
+            peer_id: peer_id.to_owned(),
+            incoming,
+            task,
+        }
+    }
+
+    /// Everything the relay sent `client` while handling what it sent so
+    /// far. Whatever that made the relay send other peers is queued by the
+    /// time this returns.
+    pub async fn replies(&mut self, client: &Client) -> Vec<Outgoing> {
+        // The relay answers an unknown message type with an error, after
+        // everything sent before it.
+        client.send(json!({ "type": "settle", "requestId": "settle" }));
+        let rx = self.rx(&client.peer_id);
+        let mut replies = Vec::new();
+        loop {
+            let outgoing = timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("the relay did not reply")
+                .expect("the socket was closed");
+            if let Outgoing::Control(msg) = &outgoing
+                && msg.request_id.as_deref() == Some("settle")
+            {
+                return replies;
+            }
+            replies.push(outgoing);
+        }
+    }
+}
+
+/// A client socket driven by a test. What it sends goes through the relay's
+/// read loop; what the relay sends it is on [`Share::rx`].
+pub struct Client {
+    peer_id: String,
+    incoming: UnboundedSender<Result<Message, axum::Error>>,
+    pub task: JoinHandle<DisconnectReason>,
+}
+
+impl Client {
+    pub fn send(&self, message: Value) {
+        let text = Message::Text(message.to_string().into());
+        let _ = self.incoming.unbounded_send(Ok(text));
+    }
+
+    pub fn send_binary(&self, data: impl Into<Bytes>) {
+        let _ = self
+            .incoming
+            .unbounded_send(Ok(Message::Binary(data.into())));
+    }
+}
+
+/// Codes of the errors among `replies`.
+pub fn error_codes(replies: &[Outgoing]) -> Vec<ErrorCode> {
+    replies
+        .iter()
+        .filter_map(|outgoing| match outgoing {
+            Outgoing::Control(msg) => match &msg.payload {
+                RelayOutgoingPayload::Error(err) => Some(err.code),
+                _ => None,
+            },
+            Outgoing::Frame(_) => None,
+        })
+        .collect()
+}
+
+pub fn control(outgoing: Outgoing) -> RelayOutgoingMessage {
+    match outgoing {
+        Outgoing::Control(msg) => *msg,
+        other => panic!("expected a control message, got {other:?}"),
+    }
+}
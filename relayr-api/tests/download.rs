@@ -0,0 +1,125 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use axum::body::Body;
+use relayr_api::feature::relay::{
+    error::ErrorCode,
+    http::{
+        download::{DownloadError, HttpRecipient, content_disposition},
+        upload::{HttpSender, HttpUpload},
+    },
+    state::RelayState,
+};
+use tokio::sync::mpsc;
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn announce(state: &RelayState, passphrase: Option<&str>) -> HttpSender {
+    let upload = HttpUpload {
+        name: "report.pdf".into(),
+        size: 5,
+        mime_type: "application/pdf".into(),
+        passphrase: passphrase.map(str::to_owned),
+    };
+    HttpSender::announce(state.clone(), upload, CLIENT)
+        .await
+        .unwrap()
+}
+
+#[test]
+fn content_disposition_escapes_the_file_name() {
+    assert_eq!(
+        content_disposition("report.pdf"),
+        "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+    );
+    assert_eq!(
+        content_disposition("ré\"sumé\r\n.pdf"),
+        "attachment; filename=\"r__sum___.pdf\"; filename*=UTF-8''r%C3%A9%22sum%C3%A9%0D%0A.pdf"
+    );
+    assert_eq!(
+        content_disposition("../etc/passwd"),
+        "attachment; filename=\".._etc_passwd\"; filename*=UTF-8''.._etc_passwd"
+    );
+}
+
+#[tokio::test]
+async fn unknown_senders_are_not_found() {
+    let result = HttpRecipient::pair(
+        RelayState::new(),
+        "nobody".into(),
+        None,
+        CLIENT,
+        START_TIMEOUT,
+    )
+    .await;
+
+    assert!(matches!(result, Err(DownloadError::NotFound)));
+}
+
+#[tokio::test]
+async fn downloads_receive_the_whole_file() {
+    let state = RelayState::new();
+    let sender = announce(&state, None).await;
+    let sender_id = sender.sender_id().to_owned();
+    let (progress_tx, _progress_rx) = mpsc::channel(16);
+    let upload_task = tokio::spawn(sender.run(Body::from("hello"), progress_tx));
+
+    let recipient = HttpRecipient::pair(state.clone(), sender_id, None, CLIENT, START_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(recipient.file().name, "report.pdf");
+    let recipient_id = recipient.recipient_id().to_owned();
+
+    let (body_tx, mut body_rx) = mpsc::channel(4);
+    recipient.run(body_tx).await;
+    upload_task.await.unwrap();
+
+    let mut received = Vec::new();
+    while let Some(chunk) = body_rx.recv().await {
+        received.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(received, b"hello");
+    assert!(state.registry.peer_tx(&recipient_id).is_none());
+}
+
+#[tokio::test]
+async fn protected_downloads_need_the_passphrase() {
+    let state = RelayState::new();
+    let sender = announce(&state, Some("secret")).await;
+
+    let result = HttpRecipient::pair(
+        state.clone(),
+        sender.sender_id().to_owned(),
+        Some("guess".into()),
+        CLIENT,
+        START_TIMEOUT,
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(DownloadError::Refused(err)) if err.code == ErrorCode::WrongPassphrase
+    ));
+}
+
+#[tokio::test]
+async fn downloads_give_up_when_the_sender_does_not_accept() {
+    let state = RelayState::new();
+    // Announced but never run, so nobody answers `recipientReady`.
+    let sender = announce(&state, None).await;
+
+    let result = HttpRecipient::pair(
+        state.clone(),
+        sender.sender_id().to_owned(),
+        None,
+        CLIENT,
+        Duration::from_millis(20),
+    )
+    .await;
+
+    assert!(matches!(result, Err(DownloadError::TimedOut)));
+    assert!(state.registry.recipients_of(sender.sender_id()).is_empty());
+}
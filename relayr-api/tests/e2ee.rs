@@ -0,0 +1,145 @@
+mod common;
+
+use axum::extract::ws::Message;
+use common::{ShareBuilder, error_codes, next_payload};
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    e2ee::{InvalidPublicKey, parse_public_key},
+    error::ErrorCode,
+    frame::{FLAG_ENCRYPTED, FLAG_LAST_CHUNK, FRAME_VERSION, FrameHeader},
+    ws::dto::response::RelayOutgoingPayload,
+};
+use serde_json::{Value, json};
+use tokio::sync::mpsc::Receiver;
+
+#[test]
+fn public_keys_are_32_hex_bytes() {
+    let key = "ab".repeat(32);
+    assert_eq!(parse_public_key(&key), Ok([0xab; 32]));
+    assert_eq!(
+        parse_public_key("abcd"),
+        Err(InvalidPublicKey("abcd".into()))
+    );
+}
+
+#[test]
+fn chunks_wait_for_every_recipient_to_swap_keys() {
+    let share = ShareBuilder::new(&["a", "b"]).broadcast().e2ee().build();
+    let registry = share.registry();
+    assert!(registry.is_e2ee("sender"));
+    assert_eq!(registry.awaiting_keys("sender"), vec!["a", "b"]);
+
+    registry.record_public_key("sender", "a", true);
+    registry.record_public_key("sender", "a", false);
+    registry.record_public_key("sender", "b", true);
+    assert_eq!(registry.awaiting_keys("sender"), vec!["b"]);
+
+    registry.record_public_key("sender", "b", false);
+    assert!(registry.awaiting_keys("sender").is_empty());
+}
+
+#[test]
+fn recipients_that_leave_take_their_keys_with_them() {
+    let share = ShareBuilder::new(&["a"]).broadcast().e2ee().build();
+    let registry = share.registry();
+    registry.record_public_key("sender", "a", true);
+    registry.record_public_key("sender", "a", false);
+
+    registry.unpair_recipient("sender", "a").unwrap();
+    registry.pair("sender", "a", 10).unwrap();
+    assert_eq!(registry.awaiting_keys("sender"), vec!["a"]);
+}
+
+#[test]
+fn encrypted_flag_is_a_known_frame_flag() {
+    let header = FrameHeader {
+        version: FRAME_VERSION,
+        flags: FLAG_ENCRYPTED | FLAG_LAST_CHUNK,
+        session_id: "sender".into(),
+        file_index: 0,
+        chunk_index: 3,
+        length: 0,
+    };
+    let frame = header.encode(b"ciphertext");
+
+    let (parsed, payload) = FrameHeader::parse(&frame).unwrap();
+    assert_eq!(parsed.flags, FLAG_ENCRYPTED | FLAG_LAST_CHUNK);
+    assert_eq!(payload, b"ciphertext");
+}
+
+fn envelope(flags: u8) -> Vec<u8> {
+    FrameHeader {
+        version: FRAME_VERSION,
+        flags,
+        session_id: "sender".into(),
+        file_index: 0,
+        chunk_index: 0,
+        length: 0,
+    }
+    .encode(b"ciphertext")
+}
+
+fn key_exchange(to_id: &str) -> Value {
+    json!({ "type": "keyExchange", "toId": to_id, "publicKey": "ab".repeat(32) })
+}
+
+fn relayed_frames(rx: &mut Receiver<Outgoing>) -> usize {
+    std::iter::from_fn(|| rx.try_recv().ok())
+        .filter(|outgoing| matches!(outgoing, Outgoing::Frame(Message::Binary(_))))
+        .count()
+}
+
+#[tokio::test]
+async fn encrypted_shares_refuse_plain_frames() {
+    let mut share = ShareBuilder::new(&["a"]).e2ee().build();
+    let sender = share.connect("sender").await;
+
+    sender.send_binary(&b"plaintext"[..]);
+    sender.send_binary(envelope(0));
+    let replies = share.replies(&sender).await;
+
+    assert_eq!(
+        error_codes(&replies),
+        vec![ErrorCode::EncryptionRequired, ErrorCode::EncryptionRequired]
+    );
+    assert_eq!(relayed_frames(share.rx("a")), 0);
+}
+
+#[tokio::test]
+async fn encrypted_chunks_wait_for_the_key_exchange() {
+    let mut share = ShareBuilder::new(&["a"]).e2ee().build();
+    let sender = share.connect("sender").await;
+    let recipient = share.connect("a").await;
+
+    sender.send_binary(envelope(FLAG_ENCRYPTED));
+    let replies = share.replies(&sender).await;
+    assert_eq!(
+        error_codes(&replies),
+        vec![ErrorCode::KeyExchangeIncomplete]
+    );
+
+    sender.send(key_exchange("a"));
+    recipient.send(key_exchange("sender"));
+    share.replies(&recipient).await;
+    sender.send_binary(envelope(FLAG_ENCRYPTED));
+    let replies = share.replies(&sender).await;
+    assert!(error_codes(&replies).is_empty());
+    assert_eq!(relayed_frames(share.rx("a")), 1);
+}
+
+#[tokio::test]
+async fn keys_only_reach_the_paired_peer() {
+    let mut share = ShareBuilder::new(&["a"]).idle(&["mallory"]).e2ee().build();
+    let sender = share.connect("sender").await;
+
+    sender.send(key_exchange("a"));
+    sender.send(key_exchange("mallory"));
+    let replies = share.replies(&sender).await;
+
+    assert_eq!(error_codes(&replies), vec![ErrorCode::Forbidden]);
+    assert!(matches!(
+        next_payload(share.rx("a")),
+        RelayOutgoingPayload::KeyExchange(key) if key.from_id == "sender"
+    ));
+    assert!(share.rx("mallory").try_recv().is_err());
+}
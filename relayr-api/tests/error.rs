@@ -0,0 +1,54 @@
+use relayr_api::feature::relay::error::{ErrorCode, ErrorContext, ErrorMessage};
+use serde_json::json;
+
+#[test]
+fn numeric_codes_are_stable() {
+    let codes = [
+        (ErrorCode::InvalidPayload, 1000),
+        (ErrorCode::LimitExceeded, 1005),
+        (ErrorCode::SenderAlreadyConnected, 2000),
+        (ErrorCode::RecipientDisconnected, 2010),
+        (ErrorCode::RecipientMismatch, 3001),
+        (ErrorCode::UnsupportedWsMessageTextType, 4001),
+        (ErrorCode::Unknown, 9000),
+    ];
+
+    for (code, numeric) in codes {
+        assert_eq!(code.numeric(), numeric, "{code:?}");
+    }
+}
+
+#[test]
+fn only_transient_errors_are_retryable() {
+    assert!(ErrorCode::FlowControlBlocked.is_retryable());
+    assert!(ErrorCode::SenderDisconnected.is_retryable());
+    assert!(!ErrorCode::InvalidPayload.is_retryable());
+    assert!(!ErrorCode::ShareFull.is_retryable());
+    assert!(!ErrorCode::RecipientMismatch.is_retryable());
+}
+
+#[test]
+fn error_messages_carry_the_taxonomy() {
+    let error = ErrorMessage::new(ErrorCode::RecipientMismatch, "recipient ID mismatch")
+        .with_context(ErrorContext {
+            sender_id: Some("sender".into()),
+            expected_recipient_id: Some("alice".into()),
+            actual_recipient_id: Some("bob".into()),
+            ..Default::default()
+        });
+
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({
+            "code": "recipientMismatch",
+            "numericCode": 3001,
+            "retryable": false,
+            "message": "recipient ID mismatch",
+            "context": {
+                "senderId": "sender",
+                "expectedRecipientId": "alice",
+                "actualRecipientId": "bob",
+            },
+        })
+    );
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{Share, ShareBuilder};
+use relayr_api::feature::relay::{
+    flow::{Admission, FlowStatus},
+    registry::{SessionRegistry, UnpairError},
+};
+
+const MAX_QUEUED_BYTES: u64 = 300;
+
+// `sender` has started transferring to `recipients`.
+fn share_with(share: ShareBuilder) -> Share {
+    share
+        .registry(SessionRegistry::new().with_max_queued_bytes(MAX_QUEUED_BYTES))
+        .transferring()
+        .build()
+}
+
+fn forwarded(pause: Option<FlowStatus>) -> Admission {
+    Admission::Forward { pause }
+}
+
+#[test]
+fn sender_is_paused_once_queued_bytes_reach_the_limit() {
+    let share = share_with(ShareBuilder::new(&["recipient"]));
+    let registry = share.registry();
+
+    assert_eq!(registry.admit_chunk("sender", 100), forwarded(None));
+    assert_eq!(registry.admit_chunk("sender", 100), forwarded(None));
+    assert_eq!(
+        registry.admit_chunk("sender", 100),
+        forwarded(Some(FlowStatus {
+            credits: None,
+            queued_bytes: 300,
+        }))
+    );
+    assert_eq!(
+        registry.admit_chunk("sender", 100),
+        Admission::Blocked(FlowStatus {
+            credits: None,
+            queued_bytes: 300,
+        })
+    );
+}
+
+#[test]
+fn acks_release_queued_bytes_and_resume_the_sender() {
+    let share = share_with(ShareBuilder::new(&["recipient"]));
+    let registry = share.registry();
+    for _ in 0..3 {
+        registry.admit_chunk("sender", 100);
+    }
+
+    assert_eq!(
+        registry.release_chunk("sender", "recipient"),
+        Ok(Some(FlowStatus {
+            credits: None,
+            queued_bytes: 200,
+        }))
+    );
+    // Already resumed, so later acks stay quiet.
+    assert_eq!(registry.release_chunk("sender", "recipient"), Ok(None));
+    assert_eq!(registry.admit_chunk("sender", 100), forwarded(None));
+}
+
+#[test]
+fn an_empty_queue_admits_a_chunk_larger_than_the_limit() {
+    let share = share_with(ShareBuilder::new(&["recipient"]));
+    let registry = share.registry();
+
+    assert!(matches!(
+        registry.admit_chunk("sender", 1000),
+        Admission::Forward { pause: Some(_) }
+    ));
+    assert!(matches!(
+        registry.admit_chunk("sender", 1),
+        Admission::Blocked(_)
+    ));
+}
+
+#[test]
+fn granted_credits_bound_the_chunks_in_flight() {
+    let share = share_with(ShareBuilder::new(&["recipient"]));
+    let registry = share.registry();
+    assert_eq!(registry.grant_credits("sender", "recipient", 2), Ok(None));
+
+    assert_eq!(registry.admit_chunk("sender", 10), forwarded(None));
+    assert_eq!(
+        registry.admit_chunk("sender", 10),
+        forwarded(Some(FlowStatus {
+            credits: Some(0),
+            queued_bytes: 20,
+        }))
+    );
+    assert!(matches!(
+        registry.admit_chunk("sender", 10),
+        Admission::Blocked(_)
+    ));
+
+    // Acks free queued bytes but do not hand credits back.
+    assert_eq!(registry.release_chunk("sender", "recipient"), Ok(None));
+    assert_eq!(
+        registry.grant_credits("sender", "recipient", 1),
+        Ok(Some(FlowStatus {
+            credits: Some(1),
+            queued_bytes: 10,
+        }))
+    );
+}
+
+#[test]
+fn broadcast_is_paced_by_the_slowest_recipient() {
+    let share = share_with(ShareBuilder::new(&["fast", "slow"]).broadcast());
+    let registry = share.registry();
+    for _ in 0..3 {
+        registry.admit_chunk("sender", 100);
+    }
+
+    for _ in 0..3 {
+        assert_eq!(registry.release_chunk("sender", "fast"), Ok(None));
+    }
+    assert!(matches!(
+        registry.admit_chunk("sender", 100),
+        Admission::Blocked(_)
+    ));
+
+    assert_eq!(
+        registry.release_chunk("sender", "slow"),
+        Ok(Some(FlowStatus {
+            credits: None,
+            queued_bytes: 200,
+        }))
+    );
+}
+
+#[test]
+fn grant_credits_requires_a_paired_recipient() {
+    let share = share_with(ShareBuilder::new(&["recipient"]));
+    let registry = share.registry();
+
+    assert_eq!(
+        registry.grant_credits("sender", "stranger", 5),
+        Err(UnpairError::RecipientMismatch {
+            current_recipient: "recipient".into(),
+        })
+    );
+    assert_eq!(
+        registry.grant_credits("nobody", "recipient", 5),
+        Err(UnpairError::NotPaired)
+    );
+}
@@ -0,0 +1,77 @@
+use relayr_api::feature::relay::frame::{FLAG_LAST_CHUNK, FRAME_VERSION, FrameError, FrameHeader};
+
+fn header(session_id: &str, chunk_index: u32, length: u32) -> FrameHeader {
+    FrameHeader {
+        version: FRAME_VERSION,
+        flags: 0,
+        session_id: session_id.into(),
+        file_index: 2,
+        chunk_index,
+        length,
+    }
+}
+
+#[test]
+fn encoded_frame_parses_back() {
+    let mut expected = header("sender", 7, 5);
+    expected.flags = FLAG_LAST_CHUNK;
+    let frame = expected.encode(b"hello");
+
+    assert!(FrameHeader::is_envelope(&frame));
+    let (parsed, payload) = FrameHeader::parse(&frame).unwrap();
+    assert_eq!(parsed, expected);
+    assert!(parsed.is_last_chunk());
+    assert_eq!(payload, b"hello");
+}
+
+#[test]
+fn raw_chunks_are_not_envelopes() {
+    assert!(!FrameHeader::is_envelope(b"\x89PNG\r\n"));
+    assert!(!FrameHeader::is_envelope(b""));
+}
+
+#[test]
+fn truncated_frames_are_rejected() {
+    let frame = header("sender", 0, 3).encode(b"abc");
+
+    assert_eq!(FrameHeader::parse(&frame[..6]), Err(FrameError::Truncated));
+    assert_eq!(
+        FrameHeader::parse(&frame[..frame.len() - 4]),
+        Err(FrameError::Truncated)
+    );
+    assert_eq!(
+        FrameHeader::parse(&frame[..frame.len() - 1]),
+        Err(FrameError::LengthMismatch {
+            declared: 3,
+            actual: 2,
+        })
+    );
+}
+
+#[test]
+fn unknown_versions_and_flags_are_rejected() {
+    let mut newer = header("sender", 0, 0);
+    newer.version = FRAME_VERSION + 1;
+    assert_eq!(
+        FrameHeader::parse(&newer.encode(b"")),
+        Err(FrameError::UnsupportedVersion(FRAME_VERSION + 1))
+    );
+
+    let mut flagged = header("sender", 0, 0);
+    flagged.flags = 0b1000_0000;
+    assert_eq!(
+        FrameHeader::parse(&flagged.encode(b"")),
+        Err(FrameError::UnknownFlags(0b1000_0000))
+    );
+}
+
+#[test]
+fn session_id_must_be_utf8() {
+    let mut frame = header("ab", 0, 0).encode(b"");
+    frame[7] = 0xff;
+
+    assert_eq!(
+        FrameHeader::parse(&frame),
+        Err(FrameError::InvalidSessionId)
+    );
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use axum::extract::ws::Message;
+use common::{Client, Share, ShareBuilder, error_codes, next_payload};
+use relayr_api::feature::relay::{
+    codec::Outgoing, error::ErrorCode, types::IdentityPolicy,
+    ws::dto::response::RelayOutgoingPayload,
+};
+use serde_json::json;
+
+#[test]
+fn payloads_default_to_the_connection_id() {
+    for policy in [IdentityPolicy::Strict, IdentityPolicy::Compat] {
+        assert_eq!(policy.resolve(None, "alice"), Ok("alice".to_owned()));
+        assert_eq!(
+            policy.resolve(Some("alice".into()), "alice"),
+            Ok("alice".to_owned())
+        );
+    }
+}
+
+#[test]
+fn strict_policy_rejects_other_peer_ids() {
+    assert_eq!(
+        IdentityPolicy::Strict.resolve(Some("bob".into()), "alice"),
+        Err("bob".to_owned())
+    );
+}
+
+#[test]
+fn compat_policy_trusts_payload_ids() {
+    assert_eq!(
+        IdentityPolicy::Compat.resolve(Some("bob".into()), "alice"),
+        Ok("bob".to_owned())
+    );
+}
+
+#[test]
+fn policy_parses_from_config() {
+    assert_eq!("strict".parse(), Ok(IdentityPolicy::Strict));
+    assert_eq!("Compat".parse(), Ok(IdentityPolicy::Compat));
+    assert!("lenient".parse::<IdentityPolicy>().is_err());
+}
+
+#[test]
+fn forbidden_is_not_retryable() {
+    assert_eq!(ErrorCode::Forbidden.numeric(), 1006);
+    assert!(!ErrorCode::Forbidden.is_retryable());
+}
+
+// `mallory` is connected but not part of `sender`'s share with `bob`.
+async fn share_with_mallory(policy: IdentityPolicy) -> (Share, Client) {
+    let mut share = ShareBuilder::new(&["bob"])
+        .idle(&["mallory"])
+        .identity_policy(policy)
+        .transferring()
+        .build();
+    let mallory = share.connect("mallory").await;
+    (share, mallory)
+}
+
+#[tokio::test]
+async fn chunks_sent_as_another_sender_follow_the_policy() {
+    let chunk = json!({
+        "type": "fileChunk",
+        "senderId": "sender",
+        "fileName": "file.bin",
+        "totalSize": 1024,
+        "totalChunks": 1,
+        "uploadedSize": 1024,
+        "chunkIndex": 0,
+        "chunkDataSize": 1024,
+        "senderTransferProgress": 100,
+    });
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Strict).await;
+    mallory.send(chunk.clone());
+    let replies = share.replies(&mallory).await;
+    assert_eq!(error_codes(&replies), vec![ErrorCode::Forbidden]);
+    assert!(share.rx("bob").try_recv().is_err());
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Compat).await;
+    mallory.send(chunk);
+    let replies = share.replies(&mallory).await;
+    assert!(error_codes(&replies).is_empty());
+    assert!(matches!(
+        next_payload(share.rx("bob")),
+        RelayOutgoingPayload::FileChunk(_)
+    ));
+}
+
+#[tokio::test]
+async fn cancelling_as_another_recipient_follows_the_policy() {
+    let cancel = json!({
+        "type": "cancelRecipientTransfer",
+        "senderId": "sender",
+        "recipientId": "bob",
+    });
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Strict).await;
+    mallory.send(cancel.clone());
+    let replies = share.replies(&mallory).await;
+    assert_eq!(error_codes(&replies), vec![ErrorCode::Forbidden]);
+    assert!(share.rx("sender").try_recv().is_err());
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Compat).await;
+    mallory.send(cancel);
+    let replies = share.replies(&mallory).await;
+    assert!(error_codes(&replies).is_empty());
+    assert!(matches!(
+        next_payload(share.rx("sender")),
+        RelayOutgoingPayload::CancelRecipientTransfer(_)
+    ));
+}
+
+#[tokio::test]
+async fn closing_as_another_peer_follows_the_policy() {
+    let close = json!({ "type": "userClose", "userId": "sender", "role": "sender" });
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Strict).await;
+    mallory.send(close.clone());
+    let replies = share.replies(&mallory).await;
+    assert_eq!(error_codes(&replies), vec![ErrorCode::Forbidden]);
+
+    let (mut share, mallory) = share_with_mallory(IdentityPolicy::Compat).await;
+    mallory.send(close);
+    let replies = share.replies(&mallory).await;
+    assert!(matches!(
+        replies.as_slice(),
+        [Outgoing::Frame(Message::Close(Some(frame)))] if frame.code == 1000
+    ));
+}
+
+#[tokio::test]
+async fn strict_policy_keeps_a_senders_recipients_private() {
+    for message_type in ["cancelRecipientReady", "cancelRecipientTransfer"] {
+        let (mut share, mallory) = share_with_mallory(IdentityPolicy::Strict).await;
+        mallory.send(json!({ "type": message_type, "senderId": "sender" }));
+        let replies = share.replies(&mallory).await;
+
+        assert_eq!(error_codes(&replies), vec![ErrorCode::Forbidden]);
+        let Outgoing::Control(reply) = &replies[0] else {
+            panic!("expected a control message");
+        };
+        assert!(!reply.to_json().contains("bob"), "{}", reply.to_json());
+    }
+}
@@ -0,0 +1,272 @@
+mod common;
+
+use axum::extract::ws::Message;
+use common::{ShareBuilder, control, error_codes};
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    error::ErrorCode,
+    integrity::{IntegrityError, Sha256Digest, StreamVerifier, parse_sha256},
+    registry::SessionRegistry,
+    ws::dto::response::RelayOutgoingPayload,
+};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+fn sha256(data: &[u8]) -> Sha256Digest {
+    Sha256::digest(data).into()
+}
+
+fn stream(verifier: &mut StreamVerifier, file_index: Option<u32>, chunks: &[&[u8]]) {
+    for (chunk_index, data) in chunks.iter().enumerate() {
+        verifier.expect_chunk(file_index, chunk_index as u32, Some(sha256(data)));
+        verifier.check_chunk(data).unwrap();
+        verifier.commit_chunk(data);
+    }
+}
+
+// `fileChunk` for one chunk of the 11-byte `hello world`.
+fn file_chunk(file_index: Option<u32>, chunk_index: u32, data: &[u8]) -> Value {
+    json!({
+        "type": "fileChunk",
+        "fileIndex": file_index,
+        "fileName": "file.bin",
+        "totalSize": 11,
+        "totalChunks": 2,
+        "uploadedSize": 6 * chunk_index + data.len() as u32,
+        "chunkIndex": chunk_index,
+        "chunkDataSize": data.len(),
+        "chunkSha256": hex::encode(sha256(data)),
+        "senderTransferProgress": 50,
+    })
+}
+
+fn file_end(data: &[u8]) -> Value {
+    json!({
+        "type": "fileEnd",
+        "fileName": "file.bin",
+        "totalSize": 11,
+        "totalChunks": 2,
+        "uploadedSize": 11,
+        "lastChunkIndex": 1,
+        "sha256": hex::encode(sha256(data)),
+    })
+}
+
+#[test]
+fn parse_sha256_accepts_hex_in_either_case() {
+    let digest = sha256(b"abc");
+
+    assert_eq!(parse_sha256(&hex::encode(digest)), Ok(digest));
+    assert_eq!(parse_sha256(&hex::encode_upper(digest)), Ok(digest));
+    assert!(matches!(
+        parse_sha256("abc"),
+        Err(IntegrityError::InvalidHash(_))
+    ));
+}
+
+#[test]
+fn matching_file_passes_declared_and_confirmed_hashes() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"hello world"))]);
+
+    stream(&mut verifier, None, &[b"hello ", b"world"]);
+
+    assert_eq!(
+        verifier.check_file(None, Some(sha256(b"hello world"))),
+        Ok(())
+    );
+}
+
+#[test]
+fn corrupted_chunk_is_reported() {
+    let mut verifier = StreamVerifier::default();
+    verifier.expect_chunk(None, 0, Some(sha256(b"hello")));
+
+    assert_eq!(
+        verifier.check_chunk(b"hellp"),
+        Err(IntegrityError::ChunkMismatch {
+            file_index: None,
+            chunk_index: 0,
+        })
+    );
+    // Checking leaves the chunk expected, so it can be sent again.
+    assert_eq!(verifier.pending_frame(), Some((0, 0)));
+    assert_eq!(verifier.check_chunk(b"hello"), Ok(()));
+}
+
+#[test]
+fn truncated_file_is_reported() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"hello world"))]);
+
+    stream(&mut verifier, None, &[b"hello "]);
+
+    assert_eq!(
+        verifier.check_file(None, None),
+        Err(IntegrityError::FileMismatch { file_index: None })
+    );
+}
+
+#[test]
+fn confirmed_hash_must_agree_with_the_declared_one() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"hello"))]);
+
+    stream(&mut verifier, None, &[b"hello"]);
+
+    assert_eq!(
+        verifier.check_file(None, Some(sha256(b"other"))),
+        Err(IntegrityError::FileMismatch { file_index: None })
+    );
+}
+
+#[test]
+fn manifest_files_are_checked_separately() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"first")), None, Some(sha256(b"third"))]);
+
+    stream(&mut verifier, Some(0), &[b"fir", b"st"]);
+    assert_eq!(verifier.check_file(Some(0), None), Ok(()));
+    verifier.finish_file();
+    stream(&mut verifier, Some(1), &[b"anything"]);
+    assert_eq!(verifier.check_file(Some(1), None), Ok(()));
+    verifier.finish_file();
+    stream(&mut verifier, Some(2), &[b"thirc"]);
+    assert_eq!(
+        verifier.check_file(Some(2), None),
+        Err(IntegrityError::FileMismatch {
+            file_index: Some(2)
+        })
+    );
+}
+
+#[test]
+fn resumed_file_skips_the_whole_file_check() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"hello world"))]);
+
+    verifier.expect_chunk(None, 1, Some(sha256(b"world")));
+    verifier.commit_chunk(b"world");
+
+    assert_eq!(verifier.check_file(None, None), Ok(()));
+}
+
+#[test]
+fn chunk_turned_away_by_flow_control_is_checked_once_resent() {
+    let mut verifier = StreamVerifier::default();
+    verifier.reset(vec![Some(sha256(b"hello world"))]);
+
+    stream(&mut verifier, None, &[b"hello "]);
+    // The frame of chunk 1 is blocked and never hashed, then sent again.
+    verifier.expect_chunk(None, 1, Some(sha256(b"world")));
+    verifier.expect_chunk(None, 1, Some(sha256(b"world")));
+    verifier.commit_chunk(b"world");
+    assert_eq!(verifier.check_file(None, None), Ok(()));
+    verifier.finish_file();
+
+    stream(&mut verifier, None, &[b"hello "]);
+    verifier.expect_chunk(None, 1, None);
+    verifier.expect_chunk(None, 1, None);
+    verifier.commit_chunk(b"wordl");
+    assert_eq!(
+        verifier.check_file(None, None),
+        Err(IntegrityError::FileMismatch { file_index: None })
+    );
+}
+
+#[test]
+fn pending_frame_tracks_the_announced_chunk() {
+    let mut verifier = StreamVerifier::default();
+    assert_eq!(verifier.pending_frame(), None);
+
+    verifier.expect_chunk(Some(1), 4, None);
+    assert_eq!(verifier.pending_frame(), Some((1, 4)));
+
+    verifier.commit_chunk(b"data");
+    assert_eq!(verifier.pending_frame(), None);
+}
+
+#[tokio::test]
+async fn corrupt_chunks_never_reach_the_recipient() {
+    let mut share = ShareBuilder::new(&["recipient"]).build();
+    let sender = share.connect("sender").await;
+
+    sender.send(file_chunk(None, 0, b"hello "));
+    sender.send_binary(&b"hellp "[..]);
+    let replies = share.replies(&sender).await;
+    assert_eq!(error_codes(&replies), vec![ErrorCode::IntegrityMismatch]);
+
+    let recipient_rx = share.rx("recipient");
+    assert!(matches!(
+        control(recipient_rx.try_recv().unwrap()).payload,
+        RelayOutgoingPayload::FileChunk(_)
+    ));
+    assert!(matches!(
+        control(recipient_rx.try_recv().unwrap()).payload,
+        RelayOutgoingPayload::Error(err) if err.code == ErrorCode::IntegrityMismatch
+    ));
+    assert!(recipient_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn chunks_turned_away_still_count_towards_the_file_once_resent() {
+    let mut share = ShareBuilder::new(&["recipient"])
+        .registry(SessionRegistry::new().with_max_queued_bytes(6))
+        .build();
+    let sender = share.connect("sender").await;
+    let recipient = share.connect("recipient").await;
+
+    sender.send(file_chunk(None, 0, b"hello "));
+    sender.send_binary(&b"hello "[..]);
+    sender.send(file_chunk(None, 1, b"world"));
+    sender.send_binary(&b"world"[..]);
+    let replies = share.replies(&sender).await;
+    assert_eq!(error_codes(&replies), vec![ErrorCode::FlowControlBlocked]);
+
+    recipient.send(json!({
+        "type": "fileTransferAck",
+        "senderId": "sender",
+        "status": "acknowledged",
+        "fileName": "file.bin",
+        "totalChunks": 2,
+        "uploadedSize": 6,
+        "chunkIndex": 0,
+        "chunkDataSize": 6,
+        "recipientTransferProgress": 50,
+    }));
+    // Draining the recipient's socket also takes the first relayed frame.
+    let mut relayed = share.replies(&recipient).await;
+
+    sender.send(file_chunk(None, 1, b"world"));
+    sender.send_binary(&b"world"[..]);
+    sender.send(file_end(b"hello world"));
+    let replies = share.replies(&sender).await;
+    assert!(error_codes(&replies).is_empty());
+
+    relayed.extend(std::iter::from_fn(|| share.rx("recipient").try_recv().ok()));
+    let frames = relayed
+        .iter()
+        .filter(|outgoing| matches!(outgoing, Outgoing::Frame(Message::Binary(_))))
+        .count();
+    assert_eq!(frames, 2);
+}
+
+#[tokio::test]
+async fn rejected_chunks_do_not_stop_the_whole_file_check() {
+    let mut share = ShareBuilder::new(&["recipient"]).build();
+    let sender = share.connect("sender").await;
+
+    sender.send(file_chunk(None, 0, b"hello "));
+    sender.send_binary(&b"hello "[..]);
+    // Out of range, so turned away before the verifier sees it.
+    sender.send(file_chunk(Some(3), 1, b"world"));
+    sender.send(file_chunk(None, 1, b"wordl"));
+    sender.send_binary(&b"wordl"[..]);
+    sender.send(file_end(b"hello world"));
+
+    let replies = share.replies(&sender).await;
+    assert_eq!(
+        error_codes(&replies),
+        vec![ErrorCode::InvalidPayload, ErrorCode::IntegrityMismatch]
+    );
+}
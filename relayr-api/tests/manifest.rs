@@ -0,0 +1,80 @@
+use relayr_api::feature::relay::manifest::{
+    FileManifest, ManifestEntry, ManifestError, validate_relative_path,
+};
+
+fn entry(path: &str, size: u64) -> ManifestEntry {
+    ManifestEntry {
+        path: path.into(),
+        size,
+        mime_type: "application/octet-stream".into(),
+        sha256: None,
+    }
+}
+
+#[test]
+fn relative_paths_are_accepted() {
+    for path in [
+        "file.txt",
+        "photos/2024/img.jpg",
+        "a\\b.txt",
+        "..hidden",
+        "x..y/z",
+    ] {
+        assert_eq!(validate_relative_path(path), Ok(()), "{path}");
+    }
+}
+
+#[test]
+fn escaping_paths_are_rejected() {
+    for path in [
+        "",
+        "/etc/passwd",
+        "\\\\server\\share",
+        "C:\\Windows",
+        "c:relative",
+        "../secret",
+        "photos/../../secret",
+        "photos\\..\\secret",
+        "./file",
+        "photos//img.jpg",
+        "photos/",
+        "nul\0byte",
+    ] {
+        assert!(validate_relative_path(path).is_err(), "{path:?}");
+    }
+}
+
+#[test]
+fn manifest_sums_sizes() {
+    let manifest = FileManifest::new(vec![entry("a.txt", 3), entry("dir/b.txt", 4)]).unwrap();
+
+    assert_eq!(manifest.files.len(), 2);
+    assert_eq!(manifest.total_size, 7);
+}
+
+#[test]
+fn manifest_rejects_empty_and_duplicate_and_invalid_entries() {
+    assert_eq!(FileManifest::new(vec![]), Err(ManifestError::Empty));
+    assert_eq!(
+        FileManifest::new(vec![entry("a.txt", 1), entry("a.txt", 2)]),
+        Err(ManifestError::DuplicatePath("a.txt".into()))
+    );
+    assert!(matches!(
+        FileManifest::new(vec![entry("a.txt", 1), entry("../b.txt", 2)]),
+        Err(ManifestError::InvalidPath { path, .. }) if path == "../b.txt"
+    ));
+}
+
+#[test]
+fn manifest_rejects_malformed_hashes() {
+    let mut hashed = entry("a.txt", 1);
+    hashed.sha256 = Some("not-a-digest".into());
+
+    assert_eq!(
+        FileManifest::new(vec![hashed]),
+        Err(ManifestError::InvalidHash {
+            path: "a.txt".into(),
+            hash: "not-a-digest".into(),
+        })
+    );
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::ShareBuilder;
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    error::{ErrorCode, ErrorMessage},
+    flow::FlowStatus,
+    ws::dto::{
+        request::{REQUEST, RelayIncomingMessage, RelayIncomingPayload, RequestContext},
+        response::{
+            AsWsMessage, FileChunkResponseDto, FlowControlResponseDto, RegisterResponseDto,
+            RelayOutgoingMessage, RelayOutgoingPayload,
+        },
+    },
+};
+use serde_json::{Value, json};
+
+fn json_of(outgoing: Outgoing) -> Value {
+    match outgoing {
+        Outgoing::Control(msg) => serde_json::from_str(&msg.to_json()).unwrap(),
+        other => panic!("expected a control message, got {other:?}"),
+    }
+}
+
+#[test]
+fn messages_share_one_envelope() {
+    let value = json_of(RegisterResponseDto::new("abc").as_ws_message());
+
+    assert_eq!(value["success"], json!(true));
+    assert_eq!(value["type"], json!("register"));
+    assert_eq!(value["connId"], json!("abc"));
+    assert!(value["timestamp"].is_i64());
+}
+
+#[test]
+fn errors_are_tagged_and_unsuccessful() {
+    let error = ErrorMessage::new(ErrorCode::ShareFull, "share is full").with_details("10 of 10");
+    let value = json_of(error.as_ws_message());
+
+    assert_eq!(value["success"], json!(false));
+    assert_eq!(value["type"], json!("error"));
+    assert_eq!(value["code"], json!("shareFull"));
+    assert_eq!(value["details"], json!("10 of 10"));
+}
+
+#[test]
+fn flow_control_uses_one_body_for_pause_and_resume() {
+    let status = FlowStatus {
+        credits: Some(0),
+        queued_bytes: 42,
+    };
+    let pause = RelayOutgoingPayload::Pause(FlowControlResponseDto::new("sender", status));
+    let resume = RelayOutgoingPayload::Resume(FlowControlResponseDto::new("sender", status));
+
+    assert_eq!(json_of(pause.as_ws_message())["type"], json!("pause"));
+    assert_eq!(json_of(resume.as_ws_message())["type"], json!("resume"));
+}
+
+#[test]
+fn outgoing_messages_round_trip() {
+    let chunk = FileChunkResponseDto::new("sender", "recipient", "a.bin", 10, 1, 10, 0, 10, 100)
+        .with_file_index(Some(2));
+    let text = RelayOutgoingMessage::new(chunk.into()).to_json();
+
+    let decoded: RelayOutgoingMessage = serde_json::from_str(&text).unwrap();
+    assert!(decoded.success);
+    match decoded.payload {
+        RelayOutgoingPayload::FileChunk(chunk) => {
+            assert_eq!(chunk.file_index, Some(2));
+            assert_eq!(chunk.file_name, "a.bin");
+        }
+        other => panic!("expected a fileChunk, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn request_id_is_echoed_within_its_scope() {
+    let context = RequestContext {
+        request_id: Some("r-1".into()),
+        message_type: Some("recipientReady".into()),
+    };
+    let scoped = REQUEST
+        .scope(context, async {
+            json_of(ErrorMessage::new(ErrorCode::ShareFull, "share is full").as_ws_message())
+        })
+        .await;
+    let unscoped = json_of(RegisterResponseDto::new("abc").as_ws_message());
+
+    assert_eq!(scoped["requestId"], json!("r-1"));
+    assert_eq!(scoped["relatedMessageType"], json!("recipientReady"));
+    assert!(unscoped.get("requestId").is_none());
+}
+
+#[test]
+fn incoming_messages_carry_an_optional_request_id() {
+    let with_id: RelayIncomingMessage =
+        serde_json::from_value(json!({ "type": "terminate", "requestId": "r-2" })).unwrap();
+    let without_id: RelayIncomingMessage = serde_json::from_value(json!({
+        "type": "grantCredits",
+        "senderId": "sender",
+        "credits": 3,
+    }))
+    .unwrap();
+
+    assert_eq!(with_id.request_id.as_deref(), Some("r-2"));
+    assert!(matches!(with_id.payload, RelayIncomingPayload::Terminate));
+    assert!(without_id.request_id.is_none());
+    assert!(matches!(
+        without_id.payload,
+        RelayIncomingPayload::GrantCredits(_)
+    ));
+}
+
+#[tokio::test]
+async fn request_id_stays_with_the_peer_that_sent_it() {
+    let mut share = ShareBuilder::new(&["recipient"]).build();
+    let sender = share.connect("sender").await;
+
+    sender.send(json!({
+        "type": "fileChunk",
+        "requestId": "r-1",
+        "senderId": "sender",
+        "fileName": "file.bin",
+        "totalSize": 1024,
+        "totalChunks": 1,
+        "uploadedSize": 1024,
+        "chunkIndex": 0,
+        "chunkDataSize": 1024,
+        "senderTransferProgress": 100,
+    }));
+    sender.send(json!({ "type": "fileEnd", "requestId": "r-2", "senderId": "sender" }));
+    let replies = share.replies(&sender).await;
+
+    let reply = json_of(replies.into_iter().next().unwrap());
+    assert_eq!(reply["type"], json!("error"));
+    assert_eq!(reply["requestId"], json!("r-2"));
+    let relayed = json_of(share.rx("recipient").try_recv().unwrap());
+    assert_eq!(relayed["type"], json!("fileChunk"));
+    assert!(relayed.get("requestId").is_none());
+}
@@ -0,0 +1,164 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{
+    body::to_bytes,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use relayr_api::feature::relay::{
+    handlers::handle_redeem_pairing_code,
+    pairing::{PairingCodeStyle, PairingCodes, PairingError},
+    passphrase::PASSPHRASE_HEADER,
+    state::RelayState,
+};
+use serde_json::{Value, json};
+
+const ALICE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const BOB: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+fn codes() -> PairingCodes {
+    PairingCodes::new(Duration::from_secs(600), 3, Duration::from_secs(60))
+}
+
+#[test]
+fn digit_codes_redeem_once() {
+    let codes = codes();
+    let code = codes.mint("sender", PairingCodeStyle::Digits);
+
+    assert_eq!(code.len(), 6);
+    assert!(code.bytes().all(|b| b.is_ascii_digit()));
+    assert_eq!(codes.redeem(&code, ALICE), Ok("sender".to_owned()));
+    assert_eq!(codes.redeem(&code, ALICE), Err(PairingError::InvalidCode));
+}
+
+#[test]
+fn peeked_codes_can_still_be_redeemed() {
+    let codes = codes();
+    let code = codes.mint("sender", PairingCodeStyle::Digits);
+
+    assert_eq!(codes.peek(&code, ALICE), Ok("sender".to_owned()));
+    assert_eq!(codes.peek(&code, ALICE), Ok("sender".to_owned()));
+    assert_eq!(codes.redeem(&code, ALICE), Ok("sender".to_owned()));
+    assert_eq!(codes.peek(&code, ALICE), Err(PairingError::InvalidCode));
+}
+
+#[test]
+fn word_codes_accept_loose_spelling() {
+    let codes = codes();
+    let code = codes.mint("sender", PairingCodeStyle::Words);
+    assert_eq!(code.split('-').count(), 3);
+
+    let typed = format!(" {} ", code.replace('-', " ").to_uppercase());
+    assert_eq!(codes.redeem(&typed, ALICE), Ok("sender".to_owned()));
+}
+
+#[test]
+fn digit_codes_accept_separators() {
+    let codes = codes();
+    let code = codes.mint("sender", PairingCodeStyle::Digits);
+
+    let typed = format!("{}-{}", &code[..3], &code[3..]);
+    assert_eq!(codes.redeem(&typed, ALICE), Ok("sender".to_owned()));
+}
+
+#[test]
+fn minting_again_revokes_the_previous_code() {
+    let codes = codes();
+    let first = codes.mint("sender", PairingCodeStyle::Digits);
+    let second = codes.mint("sender", PairingCodeStyle::Words);
+
+    assert_eq!(codes.redeem(&first, ALICE), Err(PairingError::InvalidCode));
+    assert_eq!(codes.redeem(&second, ALICE), Ok("sender".to_owned()));
+}
+
+#[test]
+fn expired_codes_are_rejected() {
+    let codes = PairingCodes::new(Duration::ZERO, 3, Duration::from_secs(60));
+    let code = codes.mint("sender", PairingCodeStyle::Digits);
+
+    assert_eq!(codes.redeem(&code, ALICE), Err(PairingError::InvalidCode));
+}
+
+#[test]
+fn failed_attempts_throttle_the_client() {
+    let codes = codes();
+    let code = codes.mint("sender", PairingCodeStyle::Words);
+
+    for _ in 0..3 {
+        assert_eq!(
+            codes.peek("not-a-code", ALICE),
+            Err(PairingError::InvalidCode)
+        );
+    }
+
+    // Even the right code is refused until the window ends, and it stays
+    // valid for other clients.
+    assert!(matches!(
+        codes.redeem(&code, ALICE),
+        Err(PairingError::Throttled { retry_after }) if retry_after <= Duration::from_secs(60)
+    ));
+    assert_eq!(codes.redeem(&code, BOB), Ok("sender".to_owned()));
+}
+
+#[test]
+fn throttle_window_expires() {
+    let codes = PairingCodes::new(Duration::from_secs(600), 1, Duration::ZERO);
+    let code = codes.mint("sender", PairingCodeStyle::Digits);
+
+    assert_eq!(
+        codes.redeem("000000x", ALICE),
+        Err(PairingError::InvalidCode)
+    );
+    assert_eq!(codes.redeem(&code, ALICE), Ok("sender".to_owned()));
+}
+
+async fn redeem_over_rest(
+    state: &RelayState,
+    code: &str,
+    passphrase: Option<&str>,
+) -> (StatusCode, Value) {
+    let mut headers = HeaderMap::new();
+    if let Some(passphrase) = passphrase {
+        headers.insert(
+            PASSPHRASE_HEADER,
+            HeaderValue::from_str(passphrase).unwrap(),
+        );
+    }
+    let response = handle_redeem_pairing_code(
+        Path(code.to_owned()),
+        ConnectInfo(SocketAddr::new(ALICE, 443)),
+        State(state.clone()),
+        headers,
+    )
+    .await
+    .into_response();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn rest_redemption_of_protected_shares_needs_the_passphrase() {
+    let state = RelayState::new();
+    state
+        .share_locks
+        .protect("sender", Some("hunter2".into()))
+        .await
+        .unwrap();
+    let code = state.pairing.mint("sender", PairingCodeStyle::Digits);
+
+    let (status, _) = redeem_over_rest(&state, &code, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = redeem_over_rest(&state, &code, Some("hunter3")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = redeem_over_rest(&state, &code, Some("hunter2")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["senderId"], json!("sender"));
+    let (status, _) = redeem_over_rest(&state, &code, Some("hunter2")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
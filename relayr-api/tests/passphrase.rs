@@ -0,0 +1,162 @@
+mod common;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use common::ShareBuilder;
+use relayr_api::feature::relay::{
+    handlers::handle_get_file_metadata,
+    passphrase::{PassphraseError, ShareLocks},
+    state::RelayState,
+};
+
+const ALICE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const BOB: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+fn locks() -> ShareLocks {
+    ShareLocks::new(2, Duration::from_secs(300))
+}
+
+#[tokio::test]
+async fn unprotected_shares_need_no_passphrase() {
+    let locks = locks();
+
+    assert!(!locks.is_protected("sender"));
+    assert_eq!(locks.verify("sender", None, ALICE).await, Ok(()));
+}
+
+#[tokio::test]
+async fn protected_shares_check_the_passphrase() {
+    let locks = locks();
+    locks
+        .protect("sender", Some("correct horse".into()))
+        .await
+        .unwrap();
+
+    assert!(locks.is_protected("sender"));
+    assert_eq!(
+        locks.verify("sender", None, ALICE).await,
+        Err(PassphraseError::Required)
+    );
+    assert_eq!(
+        locks.verify("sender", Some("battery staple"), ALICE).await,
+        Err(PassphraseError::Wrong)
+    );
+    assert_eq!(
+        locks.verify("sender", Some("correct horse"), ALICE).await,
+        Ok(())
+    );
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_client_out() {
+    let locks = locks();
+    locks
+        .protect("sender", Some("secret".into()))
+        .await
+        .unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(
+            locks.verify("sender", Some("guess"), ALICE).await,
+            Err(PassphraseError::Wrong)
+        );
+    }
+
+    assert!(matches!(
+        locks.verify("sender", Some("secret"), ALICE).await,
+        Err(PassphraseError::LockedOut { .. })
+    ));
+    assert_eq!(locks.verify("sender", Some("secret"), BOB).await, Ok(()));
+}
+
+#[tokio::test]
+async fn announcing_without_a_passphrase_lifts_the_protection() {
+    let locks = locks();
+    locks
+        .protect("sender", Some("secret".into()))
+        .await
+        .unwrap();
+    locks.protect("sender", None).await.unwrap();
+
+    assert_eq!(locks.verify("sender", None, ALICE).await, Ok(()));
+}
+
+#[tokio::test]
+async fn rejected_announcements_get_the_old_passphrase_back() {
+    let locks = locks();
+    locks
+        .protect("sender", Some("secret".into()))
+        .await
+        .unwrap();
+
+    let replaced = locks.protect("sender", None).await.unwrap();
+    locks.restore("sender", replaced);
+    assert_eq!(
+        locks.verify("sender", None, ALICE).await,
+        Err(PassphraseError::Required)
+    );
+
+    let replaced = locks.protect("sender", Some("other".into())).await.unwrap();
+    locks.restore("sender", replaced);
+    assert_eq!(locks.verify("sender", Some("secret"), ALICE).await, Ok(()));
+
+    let replaced = locks.protect("idle", Some("secret".into())).await.unwrap();
+    locks.restore("idle", replaced);
+    assert!(!locks.is_protected("idle"));
+}
+
+#[tokio::test]
+async fn released_passphrases_expire() {
+    let locks = locks();
+    locks
+        .protect("sender", Some("secret".into()))
+        .await
+        .unwrap();
+
+    locks.release_after("sender", Duration::from_secs(60));
+    assert!(locks.is_protected("sender"));
+
+    locks.release_after("sender", Duration::ZERO);
+    assert!(!locks.is_protected("sender"));
+}
+
+async fn file_metadata_status(state: &RelayState, sender_id: &str) -> StatusCode {
+    handle_get_file_metadata(
+        Path(sender_id.to_owned()),
+        ConnectInfo(SocketAddr::new(ALICE, 443)),
+        State(state.clone()),
+        HeaderMap::new(),
+    )
+    .await
+    .into_response()
+    .status()
+}
+
+// The passphrase guards a share's files, not whether it exists.
+#[tokio::test]
+async fn unknown_shares_are_not_found_before_any_passphrase_is_asked_for() {
+    let share = ShareBuilder::new(&[]).build();
+    share
+        .state
+        .share_locks
+        .protect("sender", Some("hunter2".into()))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        file_metadata_status(&share.state, "sender").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        file_metadata_status(&share.state, "nobody").await,
+        StatusCode::NOT_FOUND
+    );
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use common::{ShareBuilder, control};
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    error::ErrorCode,
+    protocol::{Capability, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, UnsupportedVersion, negotiate},
+    ws::dto::response::RelayOutgoingPayload,
+};
+use serde_json::json;
+use tokio::time::timeout;
+
+#[test]
+fn negotiation_settles_on_the_lower_version() {
+    let enabled = Capability::enabled(false);
+
+    let negotiated = negotiate(PROTOCOL_VERSION + 5, &[], &enabled).unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+
+    let negotiated = negotiate(MIN_PROTOCOL_VERSION, &[], &enabled).unwrap();
+    assert_eq!(negotiated.version, MIN_PROTOCOL_VERSION);
+}
+
+#[test]
+fn versions_older_than_the_minimum_are_rejected() {
+    assert_eq!(
+        negotiate(MIN_PROTOCOL_VERSION - 1, &[], &Capability::enabled(true)),
+        Err(UnsupportedVersion {
+            requested: MIN_PROTOCOL_VERSION - 1,
+        })
+    );
+}
+
+#[test]
+fn features_are_those_both_sides_support() {
+    let client: Vec<Capability> =
+        serde_json::from_str(r#"["integrity", "storeAndForward", "teleportation"]"#).unwrap();
+    assert_eq!(client[2], Capability::Unknown);
+
+    let without_spool = negotiate(PROTOCOL_VERSION, &client, &Capability::enabled(false)).unwrap();
+    assert_eq!(without_spool.features, vec![Capability::Integrity]);
+
+    let with_spool = negotiate(PROTOCOL_VERSION, &client, &Capability::enabled(true)).unwrap();
+    assert_eq!(
+        with_spool.features,
+        vec![Capability::Integrity, Capability::StoreAndForward]
+    );
+}
+
+#[tokio::test]
+async fn nothing_is_answered_after_a_rejected_hello() {
+    let mut share = ShareBuilder::new(&["recipient"]).build();
+    let client = share.connect("recipient").await;
+
+    client.send(json!({ "type": "hello", "protocolVersion": MIN_PROTOCOL_VERSION - 1 }));
+    client.send(json!({ "type": "hello", "protocolVersion": PROTOCOL_VERSION }));
+    timeout(Duration::from_secs(5), client.task)
+        .await
+        .expect("the relay kept reading")
+        .unwrap();
+
+    let rx = share.rx("recipient");
+    assert!(matches!(
+        control(rx.try_recv().unwrap()).payload,
+        RelayOutgoingPayload::Error(err) if err.code == ErrorCode::UnsupportedProtocolVersion
+    ));
+    assert!(matches!(
+        rx.try_recv(),
+        Ok(Outgoing::Frame(Message::Close(Some(frame)))) if frame.code == 1002
+    ));
+    assert!(rx.try_recv().is_err());
+}
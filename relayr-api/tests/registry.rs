@@ -0,0 +1,578 @@
+mod common;
+
+use std::{
+    sync::{Arc, Barrier},
+    time::Duration,
+};
+
+use axum::extract::ws::Message;
+use common::{ShareBuilder, file_metadata, peer_tx, registry_with_peers};
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    registry::{PairError, RegisterError, SessionRegistry, UnpairError},
+    session::{AckOutcome, ResumePoint, ShareMode, TransferEvent, TransferState},
+    types::{FileMetadata, PeerIdPolicy, SharedFiles},
+};
+
+const MAX_RECIPIENTS: usize = 10;
+
+fn announce(registry: &SessionRegistry, sender_id: &str) {
+    registry
+        .transition(sender_id, TransferEvent::Announce)
+        .unwrap();
+}
+
+fn ack(registry: &SessionRegistry, recipient_id: &str, chunk_index: u32) -> AckOutcome {
+    registry
+        .record_ack(
+            "sender",
+            recipient_id,
+            chunk_index,
+            100,
+            "acknowledged",
+            Message::Text(format!("{recipient_id}:{chunk_index}").into()).into(),
+        )
+        .unwrap()
+}
+
+#[test]
+fn pair_sets_forward_and_reverse_index() {
+    let registry = registry_with_peers(&["sender", "recipient"]);
+    announce(&registry, "sender");
+
+    assert!(registry.pair("sender", "recipient", MAX_RECIPIENTS).is_ok());
+    assert_eq!(
+        registry.recipients_of("sender"),
+        vec!["recipient".to_owned()]
+    );
+    assert_eq!(registry.sender_of("recipient").as_deref(), Some("sender"));
+}
+
+#[test]
+fn pair_requires_both_peers_connected() {
+    let registry = registry_with_peers(&["sender"]);
+
+    assert_eq!(
+        registry
+            .pair("missing", "sender", MAX_RECIPIENTS)
+            .unwrap_err(),
+        PairError::SenderNotConnected
+    );
+    assert_eq!(
+        registry
+            .pair("sender", "missing", MAX_RECIPIENTS)
+            .unwrap_err(),
+        PairError::RecipientNotConnected
+    );
+}
+
+#[test]
+fn unpair_recipient_rejects_mismatched_recipient() {
+    let registry = registry_with_peers(&["sender", "a", "b"]);
+    announce(&registry, "sender");
+    registry.pair("sender", "a", MAX_RECIPIENTS).unwrap();
+
+    assert_eq!(
+        registry.unpair_recipient("sender", "b").err(),
+        Some(UnpairError::RecipientMismatch {
+            current_recipient: "a".into()
+        })
+    );
+    assert!(registry.unpair_recipient("sender", "a").unwrap().is_none());
+    assert_eq!(
+        registry.unpair_recipient("sender", "a").err(),
+        Some(UnpairError::NotPaired)
+    );
+    assert!(registry.sender_of("a").is_none());
+}
+
+#[test]
+fn remove_peer_clears_both_directions() {
+    let registry = registry_with_peers(&["sender", "recipient"]);
+    announce(&registry, "sender");
+    registry
+        .pair("sender", "recipient", MAX_RECIPIENTS)
+        .unwrap();
+
+    let recipient_tx = registry.peer_tx("recipient").unwrap();
+    let removed = registry.remove_peer("recipient", &recipient_tx).unwrap();
+    assert_eq!(removed.sender_id.as_deref(), Some("sender"));
+    assert!(removed.recipient_ids.is_empty());
+    assert!(registry.recipients_of("sender").is_empty());
+    assert!(registry.peer_tx("recipient").is_none());
+    assert_eq!(
+        registry.session_state("sender"),
+        Some(TransferState::Announced)
+    );
+
+    // The sender can pair again once the old recipient is gone.
+    registry
+        .register_peer(Some("recipient-2"), peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+    assert!(
+        registry
+            .pair("sender", "recipient-2", MAX_RECIPIENTS)
+            .is_ok()
+    );
+}
+
+#[test]
+fn concurrent_recipients_cannot_pair_with_the_same_sender() {
+    const RECIPIENTS: usize = 64;
+
+    for _ in 0..50 {
+        let mut peer_ids: Vec<String> = (0..RECIPIENTS).map(|i| format!("r{i}")).collect();
+        peer_ids.push("sender".into());
+        let registry = registry_with_peers(&peer_ids);
+        announce(&registry, "sender");
+        let barrier = Arc::new(Barrier::new(RECIPIENTS));
+
+        let handles: Vec<_> = (0..RECIPIENTS)
+            .map(|i| {
+                let registry = registry.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    registry
+                        .pair("sender", &format!("r{i}"), MAX_RECIPIENTS)
+                        .is_ok()
+                })
+            })
+            .collect();
+
+        let winners = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|paired| *paired)
+            .count();
+        assert_eq!(winners, 1);
+
+        let recipients = registry.recipients_of("sender");
+        assert_eq!(recipients.len(), 1);
+        let recipient = &recipients[0];
+        assert_eq!(registry.sender_of(recipient).as_deref(), Some("sender"));
+        let paired_recipients = (0..RECIPIENTS)
+            .filter(|i| registry.sender_of(&format!("r{i}")).is_some())
+            .count();
+        assert_eq!(paired_recipients, 1);
+    }
+}
+
+#[test]
+fn concurrent_senders_cannot_pair_with_the_same_recipient() {
+    const SENDERS: usize = 64;
+
+    for _ in 0..50 {
+        let mut peer_ids: Vec<String> = (0..SENDERS).map(|i| format!("s{i}")).collect();
+        peer_ids.push("recipient".into());
+        let registry = registry_with_peers(&peer_ids);
+        for i in 0..SENDERS {
+            announce(&registry, &format!("s{i}"));
+        }
+        let barrier = Arc::new(Barrier::new(SENDERS));
+
+        let handles: Vec<_> = (0..SENDERS)
+            .map(|i| {
+                let registry = registry.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    registry
+                        .pair(&format!("s{i}"), "recipient", MAX_RECIPIENTS)
+                        .is_ok()
+                })
+            })
+            .collect();
+
+        let winners = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|paired| *paired)
+            .count();
+        assert_eq!(winners, 1);
+
+        let sender = registry.sender_of("recipient").unwrap();
+        assert_eq!(
+            registry.recipients_of(&sender),
+            vec!["recipient".to_owned()]
+        );
+    }
+}
+
+#[test]
+fn concurrent_pair_and_remove_leave_no_dangling_entries() {
+    for _ in 0..200 {
+        let registry = registry_with_peers(&["sender", "recipient"]);
+        announce(&registry, "sender");
+        let recipient_tx = registry.peer_tx("recipient").unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+
+        let pairing = {
+            let registry = registry.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                let _ = registry.pair("sender", "recipient", MAX_RECIPIENTS);
+            })
+        };
+        let removal = {
+            let registry = registry.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                registry.remove_peer("recipient", &recipient_tx);
+            })
+        };
+        pairing.join().unwrap();
+        removal.join().unwrap();
+
+        assert!(registry.recipients_of("sender").is_empty());
+        assert!(registry.sender_of("recipient").is_none());
+    }
+}
+
+#[test]
+fn register_peer_mints_an_id_when_none_is_requested() {
+    let registry = SessionRegistry::new();
+
+    let first = registry
+        .register_peer(None, peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+    let second = registry
+        .register_peer(None, peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+
+    assert!(!first.peer_id.is_empty());
+    assert_ne!(first.peer_id, second.peer_id);
+    assert!(registry.peer_tx(&first.peer_id).is_some());
+}
+
+#[test]
+fn register_peer_rejects_duplicate_id() {
+    let registry = registry_with_peers(&["peer"]);
+
+    assert_eq!(
+        registry
+            .register_peer(Some("peer"), peer_tx(), PeerIdPolicy::Reject)
+            .unwrap_err(),
+        RegisterError::PeerIdTaken
+    );
+}
+
+#[test]
+fn register_peer_take_over_keeps_state_from_old_connection() {
+    let registry = registry_with_peers(&["sender", "recipient"]);
+    announce(&registry, "sender");
+    registry
+        .pair("sender", "recipient", MAX_RECIPIENTS)
+        .unwrap();
+    let old_tx = registry.peer_tx("sender").unwrap();
+    let new_tx = peer_tx();
+
+    let registration = registry
+        .register_peer(Some("sender"), new_tx.clone(), PeerIdPolicy::TakeOver)
+        .unwrap();
+    assert_eq!(registration.peer_id, "sender");
+    assert!(registration.replaced.unwrap().same_channel(&old_tx));
+
+    // Cleanup of the replaced socket must not touch the new owner.
+    assert!(registry.remove_peer("sender", &old_tx).is_none());
+    assert!(registry.peer_tx("sender").unwrap().same_channel(&new_tx));
+    assert_eq!(
+        registry.recipients_of("sender"),
+        vec!["recipient".to_owned()]
+    );
+}
+
+#[test]
+fn register_peer_suffix_keeps_both_connections() {
+    let registry = registry_with_peers(&["peer"]);
+
+    let registration = registry
+        .register_peer(Some("peer"), peer_tx(), PeerIdPolicy::Suffix)
+        .unwrap();
+
+    assert_ne!(registration.peer_id, "peer");
+    assert!(registration.peer_id.starts_with("peer-"));
+    assert!(registry.peer_tx("peer").is_some());
+    assert!(registry.peer_tx(&registration.peer_id).is_some());
+}
+
+#[test]
+fn pair_requires_an_announced_file() {
+    let registry = registry_with_peers(&["sender", "recipient"]);
+
+    assert!(matches!(
+        registry.pair("sender", "recipient", MAX_RECIPIENTS),
+        Err(PairError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn unpair_is_rejected_while_transferring() {
+    let registry = registry_with_peers(&["sender", "recipient"]);
+    announce(&registry, "sender");
+    registry
+        .pair("sender", "recipient", MAX_RECIPIENTS)
+        .unwrap();
+    registry.transition("sender", TransferEvent::Chunk).unwrap();
+
+    assert!(matches!(
+        registry.unpair("sender"),
+        Err(UnpairError::InvalidState(_))
+    ));
+    assert_eq!(
+        registry.recipients_of("sender"),
+        vec!["recipient".to_owned()]
+    );
+}
+
+#[test]
+fn single_share_rejects_a_second_recipient() {
+    let registry = registry_with_peers(&["sender", "a", "b"]);
+    announce(&registry, "sender");
+    registry.pair("sender", "a", MAX_RECIPIENTS).unwrap();
+
+    assert_eq!(
+        registry.pair("sender", "b", MAX_RECIPIENTS).unwrap_err(),
+        PairError::SenderBusy {
+            current_recipient: "a".into()
+        }
+    );
+}
+
+#[test]
+fn broadcast_share_accepts_recipients_up_to_the_limit() {
+    let share = ShareBuilder::new(&[])
+        .idle(&["a", "b", "c"])
+        .broadcast()
+        .build();
+    let registry = share.registry();
+
+    registry.pair("sender", "a", 2).unwrap();
+    registry.pair("sender", "b", 2).unwrap();
+    assert_eq!(
+        registry.pair("sender", "c", 2).unwrap_err(),
+        PairError::ShareFull { limit: 2 }
+    );
+    assert_eq!(
+        registry.recipients_of("sender"),
+        vec!["a".to_owned(), "b".to_owned()]
+    );
+    assert_eq!(
+        registry.session_state("sender"),
+        Some(TransferState::Paired)
+    );
+}
+
+#[test]
+fn broadcast_ack_is_held_until_every_recipient_catches_up() {
+    let share = ShareBuilder::new(&["a", "b"])
+        .broadcast()
+        .transferring()
+        .build();
+    let registry = share.registry();
+
+    assert!(matches!(
+        ack(registry, "a", 0),
+        AckOutcome::Hold { first: true }
+    ));
+    assert!(matches!(ack(registry, "b", 0), AckOutcome::Forward(_)));
+}
+
+#[test]
+fn recipient_leaving_releases_the_held_ack() {
+    let share = ShareBuilder::new(&["a", "b"])
+        .broadcast()
+        .transferring()
+        .build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+
+    let b_tx = registry.peer_tx("b").unwrap();
+    let removed = registry.remove_peer("b", &b_tx).unwrap();
+    assert_eq!(removed.sender_id.as_deref(), Some("sender"));
+    assert!(matches!(
+        removed.released_ack,
+        Some(Outgoing::Frame(Message::Text(text))) if text == "a:0"
+    ));
+    assert_eq!(registry.recipients_of("sender"), vec!["a".to_owned()]);
+    assert_eq!(
+        registry.session_state("sender"),
+        Some(TransferState::Transferring)
+    );
+}
+
+#[test]
+fn drop_lagging_removes_only_slow_recipients() {
+    let share = ShareBuilder::new(&["a", "b", "c"])
+        .broadcast()
+        .transferring()
+        .build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+    ack(registry, "b", 0);
+
+    let dropped = registry.drop_lagging("sender", 0);
+    assert_eq!(dropped.recipient_ids, vec!["c".to_owned()]);
+    assert!(matches!(
+        dropped.released_ack,
+        Some(Outgoing::Frame(Message::Text(text))) if text == "a:0"
+    ));
+    assert!(registry.sender_of("c").is_none());
+    assert_eq!(
+        registry.recipients_of("sender"),
+        vec!["a".to_owned(), "b".to_owned()]
+    );
+
+    // Nothing is held any more, so a second timer for the same chunk is a no-op.
+    assert!(registry.drop_lagging("sender", 0).recipient_ids.is_empty());
+}
+
+#[test]
+fn completed_transfer_restarts_after_an_error_ack() {
+    let share = ShareBuilder::new(&["a"]).transferring().build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+    registry.transition("sender", TransferEvent::End).unwrap();
+
+    // The recipient's own checks failed after `fileEnd`.
+    assert!(matches!(
+        registry.record_ack(
+            "sender",
+            "a",
+            1,
+            0,
+            "error",
+            Message::Text("error".into()).into()
+        ),
+        Ok(AckOutcome::Forward(_))
+    ));
+    assert_eq!(
+        registry.transition("sender", TransferEvent::Restart),
+        Ok(TransferState::Paired)
+    );
+    assert_eq!(registry.recipients_of("sender"), vec!["a".to_owned()]);
+    registry.transition("sender", TransferEvent::Chunk).unwrap();
+}
+
+#[test]
+fn recipient_reconnecting_resumes_from_last_contiguous_ack() {
+    let share = ShareBuilder::new(&["a"]).transferring().build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+    ack(registry, "a", 1);
+    // Chunk 2 is missing, so chunk 3 does not move the resume point.
+    ack(registry, "a", 3);
+
+    let a_tx = registry.peer_tx("a").unwrap();
+    registry.remove_peer("a", &a_tx).unwrap();
+    registry
+        .register_peer(Some("a"), peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+
+    let pairing = registry.pair("sender", "a", MAX_RECIPIENTS).unwrap();
+    assert_eq!(
+        pairing.resume_from,
+        Some(ResumePoint {
+            chunk_index: 2,
+            byte_offset: 200,
+        })
+    );
+
+    // The parked transfer is used up.
+    registry.unpair("sender").unwrap();
+    assert!(
+        registry
+            .pair("sender", "a", MAX_RECIPIENTS)
+            .unwrap()
+            .resume_from
+            .is_none()
+    );
+}
+
+#[test]
+fn sender_announcing_a_different_file_does_not_resume() {
+    let share = ShareBuilder::new(&["a"]).transferring().build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+
+    let sender_tx = registry.peer_tx("sender").unwrap();
+    registry.remove_peer("sender", &sender_tx).unwrap();
+    registry
+        .register_peer(Some("sender"), peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+
+    let other_file = FileMetadata {
+        name: "other.bin".into(),
+        size: 1024,
+        mime_type: "application/octet-stream".into(),
+        sha256: None,
+    };
+    registry
+        .announce(
+            "sender",
+            SharedFiles::Single(other_file),
+            ShareMode::Single,
+            false,
+        )
+        .unwrap();
+    assert!(
+        registry
+            .pair("sender", "a", MAX_RECIPIENTS)
+            .unwrap()
+            .resume_from
+            .is_none()
+    );
+}
+
+#[test]
+fn sender_reconnecting_resumes_the_same_file() {
+    let share = ShareBuilder::new(&["a"]).transferring().build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+
+    let sender_tx = registry.peer_tx("sender").unwrap();
+    registry.remove_peer("sender", &sender_tx).unwrap();
+    registry
+        .register_peer(Some("sender"), peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+
+    registry
+        .announce("sender", file_metadata(1024), ShareMode::Single, false)
+        .unwrap();
+    assert_eq!(
+        registry
+            .pair("sender", "a", MAX_RECIPIENTS)
+            .unwrap()
+            .resume_from,
+        Some(ResumePoint {
+            chunk_index: 1,
+            byte_offset: 100,
+        })
+    );
+}
+
+#[test]
+fn parked_transfers_expire_after_the_resume_window() {
+    let share = ShareBuilder::new(&["a"])
+        .registry(SessionRegistry::new().with_resume_window(Duration::ZERO))
+        .transferring()
+        .build();
+    let registry = share.registry();
+    ack(registry, "a", 0);
+
+    let a_tx = registry.peer_tx("a").unwrap();
+    registry.remove_peer("a", &a_tx).unwrap();
+    registry
+        .register_peer(Some("a"), peer_tx(), PeerIdPolicy::Reject)
+        .unwrap();
+
+    assert!(
+        registry
+            .pair("sender", "a", MAX_RECIPIENTS)
+            .unwrap()
+            .resume_from
+            .is_none()
+    );
+}
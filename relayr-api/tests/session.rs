@@ -0,0 +1,181 @@
+use relayr_api::feature::relay::session::{
+    InvalidTransition, ResumePoint, TransferEvent, TransferSession, TransferState,
+};
+
+use TransferEvent as E;
+use TransferState as S;
+
+const STATES: [TransferState; 5] = [
+    S::Announced,
+    S::Paired,
+    S::Transferring,
+    S::Completed,
+    S::Cancelled,
+];
+
+const EVENTS: [TransferEvent; 10] = [
+    E::Announce,
+    E::Pair,
+    E::Join,
+    E::Unpair,
+    E::SenderAck,
+    E::Chunk,
+    E::Ack,
+    E::End,
+    E::Cancel,
+    E::Restart,
+];
+
+fn expected(state: TransferState, event: TransferEvent) -> Option<TransferState> {
+    match (state, event) {
+        (S::Announced, E::Announce) => Some(S::Announced),
+        (S::Announced, E::Pair) => Some(S::Paired),
+
+        (S::Paired, E::Join) => Some(S::Paired),
+        (S::Paired, E::Unpair) => Some(S::Announced),
+        (S::Paired, E::SenderAck) => Some(S::Paired),
+        (S::Paired, E::Chunk) => Some(S::Transferring),
+        (S::Paired, E::Cancel) => Some(S::Cancelled),
+
+        (S::Transferring, E::SenderAck) => Some(S::Transferring),
+        (S::Transferring, E::Chunk) => Some(S::Transferring),
+        (S::Transferring, E::Ack) => Some(S::Transferring),
+        (S::Transferring, E::End) => Some(S::Completed),
+        (S::Transferring, E::Cancel) => Some(S::Cancelled),
+        (S::Transferring, E::Restart) => Some(S::Paired),
+
+        (S::Completed, E::Ack) => Some(S::Completed),
+        (S::Completed, E::Unpair) => Some(S::Announced),
+        (S::Completed, E::Restart) => Some(S::Paired),
+
+        (S::Cancelled, E::Unpair) => Some(S::Announced),
+        (S::Cancelled, E::Restart) => Some(S::Paired),
+
+        _ => None,
+    }
+}
+
+#[test]
+fn transition_table_matches_spec() {
+    for state in STATES {
+        for event in EVENTS {
+            assert_eq!(
+                state.next(event),
+                expected(state, event),
+                "{state:?} + {event:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn happy_path_reaches_completed() {
+    let mut session = TransferSession::default();
+
+    for event in [
+        E::Pair,
+        E::SenderAck,
+        E::Chunk,
+        E::Ack,
+        E::Chunk,
+        E::Ack,
+        E::End,
+        E::Ack,
+    ] {
+        session.apply(event).unwrap();
+    }
+
+    assert_eq!(session.state(), S::Completed);
+}
+
+#[test]
+fn chunk_before_pairing_is_rejected() {
+    let mut session = TransferSession::default();
+
+    assert_eq!(
+        session.apply(E::Chunk),
+        Err(InvalidTransition {
+            state: Some(S::Announced),
+            event: E::Chunk,
+        })
+    );
+    assert_eq!(session.state(), S::Announced);
+}
+
+#[test]
+fn second_file_end_is_rejected() {
+    let mut session = TransferSession::default();
+    for event in [E::Pair, E::Chunk, E::End] {
+        session.apply(event).unwrap();
+    }
+
+    assert!(session.apply(E::End).is_err());
+    assert_eq!(session.state(), S::Completed);
+}
+
+#[test]
+fn restart_while_idle_is_rejected() {
+    let mut session = TransferSession::default();
+    assert!(session.apply(E::Restart).is_err());
+
+    session.apply(E::Pair).unwrap();
+    assert!(session.apply(E::Restart).is_err());
+}
+
+#[test]
+fn completed_transfer_can_restart() {
+    let mut session = TransferSession::default();
+    for event in [E::Pair, E::Chunk, E::End] {
+        session.apply(event).unwrap();
+    }
+
+    assert_eq!(session.apply(E::Restart), Ok(S::Paired));
+    for event in [E::Chunk, E::Ack, E::End] {
+        session.apply(event).unwrap();
+    }
+    assert_eq!(session.state(), S::Completed);
+}
+
+#[test]
+fn invalid_transition_names_the_current_state() {
+    let err = InvalidTransition {
+        state: Some(S::Completed),
+        event: E::End,
+    };
+    assert!(err.to_string().contains("`completed`"));
+
+    let err = InvalidTransition {
+        state: None,
+        event: E::Chunk,
+    };
+    assert!(err.to_string().contains("before a file has been announced"));
+}
+
+#[test]
+fn resume_point_only_advances_over_contiguous_acks() {
+    let mut session = TransferSession::default();
+    assert_eq!(session.resume_point("r"), None);
+
+    session.record_progress("r", 1, 10);
+    assert_eq!(session.resume_point("r"), None);
+
+    session.record_progress("r", 0, 10);
+    session.record_progress("r", 0, 10);
+    session.record_progress("r", 3, 5);
+    assert_eq!(
+        session.resume_point("r"),
+        Some(ResumePoint {
+            chunk_index: 2,
+            byte_offset: 20,
+        })
+    );
+
+    session.record_progress("r", 2, 10);
+    assert_eq!(
+        session.resume_point("r"),
+        Some(ResumePoint {
+            chunk_index: 4,
+            byte_offset: 35,
+        })
+    );
+}
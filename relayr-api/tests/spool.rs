@@ -0,0 +1,206 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use axum::extract::ws::Message;
+use nanoid::nanoid;
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    spool::{ChunkInfo, FileEndInfo, ReplayEvent, Spool, SpoolError, SpooledFrame},
+    types::{FileMetadata, SharedFiles},
+    ws::replay::spawn_spool_replay,
+};
+use tokio::{sync::mpsc, time::timeout};
+
+fn spool_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("relayr-spool-{}", nanoid!()))
+}
+
+fn single_file(size: u64) -> SharedFiles {
+    SharedFiles::Single(FileMetadata {
+        name: "file.bin".into(),
+        size,
+        mime_type: "application/octet-stream".into(),
+        sha256: None,
+    })
+}
+
+fn chunk_info(chunk_index: u32, chunk_data_size: u32) -> ChunkInfo {
+    ChunkInfo {
+        file_index: None,
+        file_name: "file.bin".into(),
+        total_size: 6,
+        total_chunks: 2,
+        uploaded_size: u64::from((chunk_index + 1) * chunk_data_size),
+        chunk_index,
+        chunk_data_size,
+        chunk_sha256: None,
+        sender_transfer_progress: 0,
+    }
+}
+
+fn end_info() -> FileEndInfo {
+    FileEndInfo {
+        file_index: None,
+        file_name: "file.bin".into(),
+        total_size: 6,
+        total_chunks: 2,
+        uploaded_size: 6,
+        last_chunk_index: 1,
+        sha256: None,
+    }
+}
+
+async fn upload(spool: &Spool, sender_id: &str, chunks: &[&[u8]]) {
+    spool.begin_upload(sender_id, single_file(6)).await.unwrap();
+    for (chunk_index, data) in chunks.iter().enumerate() {
+        spool
+            .set_pending_chunk(sender_id, chunk_info(chunk_index as u32, data.len() as u32))
+            .unwrap();
+        spool.append_chunk(sender_id, data).await.unwrap();
+    }
+    assert!(spool.end_file(sender_id, end_info()).unwrap());
+}
+
+#[tokio::test]
+async fn stored_share_replays_chunks_in_order() {
+    let dir = spool_dir();
+    let spool = Spool::new(dir.clone(), 1024, Duration::from_secs(60));
+
+    upload(&spool, "sender", &[b"abc", b"def"]).await;
+    assert!(spool.is_stored("sender"));
+    assert!(!spool.is_uploading("sender"));
+
+    let frames = spool.frames("sender").unwrap();
+    assert_eq!(frames.len(), 3);
+    let mut replayed = Vec::new();
+    for frame in &frames {
+        if let SpooledFrame::Chunk(chunk) = frame {
+            replayed.extend(spool.read_chunk("sender", chunk).await.unwrap());
+        }
+    }
+    assert_eq!(replayed, b"abcdef");
+    assert!(matches!(frames.last(), Some(SpooledFrame::End(_))));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn share_over_quota_is_rejected() {
+    let dir = spool_dir();
+    let spool = Spool::new(dir.clone(), 4, Duration::from_secs(60));
+
+    assert!(matches!(
+        spool.begin_upload("big", single_file(5)).await,
+        Err(SpoolError::QuotaExceeded { quota: 4 })
+    ));
+
+    // The announced size can understate what is actually sent.
+    spool.begin_upload("sender", single_file(4)).await.unwrap();
+    spool.set_pending_chunk("sender", chunk_info(0, 3)).unwrap();
+    spool.append_chunk("sender", b"abc").await.unwrap();
+    spool.set_pending_chunk("sender", chunk_info(1, 3)).unwrap();
+    assert!(matches!(
+        spool.append_chunk("sender", b"def").await,
+        Err(SpoolError::QuotaExceeded { quota: 4 })
+    ));
+    assert!(!spool.is_uploading("sender"));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn binary_frame_needs_chunk_info() {
+    let dir = spool_dir();
+    let spool = Spool::new(dir.clone(), 1024, Duration::from_secs(60));
+
+    spool.begin_upload("sender", single_file(6)).await.unwrap();
+    assert!(matches!(
+        spool.append_chunk("sender", b"abc").await,
+        Err(SpoolError::MissingChunkInfo)
+    ));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn expired_and_abandoned_shares_are_removed_from_disk() {
+    let dir = spool_dir();
+    let spool = Spool::new(dir.clone(), 1024, Duration::ZERO);
+
+    upload(&spool, "stored", &[b"abc"]).await;
+    spool.begin_upload("partial", single_file(6)).await.unwrap();
+    spool
+        .set_pending_chunk("partial", chunk_info(0, 3))
+        .unwrap();
+    spool.append_chunk("partial", b"abc").await.unwrap();
+
+    spool.abandon_upload("partial").await;
+    assert!(!spool.is_uploading("partial"));
+    // Completed shares are only removed once they expire.
+    spool.abandon_upload("stored").await;
+    assert!(spool.is_stored("stored"));
+
+    assert_eq!(spool.purge_expired().await, 1);
+    assert!(spool.frames("stored").is_none());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn stored_shares_left_behind_cannot_be_replaced() {
+    let dir = spool_dir();
+    let spool = Spool::new(dir.clone(), 1024, Duration::from_secs(60));
+
+    // The uploader may replace its share while it is still connected.
+    upload(&spool, "sender", &[b"abc", b"def"]).await;
+    upload(&spool, "sender", &[b"ghi", b"jkl"]).await;
+
+    spool.leave_behind("sender");
+    assert!(spool.is_left_behind("sender"));
+    assert!(matches!(
+        spool.begin_upload("sender", single_file(6)).await,
+        Err(SpoolError::LeftBehind)
+    ));
+    assert!(spool.is_stored("sender"));
+
+    // Once it is gone, the ID is free again.
+    assert!(spool.remove("sender").await);
+    assert!(!spool.is_left_behind("sender"));
+    spool.begin_upload("sender", single_file(6)).await.unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn replay_waits_for_acks_once_the_window_is_full() {
+    let dir = spool_dir();
+    let spool = Arc::new(Spool::new(dir.clone(), 1024, Duration::from_secs(60)));
+    upload(&spool, "sender", &[b"abc", b"def"]).await;
+
+    let (tx, mut rx) = mpsc::channel(100);
+    // Room for a single 3-byte chunk.
+    let replay = spawn_spool_replay(spool.clone(), "sender".into(), "recipient".into(), tx, 3);
+
+    // `senderAck`, then the first `fileChunk` and its binary frame.
+    for _ in 0..2 {
+        assert!(matches!(rx.recv().await, Some(Outgoing::Control(_))));
+    }
+    assert!(matches!(
+        rx.recv().await,
+        Some(Outgoing::Frame(Message::Binary(data))) if data == "abc"
+    ));
+    assert!(timeout(Duration::from_millis(50), rx.recv()).await.is_err());
+
+    // Another recipient's ack does not count.
+    assert!(!spool.pace_replay("sender", "other", ReplayEvent::Acked));
+    assert!(spool.pace_replay("sender", "recipient", ReplayEvent::Acked));
+    assert!(matches!(rx.recv().await, Some(Outgoing::Control(_))));
+    assert!(matches!(
+        rx.recv().await,
+        Some(Outgoing::Frame(Message::Binary(data))) if data == "def"
+    ));
+    assert!(matches!(rx.recv().await, Some(Outgoing::Control(_))));
+
+    replay.await.unwrap();
+    assert!(!spool.pace_replay("sender", "recipient", ReplayEvent::Acked));
+    let _ = std::fs::remove_dir_all(dir);
+}
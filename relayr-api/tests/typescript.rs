@@ -0,0 +1,22 @@
+use std::{env, fs, path::Path};
+
+use relayr_api::feature::relay::typescript::protocol_declarations;
+
+const GENERATED: &str = "../relayr-ui/src/types/webSocketMessages/generated.ts";
+
+#[test]
+fn generated_typescript_is_up_to_date() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(GENERATED);
+    let expected = protocol_declarations();
+
+    if env::var_os("UPDATE_TS_BINDINGS").is_some() {
+        fs::write(&path, &expected).unwrap();
+        return;
+    }
+
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        current == expected,
+        "{GENERATED} is stale; run `UPDATE_TS_BINDINGS=1 cargo test --test typescript`"
+    );
+}
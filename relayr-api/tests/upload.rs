@@ -0,0 +1,175 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::ws::Message,
+    http::{HeaderMap, HeaderValue, header},
+};
+use relayr_api::feature::relay::{
+    codec::Outgoing,
+    http::upload::{HttpSender, HttpUpload, HttpUploadError},
+    state::RelayState,
+    types::PeerIdPolicy,
+    ws::dto::response::{
+        AsWsMessage, FileTransferAckResponseDto, RecipientReadyResponseDto, RelayOutgoingPayload,
+    },
+};
+use tokio::sync::mpsc::{self, Receiver};
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+fn headers(content_length: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_LENGTH,
+        HeaderValue::from_str(content_length).unwrap(),
+    );
+    headers
+}
+
+fn upload(size: u64) -> HttpUpload {
+    HttpUpload {
+        name: "build.log".into(),
+        size,
+        mime_type: "text/plain".into(),
+        passphrase: None,
+    }
+}
+
+async fn next_payload(rx: &mut Receiver<Outgoing>) -> RelayOutgoingPayload {
+    match rx.recv().await.unwrap() {
+        Outgoing::Control(msg) => msg.payload,
+        other => panic!("expected a control message, got {other:?}"),
+    }
+}
+
+#[test]
+fn uploads_are_described_by_their_headers() {
+    let mut headers = headers("5");
+    headers.insert("x-share-passphrase", HeaderValue::from_static("secret"));
+
+    let upload = HttpUpload::from_request("build.log".into(), &headers).unwrap();
+    assert_eq!(upload.size, 5);
+    assert_eq!(upload.mime_type, "application/octet-stream");
+    assert_eq!(upload.passphrase.as_deref(), Some("secret"));
+    assert_eq!(upload.total_chunks(), 1);
+}
+
+#[test]
+fn uploads_need_a_name_and_a_length() {
+    assert_eq!(
+        HttpUpload::from_request("..".into(), &headers("5")),
+        Err(HttpUploadError::InvalidName("..".into()))
+    );
+    assert_eq!(
+        HttpUpload::from_request("a\\b".into(), &headers("5")),
+        Err(HttpUploadError::InvalidName("a\\b".into()))
+    );
+    assert_eq!(
+        HttpUpload::from_request("build.log".into(), &HeaderMap::new()),
+        Err(HttpUploadError::LengthRequired)
+    );
+    assert_eq!(
+        HttpUpload::from_request("build.log".into(), &headers("0")),
+        Err(HttpUploadError::Empty)
+    );
+    assert!(matches!(
+        HttpUpload::from_request("build.log".into(), &headers(&u64::MAX.to_string())),
+        Err(HttpUploadError::TooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn uploads_stream_the_body_to_the_recipient() {
+    let state = RelayState::new();
+    let (recipient_tx, mut recipient_rx) = mpsc::channel(100);
+    state
+        .registry
+        .register_peer(Some("recipient"), recipient_tx, PeerIdPolicy::Reject)
+        .unwrap();
+
+    let sender = HttpSender::announce(state.clone(), upload(5), CLIENT)
+        .await
+        .unwrap();
+    let sender_id = sender.sender_id().to_owned();
+    assert!(sender.share_link().ends_with(&format!("?id={sender_id}")));
+    let (progress_tx, mut progress_rx) = mpsc::channel(16);
+    let upload_task = tokio::spawn(sender.run(Body::from("hello"), progress_tx));
+
+    // Play the recipient's side as the read handlers would.
+    let pairing = state.registry.pair(&sender_id, "recipient", 10).unwrap();
+    pairing
+        .sender_tx
+        .send(RecipientReadyResponseDto::new("recipient", &sender_id).as_ws_message())
+        .await
+        .unwrap();
+    let ack = |status: &str, chunk_index| {
+        FileTransferAckResponseDto::new(
+            "recipient",
+            &sender_id,
+            status,
+            "build.log",
+            1,
+            5,
+            chunk_index,
+            5,
+            100,
+        )
+        .as_ws_message()
+    };
+
+    assert!(matches!(
+        next_payload(&mut recipient_rx).await,
+        RelayOutgoingPayload::SenderAck(ack) if ack.request_type == "recipientReady"
+    ));
+    let RelayOutgoingPayload::FileChunk(chunk) = next_payload(&mut recipient_rx).await else {
+        panic!("expected a fileChunk");
+    };
+    assert_eq!((chunk.chunk_index, chunk.chunk_data_size), (0, 5));
+    assert!(matches!(
+        recipient_rx.recv().await,
+        Some(Outgoing::Frame(Message::Binary(data))) if data == "hello"
+    ));
+    pairing
+        .sender_tx
+        .send(ack("acknowledged", 0))
+        .await
+        .unwrap();
+
+    let RelayOutgoingPayload::FileEnd(end) = next_payload(&mut recipient_rx).await else {
+        panic!("expected a fileEnd");
+    };
+    assert_eq!((end.uploaded_size, end.last_chunk_index), (5, 1));
+    pairing.sender_tx.send(ack("completed", 1)).await.unwrap();
+
+    upload_task.await.unwrap();
+    let mut lines = Vec::new();
+    while let Some(line) = progress_rx.recv().await {
+        lines.push(line);
+    }
+    assert_eq!(lines.last().map(String::as_str), Some("Sent 5 bytes.\n"));
+    assert!(state.registry.peer_tx(&sender_id).is_none());
+}
+
+#[tokio::test]
+async fn uploads_give_up_without_a_recipient() {
+    let state = RelayState::new();
+    let sender = HttpSender::announce(state.clone(), upload(5), CLIENT)
+        .await
+        .unwrap()
+        .with_pair_timeout(Duration::from_millis(20));
+    let sender_id = sender.sender_id().to_owned();
+
+    let (progress_tx, mut progress_rx) = mpsc::channel(16);
+    sender.run(Body::from("hello"), progress_tx).await;
+
+    let mut last_line = String::new();
+    while let Some(line) = progress_rx.recv().await {
+        last_line = line;
+    }
+    assert!(last_line.starts_with("Upload failed: no recipient connected"));
+    assert!(state.registry.peer_tx(&sender_id).is_none());
+}
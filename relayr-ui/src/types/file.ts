@@ -3,4 +3,19 @@ export interface FileMetadata {
   name: string;
   size: number;
   type: string;
+  sha256?: string; // Hex-encoded SHA-256 of the whole file
+}
+
+// One file of a multi-file share; `path` is relative to the destination folder
+export interface ManifestEntry {
+  path: string;
+  size: number;
+  type: string;
+  sha256?: string; // Hex-encoded SHA-256 of the whole file
+}
+
+// Every file announced with a `fileManifest` message
+export interface FileManifest {
+  files: ManifestEntry[];
+  totalSize: number;
 }
@@ -0,0 +1,121 @@
+// Generated from the relayr-api protocol types. Do not edit by hand;
+// regenerate with `UPDATE_TS_BINDINGS=1 cargo test --test typescript`.
+
+export type Capability = "broadcast" | "manifest" | "resume" | "storeAndForward" | "flowControl" | "integrity" | "frameEnvelope" | "e2ee";
+
+export type Limits = { maxChunkSize: number, maxFileSize: number, maxShareRecipients: number, };
+
+export type ShareMode = "single" | "broadcast";
+
+export type PairingCodeStyle = "digits" | "words";
+
+export type ErrorCode = "invalidPayload" | "invalidManifest" | "invalidFrame" | "peerIdTaken" | "unsupportedProtocolVersion" | "limitExceeded" | "forbidden" | "senderAlreadyConnected" | "recipientAlreadyConnected" | "shareFull" | "recipientDropped" | "shareNotReady" | "spoolUnavailable" | "spoolQuotaExceeded" | "flowControlBlocked" | "integrityMismatch" | "senderDisconnected" | "recipientDisconnected" | "invalidPairingCode" | "pairingThrottled" | "passphraseRequired" | "wrongPassphrase" | "passphraseLockedOut" | "encryptionRequired" | "keyExchangeIncomplete" | "activeConnectionNotFound" | "recipientMismatch" | "invalidStateTransition" | "unsupportedWsMessageType" | "unsupportedWsMessageTextType" | "unknown";
+
+export type ErrorContext = { senderId?: string, recipientId?: string, expectedRecipientId?: string, actualRecipientId?: string, fileIndex?: number, chunkIndex?: number, 
+/**
+ * The limit that was hit, in the unit of `actual`.
+ */
+limit?: number, actual?: number, 
+/**
+ * Seconds to wait before the request can succeed.
+ */
+retryAfterSecs?: number, };
+
+export type ErrorMessage = { code: ErrorCode, numericCode: number, retryable: boolean, message: string, details?: string, 
+/**
+ * `type` of the message whose handling failed, or `binary` for a
+ * binary frame.
+ */
+relatedMessageType?: string, context?: ErrorContext, };
+
+export type RelayIncomingMessage = { requestId?: string, } & ({ "type": "hello" } & HelloPayload | { "type": "recipientReady" } & RecipientReadyPayload | { "type": "createPairingCode" } & CreatePairingCodePayload | { "type": "keyExchange" } & KeyExchangePayload | { "type": "fileMeta" } & FileMetadataPayload | { "type": "fileManifest" } & FileManifestPayload | { "type": "cancelRecipientReady" } & CancelRecipientReadyPayload | { "type": "cancelSenderReady" } & CancelSenderReadyPayload | { "type": "fileChunk" } & FileChunkPayload | { "type": "fileTransferAck" } & FileTransferAckPayload | { "type": "grantCredits" } & GrantCreditsPayload | { "type": "fileEnd" } & FileEndPayload | { "type": "cancelSenderTransfer" } & CancelSenderTransferPayload | { "type": "cancelRecipientTransfer" } & CancelRecipientTransferPayload | { "type": "senderAck" } & SenderAckPayload | { "type": "restartTransfer" } | { "type": "userClose" } & UserClosePayload | { "type": "terminate" });
+
+export type RelayIncomingPayload = { "type": "hello" } & HelloPayload | { "type": "recipientReady" } & RecipientReadyPayload | { "type": "createPairingCode" } & CreatePairingCodePayload | { "type": "keyExchange" } & KeyExchangePayload | { "type": "fileMeta" } & FileMetadataPayload | { "type": "fileManifest" } & FileManifestPayload | { "type": "cancelRecipientReady" } & CancelRecipientReadyPayload | { "type": "cancelSenderReady" } & CancelSenderReadyPayload | { "type": "fileChunk" } & FileChunkPayload | { "type": "fileTransferAck" } & FileTransferAckPayload | { "type": "grantCredits" } & GrantCreditsPayload | { "type": "fileEnd" } & FileEndPayload | { "type": "cancelSenderTransfer" } & CancelSenderTransferPayload | { "type": "cancelRecipientTransfer" } & CancelRecipientTransferPayload | { "type": "senderAck" } & SenderAckPayload | { "type": "restartTransfer" } | { "type": "userClose" } & UserClosePayload | { "type": "terminate" };
+
+export type HelloPayload = { protocolVersion: number, capabilities: Array<Capability>, };
+
+export type RecipientReadyPayload = { 
+/**
+ * Either `senderId` or a pairing `code` minted for the sender.
+ */
+senderId?: string, code?: string, recipientId?: string, passphrase?: string, };
+
+export type CreatePairingCodePayload = { senderId?: string, style?: PairingCodeStyle, };
+
+export type KeyExchangePayload = { fromId?: string, toId: string, 
+/**
+ * Hex-encoded X25519 public key.
+ */
+publicKey: string, };
+
+export type FileMetadataPayload = { senderId?: string, name: string, size: number, mimeType: string, sha256?: string, shareMode?: ShareMode, storeAndForward?: boolean, 
+/**
+ * Recipients must present this passphrase; the relay keeps only a hash.
+ */
+passphrase?: string, e2ee?: boolean, };
+
+export type FileManifestPayload = { senderId?: string, files: Array<ManifestEntryPayload>, shareMode?: ShareMode, storeAndForward?: boolean, passphrase?: string, e2ee?: boolean, };
+
+export type ManifestEntryPayload = { path: string, size: number, mimeType: string, sha256?: string, };
+
+export type CancelRecipientReadyPayload = { senderId: string, recipientId?: string, };
+
+export type CancelSenderReadyPayload = { senderId?: string, };
+
+export type FileChunkPayload = { senderId?: string, fileIndex?: number, fileName: string, totalSize: number, totalChunks: number, uploadedSize: number, chunkIndex: number, chunkDataSize: number, chunkSha256?: string, senderTransferProgress: number, };
+
+export type FileTransferAckPayload = { recipientId?: string, senderId: string, status: string, fileIndex?: number, fileName: string, totalChunks: number, uploadedSize: number, chunkIndex: number, chunkDataSize: number, recipientTransferProgress: number, };
+
+export type GrantCreditsPayload = { senderId: string, recipientId?: string, credits: number, };
+
+export type FileEndPayload = { senderId?: string, fileIndex?: number, fileName: string, totalSize: number, totalChunks: number, uploadedSize: number, lastChunkIndex: number, sha256?: string, };
+
+export type CancelSenderTransferPayload = { senderId?: string, };
+
+export type CancelRecipientTransferPayload = { senderId: string, recipientId?: string, };
+
+export type SenderAckPayload = { requestType: string, senderId?: string, recipientId: string, message?: string, };
+
+export type UserClosePayload = { userId?: string, role: string, reason?: string, };
+
+export type RelayOutgoingMessage = { success: boolean, requestId?: string, timestamp: number, } & ({ "type": "register" } & RegisterResponseDto | { "type": "hello" } & HelloResponseDto | { "type": "recipientReady" } & RecipientReadyResponseDto | { "type": "cancelRecipientReady" } & CancelRecipientReadyResponseDto | { "type": "cancelSenderReady" } & CancelSenderReadyResponseDto | { "type": "fileChunk" } & FileChunkResponseDto | { "type": "fileTransferAck" } & FileTransferAckResponseDto | { "type": "fileEnd" } & FileEndResponseDto | { "type": "cancelSenderTransfer" } & CancelSenderTransferResponseDto | { "type": "cancelRecipientTransfer" } & CancelRecipientTransferResponseDto | { "type": "senderAck" } & SenderAckResponseDto | { "type": "restartTransfer" } & RestartTransferResponseDto | { "type": "resumeFrom" } & ResumeFromResponseDto | { "type": "peerDisconnected" } & PeerDisconnectedResponseDto | { "type": "recipientDropped" } & RecipientDroppedResponseDto | { "type": "spoolStored" } & SpoolStoredResponseDto | { "type": "pairingCode" } & PairingCodeResponseDto | { "type": "keyExchange" } & KeyExchangeResponseDto | { "type": "pause" } & FlowControlResponseDto | { "type": "resume" } & FlowControlResponseDto | { "type": "error" } & ErrorMessage);
+
+export type RelayOutgoingPayload = { "type": "register" } & RegisterResponseDto | { "type": "hello" } & HelloResponseDto | { "type": "recipientReady" } & RecipientReadyResponseDto | { "type": "cancelRecipientReady" } & CancelRecipientReadyResponseDto | { "type": "cancelSenderReady" } & CancelSenderReadyResponseDto | { "type": "fileChunk" } & FileChunkResponseDto | { "type": "fileTransferAck" } & FileTransferAckResponseDto | { "type": "fileEnd" } & FileEndResponseDto | { "type": "cancelSenderTransfer" } & CancelSenderTransferResponseDto | { "type": "cancelRecipientTransfer" } & CancelRecipientTransferResponseDto | { "type": "senderAck" } & SenderAckResponseDto | { "type": "restartTransfer" } & RestartTransferResponseDto | { "type": "resumeFrom" } & ResumeFromResponseDto | { "type": "peerDisconnected" } & PeerDisconnectedResponseDto | { "type": "recipientDropped" } & RecipientDroppedResponseDto | { "type": "spoolStored" } & SpoolStoredResponseDto | { "type": "pairingCode" } & PairingCodeResponseDto | { "type": "keyExchange" } & KeyExchangeResponseDto | { "type": "pause" } & FlowControlResponseDto | { "type": "resume" } & FlowControlResponseDto | { "type": "error" } & ErrorMessage;
+
+export type RegisterResponseDto = { connId: string, };
+
+export type HelloResponseDto = { protocolVersion: number, features: Array<Capability>, limits: Limits, };
+
+export type RecipientReadyResponseDto = { recipientId: string, senderId: string, };
+
+export type CancelRecipientReadyResponseDto = { recipientId: string, senderId: string, };
+
+export type CancelSenderReadyResponseDto = { senderId: string, recipientId: string, };
+
+export type FileChunkResponseDto = { senderId: string, recipientId: string, fileIndex?: number, fileName: string, totalSize: number, totalChunks: number, uploadedSize: number, chunkIndex: number, chunkDataSize: number, chunkSha256?: string, senderTransferProgress: number, };
+
+export type FileTransferAckResponseDto = { recipientId: string, senderId: string, status: string, fileIndex?: number, fileName: string, totalChunks: number, uploadedSize: number, chunkIndex: number, chunkDataSize: number, recipientTransferProgress: number, };
+
+export type FileEndResponseDto = { senderId: string, recipientId: string, fileIndex?: number, fileName: string, totalSize: number, totalChunks: number, uploadedSize: number, lastChunkIndex: number, sha256?: string, };
+
+export type CancelSenderTransferResponseDto = { senderId: string, recipientId: string, };
+
+export type CancelRecipientTransferResponseDto = { recipientId: string, senderId: string, };
+
+export type SenderAckResponseDto = { requestType: string, senderId: string, recipientId: string, message?: string, };
+
+export type RestartTransferResponseDto = { senderId: string, recipientId: string, };
+
+export type ResumeFromResponseDto = { senderId: string, recipientId: string, chunkIndex: number, byteOffset: number, };
+
+export type PeerDisconnectedResponseDto = { peerId: string, role: string, };
+
+export type RecipientDroppedResponseDto = { senderId: string, recipientId: string, chunkIndex: number, };
+
+export type SpoolStoredResponseDto = { senderId: string, expiresAt: number, };
+
+export type PairingCodeResponseDto = { senderId: string, code: string, expiresAt: number, };
+
+export type KeyExchangeResponseDto = { senderId: string, recipientId: string, fromId: string, publicKey: string, };
+
+export type FlowControlResponseDto = { senderId: string, credits?: number, queuedBytes: number, };
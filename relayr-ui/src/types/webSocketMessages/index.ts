@@ -5,14 +5,21 @@ export * from "./receiver";
 // Import specific responses from shared, sender, and receiver
 import {
   ErrorMessageResponse,
+  HelloResponse,
+  KeyExchangeResponse,
   PeerDisconnectedResponse,
   RegisterResponse,
+  ResumeFromResponse,
 } from "./shared";
 import {
   RecipientReadyResponse,
   CancelRecipientReadyResponse,
   FileTransferAckResponse,
   CancelRecipientTransferResponse,
+  RecipientDroppedResponse,
+  SpoolStoredResponse,
+  PairingCodeResponse,
+  FlowControlResponse,
 } from "./sender";
 import {
   CancelSenderReadyResponse,
@@ -27,17 +34,27 @@ import {
 export type WebSocketSenderTextMessageResponse =
   | ErrorMessageResponse
   | RegisterResponse
+  | HelloResponse
   | PeerDisconnectedResponse
+  | ResumeFromResponse
+  | KeyExchangeResponse
   | RecipientReadyResponse
   | CancelRecipientReadyResponse
   | FileTransferAckResponse
-  | CancelRecipientTransferResponse;
+  | CancelRecipientTransferResponse
+  | RecipientDroppedResponse
+  | SpoolStoredResponse
+  | PairingCodeResponse
+  | FlowControlResponse;
 
 // Define WebSocket receiver message types
 export type WebSocketReceiverTextMessageResponse =
   | ErrorMessageResponse
   | RegisterResponse
+  | HelloResponse
   | PeerDisconnectedResponse
+  | ResumeFromResponse
+  | KeyExchangeResponse
   | CancelSenderReadyResponse
   | SenderAckResponse
   | FileChunkResponse
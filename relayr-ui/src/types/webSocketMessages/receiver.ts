@@ -9,8 +9,10 @@ import { FileTransferAckStatus, SenderAckRequestType } from "./shared";
 // Request to notify that the recipient is ready to receive the file
 export interface RecipientReadyRequest {
   type: "recipientReady";
-  senderId: string; // ID of the sender
+  senderId?: string; // ID of the sender; send either this or `code`
+  code?: string; // Pairing code minted by the sender
   recipientId?: string; // Optional recipient ID
+  passphrase?: string; // Required when the sender protected the share
 }
 
 // Request to cancel the recipient's readiness
@@ -25,6 +27,7 @@ export interface FileTransferAckRequest {
   type: "fileTransferAck";
   senderId: string; // ID of the sender
   status: FileTransferAckStatus; // Status of file transfer acknowledgment
+  fileIndex?: number; // Index into the manifest, for multi-file shares
   fileName: string; // File name
   totalChunks: number; // Total number of chunks in the file
   uploadedSize: number; // Size of the uploaded data so far
@@ -33,6 +36,14 @@ export interface FileTransferAckRequest {
   recipientTransferProgress: number; // Progress of the transfer at the recipient's end
 }
 
+// Request granting the sender credits to relay more chunks to this recipient
+export interface GrantCreditsRequest {
+  type: "grantCredits";
+  senderId: string; // ID of the sender
+  recipientId?: string; // Optional recipient ID
+  credits: number; // Number of additional chunks the recipient can take
+}
+
 // Request to cancel an ongoing file transfer by the recipient
 export interface CancelRecipientTransferRequest {
   type: "cancelRecipientTransfer";
@@ -57,12 +68,14 @@ export interface FileChunkResponse {
   type: "fileChunk"; // Type of response
   senderId: string; // ID of the sender
   recipientId: string;
+  fileIndex?: number; // Index into the manifest, for multi-file shares
   fileName: string; // File name
   totalSize: number; // Total size of the file
   totalChunks: number; // Total number of chunks
   uploadedSize: number; // Size of the uploaded data so far
   chunkIndex: number; // Index of the current chunk
   chunkDataSize: number; // Size of the current chunk
+  chunkSha256?: string; // SHA-256 of the binary frame that follows
   senderTransferProgress: number; // Sender's transfer progress
   timestamp: number; // Timestamp of the response
 }
@@ -73,11 +86,13 @@ export interface FileEndResponse {
   type: "fileEnd"; // Type of response
   senderId: string;
   recipientId: string;
+  fileIndex?: number; // Index into the manifest, for multi-file shares
   fileName: string; // File name
   totalSize: number; // Total file size
   totalChunks: number; // Total number of chunks
   uploadedSize: number; // Size uploaded so far
   lastChunkIndex: number; // Index of the last chunk
+  sha256?: string; // SHA-256 of the whole file
   timestamp: number; // Timestamp of the response
 }
 
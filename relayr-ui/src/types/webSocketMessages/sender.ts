@@ -1,4 +1,9 @@
-import { FileTransferAckStatus, SenderAckRequestType } from "./shared";
+import {
+  FileTransferAckStatus,
+  PairingCodeStyle,
+  SenderAckRequestType,
+  ShareMode,
+} from "./shared";
 
 // ====================================================
 // 🟥 Sender Section
@@ -13,6 +18,29 @@ export interface FileMetaRequest {
   name: string;
   size: number;
   mimeType: string;
+  sha256?: string; // Hex-encoded SHA-256 of the whole file
+  shareMode?: ShareMode;
+  storeAndForward?: boolean;
+  passphrase?: string; // Recipients must present it; the relay keeps only a hash
+  e2ee?: boolean; // Chunks must be encrypted once every recipient swapped keys
+}
+
+// Request announcing several files, e.g. a folder
+export interface FileManifestRequest {
+  type: "fileManifest";
+  senderId?: string;
+  files: { path: string; size: number; mimeType: string; sha256?: string }[];
+  shareMode?: ShareMode;
+  storeAndForward?: boolean;
+  passphrase?: string;
+  e2ee?: boolean;
+}
+
+// Request for a short code recipients can type instead of the sender ID
+export interface CreatePairingCodeRequest {
+  type: "createPairingCode";
+  senderId?: string;
+  style?: PairingCodeStyle; // Six digits by default, or three words
 }
 
 // Request to cancel the sender's readiness
@@ -25,12 +53,14 @@ export interface CancelSenderReadyRequest {
 export interface FileChunkRequest {
   type: "fileChunk";
   senderId?: string;
+  fileIndex?: number;
   fileName: string;
   totalSize: number;
   totalChunks: number;
   uploadedSize: number;
   chunkIndex: number;
   chunkDataSize: number;
+  chunkSha256?: string; // Hex-encoded SHA-256 of this chunk's binary frame
   senderTransferProgress: number;
 }
 
@@ -38,11 +68,13 @@ export interface FileChunkRequest {
 export interface FileEndRequest {
   type: "fileEnd";
   senderId?: string;
+  fileIndex?: number;
   fileName: string;
   totalSize: number;
   totalChunks: number;
   uploadedSize: number;
   lastChunkIndex: number;
+  sha256?: string; // Confirms the whole-file SHA-256
 }
 
 // Request to cancel an ongoing sender transfer
@@ -91,6 +123,7 @@ export interface FileTransferAckResponse {
   status: FileTransferAckStatus;
   senderId: string;
   recipientId: string;
+  fileIndex?: number;
   fileName: string;
   totalChunks: number;
   uploadedSize: number;
@@ -108,3 +141,42 @@ export interface CancelRecipientTransferResponse {
   senderId: string;
   timestamp: number;
 }
+
+// Response when a slow recipient is dropped from a broadcast share
+export interface RecipientDroppedResponse {
+  success: true;
+  type: "recipientDropped";
+  senderId: string;
+  recipientId: string;
+  chunkIndex: number;
+  timestamp: number;
+}
+
+// Response once a store-and-forward upload has been written to the relay
+export interface SpoolStoredResponse {
+  success: true;
+  type: "spoolStored";
+  senderId: string;
+  expiresAt: number;
+  timestamp: number;
+}
+
+// Response carrying a pairing code; it works once, until expiresAt
+export interface PairingCodeResponse {
+  success: true;
+  type: "pairingCode";
+  senderId: string;
+  code: string;
+  expiresAt: number;
+  timestamp: number;
+}
+
+// Response telling the sender to stop or start sending chunks
+export interface FlowControlResponse {
+  success: true;
+  type: "pause" | "resume";
+  senderId: string;
+  credits?: number; // Fewest credits left, when recipients grant credits
+  queuedBytes: number; // Most unacknowledged bytes queued for one recipient
+  timestamp: number;
+}
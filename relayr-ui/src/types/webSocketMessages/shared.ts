@@ -2,45 +2,71 @@
 // 🟦 Shared Types
 // ====================================================
 
+import type { Capability, ErrorCode, ErrorContext } from "./generated";
+
 // Acknowledgement request type for the sender (to notify when the recipient is ready)
 export type SenderAckRequestType = "recipientReady" | "uploadOutOfSync";
 
 // Status for file transfer acknowledgment from the recipient
 export type FileTransferAckStatus = "acknowledged" | "completed" | "error";
 
-export type ErrorCode =
-  | "invalidPayload"
-  | "senderAlreadyConnected"
-  | "senderDisconnected"
-  | "recipientDisconnected"
-  | "activeConnectionNotFound"
-  | "recipientMismatch"
-  | "unsupportedWsMessageType"
-  | "unsupportedWsMessageTextType"
-  | "notHandledYet"
-  | "unknown";
+// Generated from the relayr-api protocol types, see ./generated.ts
+export type {
+  Capability,
+  ErrorCode,
+  ErrorContext,
+  PairingCodeStyle,
+  ShareMode,
+} from "./generated";
 
 // Error response structure
 export interface ErrorMessageResponse {
   success: false;
+  requestId?: string; // Echoed from the request that caused the error
+  type: "error";
   code: ErrorCode;
+  numericCode: number; // Stable numeric form of `code`
+  retryable: boolean; // Whether sending the same message again later may succeed
   message: string;
   details?: string;
+  relatedMessageType?: string; // Type of the message whose handling failed
+  context?: ErrorContext;
   timestamp: number;
 }
 
 export const errorCodeMessages: Record<ErrorCode, string> = {
   invalidPayload: "Invalid to parse websocket text message payload.",
+  invalidManifest: "The file list contains an empty, duplicate or unsafe path.",
+  invalidFrame: "A file chunk was malformed and could not be relayed.",
+  peerIdTaken: "This connection ID is already in use.",
+  unsupportedProtocolVersion: "This version of the app is no longer supported. Please reload.",
+  limitExceeded: "The file or chunk is larger than the relay allows.",
+  forbidden: "This connection is not allowed to act for that peer.",
   senderAlreadyConnected: "Sender is already connected to another recipient.",
+  recipientAlreadyConnected: "Recipient is already connected to another sender.",
   senderDisconnected: "Sender is no longer connected.",
   recipientDisconnected: "Recipient is no longer connected.",
+  invalidPairingCode: "That code is invalid or has expired. Ask the sender for a new one.",
+  pairingThrottled: "Too many wrong codes. Please wait a moment and try again.",
+  passphraseRequired: "This share is protected. Enter the passphrase to continue.",
+  wrongPassphrase: "That passphrase is not correct.",
+  passphraseLockedOut: "Too many wrong passphrases. Please wait a few minutes and try again.",
+  encryptionRequired: "This share is end-to-end encrypted, but a chunk was sent in the clear.",
+  keyExchangeIncomplete: "Waiting for every recipient to finish the key exchange.",
   activeConnectionNotFound:
     "No active transfer session found. The recipient may have disconnected.",
   recipientMismatch: "Recipient ID mismatch. Please check your link.",
+  invalidStateTransition: "This action is not allowed at the current transfer step.",
+  shareFull: "This share already has the maximum number of recipients.",
+  recipientDropped: "You were removed from the share for falling too far behind.",
+  shareNotReady: "The sender is still uploading this share. Try again shortly.",
+  spoolUnavailable: "Store-and-forward is not available for this share.",
+  spoolQuotaExceeded: "The share is too large to be stored on the relay.",
+  flowControlBlocked: "The recipient is not keeping up. Wait for it to catch up.",
+  integrityMismatch: "The file was corrupted in transit. Please try again.",
   unsupportedWsMessageTextType: "Unsupported websocket text message.",
   unsupportedWsMessageType: "Unknown message type received.",
-  notHandledYet: "This feature is not implemented yet.",
-  unknown: "Websocket text message type is unknown.",
+  unknown: "The relay ran into an internal error.",
 };
 
 // Register response structure for successful connection registration
@@ -51,6 +77,27 @@ export interface RegisterResponse {
   timestamp: number;
 }
 
+// Request announcing the client's protocol version and capabilities
+export interface HelloRequest {
+  type: "hello";
+  protocolVersion: number;
+  capabilities?: Capability[];
+}
+
+// Response with the negotiated version, shared features and relay limits
+export interface HelloResponse {
+  success: true;
+  type: "hello";
+  protocolVersion: number;
+  features: Capability[];
+  limits: {
+    maxChunkSize: number;
+    maxFileSize: number;
+    maxShareRecipients: number;
+  };
+  timestamp: number;
+}
+
 // User close request structure for notifying the server about user-initiated closure
 export interface UserCloseRequest {
   type: "userClose";
@@ -59,6 +106,37 @@ export interface UserCloseRequest {
   reason?: string;
 }
 
+// Sent to both peers when a pair cut off mid-transfer pairs again in time
+export interface ResumeFromResponse {
+  success: true;
+  type: "resumeFrom";
+  senderId: string;
+  recipientId: string;
+  chunkIndex: number;
+  byteOffset: number;
+  timestamp: number;
+}
+
+// Request forwarding an ephemeral X25519 public key to the paired peer
+export interface KeyExchangeRequest {
+  type: "keyExchange";
+  fromId?: string;
+  toId: string;
+  publicKey: string; // Hex-encoded, 32 bytes
+}
+
+// Public key forwarded from the paired peer; both sides derive the short
+// authentication string from the two keys themselves
+export interface KeyExchangeResponse {
+  success: true;
+  type: "keyExchange";
+  senderId: string;
+  recipientId: string;
+  fromId: string;
+  publicKey: string;
+  timestamp: number;
+}
+
 export interface PeerDisconnectedResponse {
   success: true;
   type: "peerDisconnected";
@@ -66,3 +144,14 @@ export interface PeerDisconnectedResponse {
   role: "sender" | "receiver";
   timestamp: number;
 }
+
+// Header of an enveloped binary frame: "RLYF", version, flags, session id,
+// then big-endian u32 file index, chunk index and payload length
+export interface BinaryFrameHeader {
+  version: number;
+  flags: number; // Bit 0 marks the last chunk of a file, bit 1 an encrypted payload
+  sessionId: string; // Sender ID
+  fileIndex: number; // 0 for single-file shares
+  chunkIndex: number;
+  length: number; // Payload length in bytes
+}